@@ -3,27 +3,108 @@ use flate2::read::ZlibDecoder;
 use flate2::write::ZlibEncoder;
 use flate2::Compression;
 use sha1::{Digest, Sha1};
-use std::borrow::Cow;
 use std::collections::{HashMap, HashSet};
+use std::fmt;
 use std::fs::File;
 use std::fs::{self};
-use std::io::{self, Error, Read, Write};
+use std::io::{self, BufRead, Read, Write};
 use std::path::Path;
+use std::process::ExitCode;
+
+#[derive(Clone, Copy, PartialEq, PartialOrd)]
+enum Verbosity {
+    Quiet,
+    Normal,
+    Verbose,
+}
+
+static VERBOSITY: std::sync::OnceLock<Verbosity> = std::sync::OnceLock::new();
+
+fn verbosity() -> Verbosity {
+    *VERBOSITY.get().unwrap_or(&Verbosity::Normal)
+}
+
+// Progress and confirmation messages, silenced by --quiet. Output a command exists to produce
+// (log entries, diffs, object contents) keeps using println! directly
+macro_rules! info {
+    ($($arg:tt)*) => {
+        if verbosity() >= Verbosity::Normal {
+            println!($($arg)*);
+        }
+    };
+}
+
+// Extra detail only shown under --verbose
+macro_rules! verbose {
+    ($($arg:tt)*) => {
+        if verbosity() >= Verbosity::Verbose {
+            println!($($arg)*);
+        }
+    };
+}
+
+// Counts the items a long operation has processed, keeping a single line on stderr up to date
+// when it's a terminal. Under --verbose each item gets its own line instead, and under
+// --quiet nothing is drawn
+struct Progress {
+    label: &'static str,
+    count: usize,
+    draw: bool,
+    last_drawn: Option<std::time::Instant>,
+}
+
+impl Progress {
+    fn new(label: &'static str) -> Self {
+        use std::io::IsTerminal;
+        Progress {
+            label,
+            count: 0,
+            draw: verbosity() == Verbosity::Normal && io::stderr().is_terminal(),
+            last_drawn: None,
+        }
+    }
+
+    fn tick(&mut self) {
+        self.count += 1;
+        let due = self
+            .last_drawn
+            .is_none_or(|drawn| drawn.elapsed() >= std::time::Duration::from_millis(100));
+        if self.draw && due {
+            eprint!("\r{}: {}", self.label, self.count);
+            self.last_drawn = Some(std::time::Instant::now());
+        }
+    }
+
+    // Draws the final count and ends the line, returning the count
+    fn finish(self) -> usize {
+        if self.draw && self.last_drawn.is_some() {
+            eprintln!("\r{}: {}, done.", self.label, self.count);
+        }
+        self.count
+    }
+}
 
 #[derive(Parser)]
 struct Fit {
+    /// Only print errors and the output the command was run for
+    #[clap(short, long, conflicts_with = "verbose")]
+    quiet: bool,
+    /// Print extra detail about what the command does
+    #[clap(short, long)]
+    verbose: bool,
     #[clap(subcommand)]
     command: FitCommands,
 }
 
 #[derive(Subcommand)]
 enum FitCommands {
-    Init,
+    Init(InitArgs),
     Clone(CloneArgs),
-    Log,
+    Log(LogArgs),
     Add(AddArgs),
     Rm(RmArgs),
     Commit(CommitArgs),
+    #[clap(alias = "cat-file")]
     Catfile(FileArgs),
     Status,
     Reset(ResetArgs),
@@ -31,6 +112,351 @@ enum FitCommands {
     Diff(DiffArgs),
     Merge(MergeArgs),
     Stash(StashArgs),
+    SymbolicRef(SymbolicRefArgs),
+    LsFiles(LsFilesArgs),
+    VerifyCommit(VerifyCommitArgs),
+    CountObjects(CountObjectsArgs),
+    DescribeRepo(DescribeRepoArgs),
+    Checkout(CheckoutArgs),
+    Fetch(FetchArgs),
+    Prune(PruneArgs),
+    Tag(TagArgs),
+    MergeBase(MergeBaseArgs),
+    Describe(DescribeArgs),
+    Rebase(RebaseArgs),
+    ShowRef(ShowRefArgs),
+    Pull(PullArgs),
+    VerifyPack(VerifyPackArgs),
+    Trash(TrashArgs),
+    Archive(ArchiveArgs),
+    Bisect(BisectArgs),
+    UpdateRef(UpdateRefArgs),
+    Grep(GrepArgs),
+    Gc(GcArgs),
+    Worktree(WorktreeArgs),
+    HashObject(HashObjectArgs),
+    WriteTree,
+    CommitTree(CommitTreeArgs),
+    Notes(NotesArgs),
+    Shortlog(ShortlogArgs),
+    FormatPatch(FormatPatchArgs),
+    Apply(ApplyArgs),
+    Clean(CleanArgs),
+}
+
+#[derive(Args)]
+struct CleanArgs {
+    /// List what would be removed without removing anything
+    #[clap(short = 'n', long)]
+    dry_run: bool,
+    /// Actually remove the files; clean refuses to delete anything without it
+    #[clap(short, long)]
+    force: bool,
+    /// Also remove untracked directories, instead of only untracked files in tracked ones
+    #[clap(short = 'd')]
+    directories: bool,
+}
+
+#[derive(Args)]
+struct ApplyArgs {
+    /// The patch to apply; read from stdin when left out
+    patch: Option<std::path::PathBuf>,
+    /// Only check that the patch applies, without touching any file
+    #[clap(long)]
+    check: bool,
+}
+
+#[derive(Args)]
+struct FormatPatchArgs {
+    /// "<since>" for the commits since it up to HEAD, or "<since>..<until>"
+    range: String,
+    /// Write the patches here instead of the current directory
+    #[clap(short, long, default_value = ".")]
+    output_directory: std::path::PathBuf,
+}
+
+#[derive(Args)]
+struct ShortlogArgs {
+    /// Summarize the history of this commit instead of HEAD's
+    commit: Option<String>,
+    /// Sort authors by their number of commits, most first, instead of by name
+    #[clap(short, long)]
+    numbered: bool,
+    /// Print only each author's commit count, without the commit subjects
+    #[clap(short, long)]
+    summary: bool,
+}
+
+#[derive(Args)]
+struct NotesArgs {
+    #[clap(subcommand)]
+    command: NotesSubcommand,
+}
+
+// Each subcommand works on HEAD unless given another commit
+#[derive(Subcommand)]
+enum NotesSubcommand {
+    /// Attach a note to a commit without rewriting it
+    Add {
+        commit: Option<String>,
+        #[clap(short, long)]
+        message: String,
+        /// Replace a note the commit already has
+        #[clap(short, long)]
+        force: bool,
+    },
+    Show {
+        commit: Option<String>,
+    },
+    Remove {
+        commit: Option<String>,
+    },
+}
+
+#[derive(Args)]
+struct CommitTreeArgs {
+    tree: String,
+    /// A parent of the new commit; repeat for a merge commit, leave out for a root commit
+    #[clap(short = 'p', long = "parent")]
+    parents: Vec<String>,
+    #[clap(short, long)]
+    message: String,
+}
+
+#[derive(Args)]
+struct HashObjectArgs {
+    #[clap(required_unless_present = "stdin", conflicts_with = "stdin")]
+    path: Option<std::path::PathBuf>,
+    /// Read the content from stdin instead of a file
+    #[clap(long)]
+    stdin: bool,
+    #[clap(short = 't', long = "type", default_value = "blob")]
+    object_type: String,
+    /// Store the object as well as printing its hash
+    #[clap(short)]
+    write: bool,
+}
+
+#[derive(Args)]
+struct WorktreeArgs {
+    #[clap(subcommand)]
+    command: WorktreeSubCommand,
+}
+
+#[derive(Subcommand)]
+enum WorktreeSubCommand {
+    /// Check a branch out into a new working tree at `path`, creating the branch from HEAD
+    /// if it doesn't exist yet
+    Add {
+        path: std::path::PathBuf,
+        branch: String,
+    },
+    /// List the main working tree and every linked one with what each has checked out
+    List,
+}
+
+#[derive(Args)]
+struct GcArgs {
+    /// Store similar blobs as deltas against each other, for a smaller but slower pack
+    #[clap(long)]
+    aggressive: bool,
+    /// Delete unreachable objects last written before this, like "2.weeks.ago" or "now";
+    /// "never" keeps them all
+    #[clap(long, default_value = "2.weeks.ago")]
+    prune: String,
+}
+
+#[derive(Args)]
+struct GrepArgs {
+    pattern: String,
+    /// Search this commit's files instead of the tracked files in the working tree
+    commit: Option<String>,
+    /// Match regardless of case
+    #[clap(short, long)]
+    ignore_case: bool,
+}
+
+#[derive(Args)]
+struct UpdateRefArgs {
+    /// HEAD or a full ref name such as refs/heads/topic
+    #[clap(name = "ref")]
+    ref_name: String,
+    /// A commit-ish, or "ref: <refname>" to make the ref symbolic
+    value: String,
+    /// Only update if the ref currently holds this value; 40 zeros means it must not exist
+    #[clap(long)]
+    old_value: Option<String>,
+    /// Reflog message for the update
+    #[clap(short, long, default_value = "update-ref")]
+    message: String,
+}
+
+#[derive(Args)]
+struct BisectArgs {
+    #[clap(subcommand)]
+    command: BisectSubCommand,
+}
+
+#[derive(Subcommand)]
+enum BisectSubCommand {
+    /// Start a bisect session, remembering where HEAD was
+    Start,
+    /// Mark a commit (HEAD by default) as containing the regression
+    Bad { commit: Option<String> },
+    /// Mark a commit (HEAD by default) as free of the regression
+    Good { commit: Option<String> },
+    /// End the session and return to where HEAD was at start
+    Reset,
+}
+
+#[derive(Args)]
+struct ArchiveArgs {
+    commit: String,
+    /// Write the archive here instead of to stdout
+    #[clap(short, long)]
+    output: Option<std::path::PathBuf>,
+    /// tar or zip; defaults to zip for an output ending in .zip, tar otherwise
+    #[clap(long)]
+    format: Option<String>,
+}
+
+#[derive(Args)]
+struct TrashArgs {
+    #[clap(subcommand)]
+    command: TrashSubCommand,
+}
+
+#[derive(Subcommand)]
+enum TrashSubCommand {
+    /// List trash entries and the files saved in each
+    List,
+    /// Copy the files saved in a trash entry back into the working tree
+    Restore { entry: String, paths: Vec<String> },
+}
+
+#[derive(Args)]
+struct VerifyPackArgs {
+    /// The pack's .idx or .pack file
+    pack: std::path::PathBuf,
+    /// List every object with its type, size and offset
+    #[clap(short, long)]
+    verbose: bool,
+}
+
+#[derive(Args)]
+struct PullArgs {
+    #[clap(default_value = "origin")]
+    remote: String,
+}
+
+#[derive(Args)]
+struct ShowRefArgs {
+    #[clap(long)]
+    heads: bool,
+    #[clap(long)]
+    tags: bool,
+}
+
+#[derive(Args)]
+struct RebaseArgs {
+    #[clap(required_unless_present_any = ["abort", "continue_rebase"])]
+    upstream: Option<String>,
+    #[clap(long, conflicts_with = "upstream")]
+    abort: bool,
+    /// Commit the resolved, staged files for the commit the rebase stopped on and replay
+    /// the rest
+    #[clap(long = "continue", conflicts_with_all = ["upstream", "abort"])]
+    continue_rebase: bool,
+}
+
+#[derive(Args)]
+struct DescribeArgs {
+    commit: Option<String>,
+}
+
+#[derive(Args)]
+struct MergeBaseArgs {
+    commit1: String,
+    commit2: String,
+}
+
+#[derive(Args)]
+struct TagArgs {
+    name: Option<String>,
+    commit: Option<String>,
+    #[clap(short, long)]
+    message: Option<String>,
+    #[clap(short, long)]
+    sign: bool,
+    #[clap(short, long, conflicts_with_all = ["commit", "message", "sign"])]
+    verify: bool,
+}
+
+#[derive(Args)]
+struct PruneArgs {
+    #[clap(long, default_value = "90.days")]
+    expire: String,
+}
+
+#[derive(Args)]
+struct InitArgs {
+    directory: Option<String>,
+    #[clap(long)]
+    bare: bool,
+}
+
+#[derive(Args)]
+struct FetchArgs {
+    #[clap(default_value = "origin")]
+    remote: String,
+}
+
+#[derive(Args)]
+struct CheckoutArgs {
+    /// The branch or commit to switch to, or with paths, the commit to take them from
+    branch: Option<String>,
+    #[clap(last = true)]
+    paths: Vec<String>,
+    /// Resolve the given conflicted paths with the current branch's version
+    #[clap(long, conflicts_with_all = ["branch", "theirs"], requires = "paths")]
+    ours: bool,
+    /// Resolve the given conflicted paths with the merged branch's version
+    #[clap(long, conflicts_with = "branch", requires = "paths")]
+    theirs: bool,
+}
+
+#[derive(Args)]
+struct DescribeRepoArgs {
+    description: Option<String>,
+}
+
+#[derive(Args)]
+struct CountObjectsArgs {
+    /// Also report packed objects, the number of refs and the largest object
+    #[clap(short, long)]
+    verbose: bool,
+}
+
+#[derive(Args)]
+struct VerifyCommitArgs {
+    commit: String,
+    /// Also check the commit's signature with gpg.program
+    #[clap(long)]
+    signature: bool,
+}
+
+#[derive(Args)]
+struct LsFilesArgs {
+    #[clap(short, long)]
+    stage: bool,
+    #[clap(short, long)]
+    deleted: bool,
+}
+
+#[derive(Args)]
+struct SymbolicRefArgs {
+    name: String,
+    target: Option<String>,
 }
 
 #[derive(Args)]
@@ -41,19 +467,62 @@ struct StashArgs {
 
 #[derive(Subcommand)]
 enum StashSubCommand {
+    /// Stash the current changes, like plain `fit stash`, optionally naming the stash
+    Save {
+        #[clap(short, long)]
+        message: Option<String>,
+    },
+    /// List stashes newest first with their messages
+    List,
     Pop,
+    Show {
+        stash: Option<String>,
+        #[clap(long)]
+        name_only: bool,
+    },
 }
 
 #[derive(Args)]
 struct MergeArgs {
     branch: String,
+    /// Stage the branch's combined changes without committing or recording a merge
+    #[clap(long)]
+    squash: bool,
 }
 #[derive(Args)]
 struct DiffArgs {
     #[clap(subcommand)]
     command: Option<DiffSubcommand>,
+    /// Compare the working tree against this commit or ref
+    commit: Option<String>,
+    /// Compare the first commit against this one instead of the working tree
+    other: Option<String>,
+    /// Compare the staged content against HEAD, or against the given commit, instead of
+    /// comparing the working tree against the staged content
+    #[clap(long, visible_alias = "staged", conflicts_with = "other")]
+    cached: bool,
+    #[clap(short = 'w', long, global = true)]
+    ignore_all_space: bool,
+    #[clap(long, global = true)]
+    color_words: bool,
+    /// Exit with status 1 if there are differences and 0 if there are none
+    #[clap(long, global = true)]
+    exit_code: bool,
+    /// Print nothing; implies --exit-code
+    #[clap(long, global = true)]
+    quiet: bool,
+}
+
+#[derive(Clone, Copy, Default)]
+struct DiffOptions {
+    ignore_all_space: bool,
+    color_words: bool,
+    quiet: bool,
 }
 
+// Set by print_diff whenever it finds a file that differs, for `diff --exit-code`
+static DIFF_FOUND: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+
 #[derive(Subcommand)]
 enum DiffSubcommand {
     Commit { commit1: String, commit2: String },
@@ -64,19 +533,84 @@ struct CloneArgs {
     url: String,
 }
 
+#[derive(Args)]
+struct LogArgs {
+    #[clap(long)]
+    name_status: bool,
+    #[clap(long)]
+    author: Option<String>,
+    #[clap(long)]
+    grep: Option<String>,
+    #[clap(long)]
+    since: Option<String>,
+    #[clap(long)]
+    until: Option<String>,
+    /// Show the history of every branch and tag, not just HEAD
+    #[clap(long)]
+    all: bool,
+    /// Show at most this many commits
+    #[clap(short = 'n', long = "max-count")]
+    max_count: Option<usize>,
+    /// Show each commit as its abbreviated hash and the first line of its message
+    #[clap(long)]
+    oneline: bool,
+    /// Show the oldest commits first; applied after -n, so `-n 2 --reverse` is the two
+    /// newest commits oldest first
+    #[clap(long)]
+    reverse: bool,
+    /// Only show commits that changed this file
+    path: Option<String>,
+    /// Keep following the file's history past commits that renamed it
+    #[clap(long, requires = "path")]
+    follow: bool,
+    /// Check each signed commit's signature with gpg.program
+    #[clap(long)]
+    show_signature: bool,
+    /// Follow only the first parent of merge commits, leaving out the history they merged in
+    #[clap(long)]
+    first_parent: bool,
+}
+
 #[derive(Args)]
 struct FileArgs {
-    hash: String,
+    #[clap(required_unless_present = "batch")]
+    hash: Option<String>,
+    #[clap(long, conflicts_with = "hash")]
+    batch: bool,
+    /// For a tree or commit, list every file in the snapshot with its blob hash
+    #[clap(short, long, conflicts_with = "batch")]
+    recursive: bool,
 }
 
 #[derive(Args)]
 struct ResetArgs {
-    commit_hash: String,
+    #[clap(required_unless_present = "patch")]
+    commit: Option<String>,
+    #[clap(last = true)]
+    paths: Vec<String>,
+    /// Choose hunks staged since the commit (HEAD by default) to unstage, leaving the
+    /// working tree alone
+    #[clap(short, long, conflicts_with_all = ["keep", "dry_run"])]
+    patch: bool,
+    /// Only update files that differ between HEAD and the commit, refusing if any of them
+    /// has local changes
+    #[clap(long, conflicts_with = "paths")]
+    keep: bool,
+    /// List the files the reset would overwrite or delete without changing anything
+    #[clap(long, conflicts_with_all = ["paths", "keep"])]
+    dry_run: bool,
+    /// Don't ask before discarding local changes, even with core.resetConfirm set
+    #[clap(short, long)]
+    force: bool,
 }
 
 #[derive(Args)]
 struct AddArgs {
     path: String,
+    #[clap(short, long)]
+    patch: bool,
+    #[clap(short = 'N', long, conflicts_with = "patch")]
+    intent_to_add: bool,
 }
 
 #[derive(Args)]
@@ -86,8 +620,91 @@ struct RmArgs {
 
 #[derive(Args)]
 struct CommitArgs {
+    #[clap(short, long, required_unless_present_any = ["fixup", "squash"])]
+    message: Option<String>,
+    /// Mark the commit as a fix to fold into this one: "fixup! <its summary>"
+    #[clap(long, conflicts_with = "squash")]
+    fixup: Option<String>,
+    /// Mark the commit to be squashed into this one: "squash! <its summary>", followed by
+    /// any -m message
+    #[clap(long)]
+    squash: Option<String>,
+    #[clap(long)]
+    author: Option<String>,
+    #[clap(long)]
+    date: Option<String>,
     #[clap(short, long)]
-    message: String,
+    all: bool,
+    /// Sign the commit with gpg.program, as commit.gpgSign = true does for every commit
+    #[clap(short = 'S', long)]
+    gpg_sign: bool,
+}
+
+struct Signature {
+    name: String,
+    email: String,
+    timestamp: i64,
+    offset_minutes: i32,
+}
+
+impl Signature {
+    fn format(&self) -> String {
+        let sign = if self.offset_minutes < 0 { '-' } else { '+' };
+        let offset = self.offset_minutes.abs();
+        format!(
+            "{} <{}> {} {}{:02}{:02}",
+            self.name,
+            self.email,
+            self.timestamp,
+            sign,
+            offset / 60,
+            offset % 60
+        )
+    }
+}
+
+struct IndexEntry {
+    hash: String,
+    size: u64,
+    mtime: u64,
+    mode: u32,
+}
+
+impl IndexEntry {
+    fn new(hash: String, metadata: &fs::Metadata) -> Self {
+        IndexEntry {
+            hash,
+            size: metadata.len(),
+            mtime: file_mtime(metadata),
+            mode: file_mode(metadata),
+        }
+    }
+
+    fn unstatted(hash: &str) -> Self {
+        IndexEntry {
+            hash: hash.to_string(),
+            size: 0,
+            mtime: 0,
+            mode: MODE_FILE,
+        }
+    }
+
+    fn with_mode(mut self, mode: u32) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    // Entries without recorded stat data (e.g. applied from staging) never match
+    fn matches_stat(&self, metadata: &fs::Metadata) -> bool {
+        self.mtime != 0 && self.size == metadata.len() && self.mtime == file_mtime(metadata)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum AutoCrlf {
+    False,
+    True,
+    Input,
 }
 
 #[derive(Default)]
@@ -113,13 +730,12 @@ impl StagingArea {
     fn delete(&mut self, path: String) {
         self.deleted.push(path);
     }
-
-    fn is_staged(&self, path: &String) -> bool {
-        self.added.contains_key(path)
-            || self.modified.contains_key(path)
-            || self.deleted.contains(path)
-    }
 }
+enum HeadState {
+    Symbolic(String),
+    Detached(String),
+}
+
 #[derive(Args)]
 struct BranchArgs {
     #[clap(subcommand)]
@@ -128,587 +744,6298 @@ struct BranchArgs {
 #[derive(Subcommand)]
 enum BranchSubcommand {
     List,
-    Create { name: String },
-    Delete { name: String },
-    Checkout { name: String },
-    CheckoutNew { name: String },
+    Create {
+        name: String,
+    },
+    Delete {
+        #[clap(required = true)]
+        names: Vec<String>,
+        #[clap(short = 'D', long)]
+        force: bool,
+    },
+    Checkout {
+        name: String,
+    },
+    CheckoutNew {
+        name: String,
+    },
+}
+
+#[derive(Debug)]
+enum FitError {
+    NotARepository,
+    // Run from below the root of the repository in this directory
+    NotRepositoryRoot(String),
+    BareRepository,
+    ObjectNotFound(String),
+    CorruptObject(String),
+    CorruptIndex(String),
+    InvalidObjectName(String),
+    // The object's hash, the type the caller needed and the type it actually has
+    WrongObjectType(String, String, String),
+    BranchExists(String),
+    BranchNotFound(String),
+    PathNotFound(String),
+    RemoteNotFound(String),
+    InvalidRef(String),
+    InvalidArgument(String),
+    NoMergeBase,
+    MergeConflict(String),
+    Refused(String),
+    Io(io::Error),
+}
+
+type FitResult<T> = Result<T, FitError>;
+
+impl fmt::Display for FitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FitError::NotARepository => write!(f, "not a fit repository (no .fit directory found)"),
+            FitError::NotRepositoryRoot(root) => write!(
+                f,
+                "not at the root of a fit repository, run fit from '{}'",
+                root
+            ),
+            FitError::BareRepository => write!(f, "this operation must be run in a work tree"),
+            FitError::ObjectNotFound(hash) => write!(f, "object {} not found", hash),
+            FitError::CorruptObject(problem) => write!(f, "corrupt object: {}", problem),
+            FitError::CorruptIndex(problem) => write!(f, "corrupt index: {}", problem),
+            FitError::InvalidObjectName(name) => write!(f, "not a valid object name '{}'", name),
+            FitError::WrongObjectType(hash, expected, found) => {
+                write!(f, "object {}: expected {}, found {}", hash, expected, found)
+            }
+            FitError::BranchExists(name) => write!(
+                f,
+                "Branch called '{}' already exists, choose a different name",
+                name
+            ),
+            FitError::BranchNotFound(name) => write!(f, "Branch '{}' not found", name),
+            FitError::PathNotFound(path) => write!(
+                f,
+                "pathspec '{}' did not match any file(s) known to fit",
+                path
+            ),
+            FitError::RemoteNotFound(remote) => {
+                write!(f, "remote '{}' has no path configured", remote)
+            }
+            FitError::InvalidRef(problem) => write!(f, "{}", problem),
+            FitError::InvalidArgument(problem) => write!(f, "{}", problem),
+            FitError::NoMergeBase => write!(f, "Merge Base not found"),
+            FitError::MergeConflict(problem) => write!(f, "merge conflict: {}", problem),
+            FitError::Refused(reason) => write!(f, "{}", reason),
+            FitError::Io(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for FitError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            FitError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
 }
 
-fn main() -> io::Result<()> {
+impl From<io::Error> for FitError {
+    fn from(err: io::Error) -> Self {
+        FitError::Io(err)
+    }
+}
+
+impl FitError {
+    // Mirrors git: 128 for fatal repository problems, 129 for bad usage, 1 otherwise
+    fn exit_code(&self) -> u8 {
+        match self {
+            FitError::NotARepository
+            | FitError::NotRepositoryRoot(_)
+            | FitError::BareRepository
+            | FitError::CorruptObject(_)
+            | FitError::CorruptIndex(_)
+            | FitError::InvalidObjectName(_)
+            | FitError::WrongObjectType(..) => 128,
+            FitError::InvalidArgument(_) => 129,
+            _ => 1,
+        }
+    }
+}
+
+fn main() -> ExitCode {
     let args = Fit::parse();
+    match run(args) {
+        Ok(exit_code) => exit_code,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            ExitCode::from(err.exit_code())
+        }
+    }
+}
+
+struct Repository {
+    // Where this working tree's own files live; the same as common_dir except in a linked
+    // worktree, where it is .fit/worktrees/<name> of the main repository
+    fit_dir: std::path::PathBuf,
+    // Where the objects, refs and config shared by every working tree live
+    common_dir: std::path::PathBuf,
+    bare: bool,
+}
+
+static REPOSITORY: std::sync::OnceLock<Repository> = std::sync::OnceLock::new();
+
+// Files that belong to a single working tree rather than the repository as a whole
+const PER_WORKTREE_PATHS: &[&str] = &[
+    "HEAD",
+    "index",
+    "STAGING",
+    "MERGE_STATE",
+    "BISECT_STATE",
+    "trash",
+    "logs/HEAD",
+];
+
+// Path of `name` inside the repository: under .fit, or at the top level of a bare repository.
+// In a linked worktree, the files of PER_WORKTREE_PATHS come from its own directory instead
+fn fit_path(name: impl AsRef<Path>) -> std::path::PathBuf {
+    let name = name.as_ref();
+    match REPOSITORY.get() {
+        Some(repository) if PER_WORKTREE_PATHS.iter().any(|path| name.starts_with(path)) => {
+            repository.fit_dir.join(name)
+        }
+        Some(repository) => repository.common_dir.join(name),
+        None => Path::new(".fit").join(name),
+    }
+}
+
+// A bare repository has no .fit directory, just HEAD, objects/ and refs/ in the current one
+// A linked worktree has a .fit file instead, naming its directory under the main repository
+fn discover_repository() -> FitResult<Repository> {
+    if Path::new(".fit").is_dir() {
+        return Ok(Repository {
+            fit_dir: ".fit".into(),
+            common_dir: ".fit".into(),
+            bare: false,
+        });
+    }
+    if Path::new(".fit").is_file() {
+        let link = fs::read_to_string(".fit")?;
+        let fit_dir = link
+            .trim_end()
+            .strip_prefix("fitdir: ")
+            .map(std::path::PathBuf::from)
+            .ok_or_else(|| FitError::CorruptObject(format!(".fit: malformed link '{}'", link)))?;
+        let common_dir =
+            fs::read_to_string(fit_dir.join("commondir")).map_err(|_| FitError::NotARepository)?;
+        return Ok(Repository {
+            fit_dir,
+            common_dir: common_dir.trim_end().into(),
+            bare: false,
+        });
+    }
+    if Path::new("HEAD").is_file() && Path::new("objects").is_dir() && Path::new("refs").is_dir() {
+        return Ok(Repository {
+            fit_dir: ".".into(),
+            common_dir: ".".into(),
+            bare: true,
+        });
+    }
+    match enclosing_repository() {
+        Some(root) => Err(FitError::NotRepositoryRoot(root.display().to_string())),
+        None => Err(FitError::NotARepository),
+    }
+}
+
+// Paths are taken relative to the repository root, so fit only runs from there and never
+// picks up a repository further up. One is still looked for to point the user at it. Like
+// git, the walk never goes up into a directory listed in FIT_CEILING_DIRECTORIES, and stops
+// at the edge of the filesystem it started on unless FIT_DISCOVERY_ACROSS_FILESYSTEM is set
+fn enclosing_repository() -> Option<std::path::PathBuf> {
+    let start = std::env::current_dir().ok()?.canonicalize().ok()?;
+    let ceilings: Vec<std::path::PathBuf> = std::env::var_os("FIT_CEILING_DIRECTORIES")
+        .map(|dirs| {
+            std::env::split_paths(&dirs)
+                .filter(|dir| dir.is_absolute())
+                .filter_map(|dir| dir.canonicalize().ok())
+                .collect()
+        })
+        .unwrap_or_default();
+    let across_filesystems = std::env::var("FIT_DISCOVERY_ACROSS_FILESYSTEM")
+        .is_ok_and(|value| matches!(value.as_str(), "1" | "true" | "yes"));
+    let device = |dir: &Path| -> Option<u64> {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            fs::metadata(dir).ok().map(|metadata| metadata.dev())
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = dir;
+            None
+        }
+    };
+    let start_device = device(&start);
+    for dir in start.ancestors().skip(1) {
+        if ceilings.iter().any(|ceiling| ceiling == dir) {
+            return None;
+        }
+        if !across_filesystems && device(dir) != start_device {
+            return None;
+        }
+        if dir.join(".fit").exists() {
+            return Some(dir.to_path_buf());
+        }
+    }
+    None
+}
+
+// Commands that read or write the working tree or the index, neither of which a bare repository has
+fn needs_work_tree(command: &FitCommands) -> bool {
+    match command {
+        FitCommands::Add(_)
+        | FitCommands::Rm(_)
+        | FitCommands::Commit(_)
+        | FitCommands::Status
+        | FitCommands::Reset(_)
+        | FitCommands::Merge(_)
+        | FitCommands::Stash(_)
+        | FitCommands::LsFiles(_)
+        | FitCommands::Checkout(_)
+        | FitCommands::Rebase(_)
+        | FitCommands::Pull(_)
+        | FitCommands::Trash(_)
+        | FitCommands::Apply(_)
+        | FitCommands::Clean(_)
+        | FitCommands::Bisect(_) => true,
+        FitCommands::Branch(branch_args) => matches!(
+            branch_args.command,
+            BranchSubcommand::Checkout { .. } | BranchSubcommand::CheckoutNew { .. }
+        ),
+        FitCommands::Diff(diff_args) => {
+            diff_args.command.is_none() && diff_args.other.is_none() && !diff_args.cached
+        }
+        FitCommands::Grep(grep_args) => grep_args.commit.is_none(),
+        _ => false,
+    }
+}
+
+// Commands report success through Ok; the few whose exit status carries an answer, like
+// `diff --exit-code`, return it from here
+fn run(args: Fit) -> FitResult<ExitCode> {
+    let _ = VERBOSITY.set(match (args.quiet, args.verbose) {
+        (true, _) => Verbosity::Quiet,
+        (_, true) => Verbosity::Verbose,
+        _ => Verbosity::Normal,
+    });
+    let needs_repository = !matches!(args.command, FitCommands::Init(_) | FitCommands::Clone(_));
+    if needs_repository {
+        let repository = discover_repository()?;
+        if repository.bare && needs_work_tree(&args.command) {
+            return Err(FitError::BareRepository);
+        }
+        let _ = REPOSITORY.set(repository);
+    }
+
     match args.command {
-        FitCommands::Init => init_workflow()?,
+        FitCommands::Init(init_args) => init_workflow(init_args)?,
         FitCommands::Clone(clone_args) => clone_workflow(clone_args)?,
-        FitCommands::Log => log_workflow()?,
+        FitCommands::Log(log_args) => log_workflow(log_args)?,
         FitCommands::Add(add_args) => add_workflow(add_args)?,
         FitCommands::Rm(rm_args) => rm_workflow(rm_args)?,
         FitCommands::Commit(commit_args) => commit_workflow(commit_args)?,
         FitCommands::Catfile(file_args) => cat_file_workflow(file_args)?,
         FitCommands::Status => status_workflow()?,
-        FitCommands::Reset(reset_args) => reset_workflow(&reset_args.commit_hash)?,
+        FitCommands::Reset(reset_args) => reset_command(reset_args)?,
         FitCommands::Branch(branch_args) => branch_workflow(branch_args)?,
-        FitCommands::Diff(diff_args) => diff_workflow(diff_args)?,
+        FitCommands::Diff(diff_args) => return diff_workflow(diff_args),
         FitCommands::Merge(merge_args) => merge_workflow(merge_args)?,
         FitCommands::Stash(stash_args) => stash_workflow(stash_args)?,
+        FitCommands::SymbolicRef(symbolic_ref_args) => symbolic_ref_workflow(symbolic_ref_args)?,
+        FitCommands::LsFiles(ls_files_args) => ls_files_workflow(ls_files_args)?,
+        FitCommands::VerifyCommit(verify_commit_args) => {
+            verify_commit_workflow(verify_commit_args)?
+        }
+        FitCommands::CountObjects(count_objects_args) => {
+            count_objects_workflow(count_objects_args)?
+        }
+        FitCommands::DescribeRepo(describe_repo_args) => {
+            describe_repo_workflow(describe_repo_args)?
+        }
+        FitCommands::Checkout(checkout_args) => checkout_workflow(checkout_args)?,
+        FitCommands::Fetch(fetch_args) => fetch_workflow(fetch_args)?,
+        FitCommands::Prune(prune_args) => prune_workflow(prune_args)?,
+        FitCommands::Tag(tag_args) => tag_workflow(tag_args)?,
+        FitCommands::MergeBase(merge_base_args) => merge_base_workflow(merge_base_args)?,
+        FitCommands::Describe(describe_args) => describe_workflow(describe_args)?,
+        FitCommands::Rebase(rebase_args) => rebase_workflow(rebase_args)?,
+        FitCommands::ShowRef(show_ref_args) => show_ref_workflow(show_ref_args)?,
+        FitCommands::Pull(pull_args) => pull_workflow(pull_args)?,
+        FitCommands::VerifyPack(verify_pack_args) => verify_pack_workflow(verify_pack_args)?,
+        FitCommands::Trash(trash_args) => trash_workflow(trash_args)?,
+        FitCommands::Archive(archive_args) => archive_workflow(archive_args)?,
+        FitCommands::Bisect(bisect_args) => bisect_workflow(bisect_args)?,
+        FitCommands::UpdateRef(update_ref_args) => update_ref_workflow(update_ref_args)?,
+        FitCommands::Grep(grep_args) => grep_workflow(grep_args)?,
+        FitCommands::Gc(gc_args) => gc_workflow(gc_args)?,
+        FitCommands::Worktree(worktree_args) => worktree_workflow(worktree_args)?,
+        FitCommands::HashObject(hash_object_args) => hash_object_workflow(hash_object_args)?,
+        FitCommands::WriteTree => println!("{}", create_tree_object(&read_index()?)?),
+        FitCommands::CommitTree(commit_tree_args) => commit_tree_workflow(commit_tree_args)?,
+        FitCommands::Notes(notes_args) => notes_workflow(notes_args)?,
+        FitCommands::Shortlog(shortlog_args) => shortlog_workflow(shortlog_args)?,
+        FitCommands::FormatPatch(format_patch_args) => format_patch_workflow(format_patch_args)?,
+        FitCommands::Apply(apply_args) => apply_workflow(apply_args)?,
+        FitCommands::Clean(clean_args) => clean_workflow(clean_args)?,
     }
-    Ok(())
+    Ok(ExitCode::SUCCESS)
 }
 
-fn init_workflow() -> io::Result<()> {
-    println!("Initializing fit repository...");
+fn init_workflow(args: InitArgs) -> FitResult<()> {
+    info!("Initializing fit repository...");
 
-    fs::create_dir(".fit")?;
-    fs::create_dir(".fit/objects")?;
-    fs::create_dir_all(".fit/refs/heads")?;
-    fs::write(".fit/HEAD", "ref: refs/heads/master\n")?;
-    File::create(".fit/index")?;
+    if let Some(directory) = &args.directory {
+        fs::create_dir_all(directory)?;
+        std::env::set_current_dir(directory)?;
+    }
+    let repository = if args.bare {
+        // Refuse to scatter repository files over an existing project
+        if Path::new("HEAD").exists() || Path::new("objects").exists() {
+            return Err(FitError::Refused(
+                "cannot init a bare repository over existing files".to_string(),
+            ));
+        }
+        Repository {
+            fit_dir: ".".into(),
+            common_dir: ".".into(),
+            bare: true,
+        }
+    } else {
+        fs::create_dir(".fit")?;
+        Repository {
+            fit_dir: ".fit".into(),
+            common_dir: ".fit".into(),
+            bare: false,
+        }
+    };
+    let bare = repository.bare;
+    let _ = REPOSITORY.set(repository);
+
+    fs::create_dir(fit_path("objects"))?;
+    fs::create_dir_all(fit_path("refs/heads"))?;
+    write_head(&HeadState::Symbolic("master".to_string()))?;
+    if bare {
+        fs::write(fit_path("config"), "[core]\n\tbare = true\n")?;
+    } else {
+        File::create(fit_path("index"))?;
+    }
+    write_description(DEFAULT_DESCRIPTION)?;
 
     let empty_tree_hash = create_empty_tree()?;
     let initial_commit_hash = create_initial_commit(empty_tree_hash)?;
 
-    fs::write(".fit/refs/heads/master", initial_commit_hash)?;
+    write_ref(&fit_path("refs/heads/master"), &initial_commit_hash)?;
 
-    println!("Initialized fit repository successfully");
+    info!("Initialized fit repository successfully");
     Ok(())
 }
 
-fn create_empty_tree() -> io::Result<String> {
-    write_object("".as_bytes(), "tree")
+const DEFAULT_DESCRIPTION: &str =
+    "Unnamed repository; edit this file 'description' to name the repository.";
+
+fn read_description() -> FitResult<String> {
+    let description_path = fit_path("description");
+    if !description_path.exists() {
+        return Ok(DEFAULT_DESCRIPTION.to_string());
+    }
+    Ok(fs::read_to_string(&description_path)?
+        .trim_end()
+        .to_string())
 }
 
-fn create_initial_commit(tree_hash: String) -> io::Result<String> {
+fn write_description(description: &str) -> FitResult<()> {
+    fs::write(
+        fit_path("description"),
+        format!("{}\n", description.trim_end()),
+    )?;
+    Ok(())
+}
+
+fn describe_repo_workflow(args: DescribeRepoArgs) -> FitResult<()> {
+    match args.description {
+        Some(description) => write_description(&description)?,
+        None => println!("{}", read_description()?),
+    }
+    Ok(())
+}
+
+fn create_empty_tree() -> FitResult<String> {
+    write_object("".as_bytes(), "tree")
+}
+
+fn create_initial_commit(tree_hash: String) -> FitResult<String> {
     let commit_content = format!("tree {}\n\nInitial commit", tree_hash);
     write_object(commit_content.as_bytes(), "commit")
 }
 
-fn clone_workflow(_args: CloneArgs) -> io::Result<()> {
+fn clone_workflow(_args: CloneArgs) -> FitResult<()> {
     println!("Clone functionality not yet implemented");
     Ok(())
 }
 
-fn log_workflow() -> io::Result<()> {
-    let mut current_commit = get_current_commit()?;
-    while !current_commit.is_empty() {
-        if let Some((_, content)) = read_object(&current_commit)? {
-            let commit_content = String::from_utf8_lossy(&content);
-            let (commit_info, message) = commit_content.split_once("\n\n").unwrap();
-            println!("commit {}", current_commit);
-            println!("{}", commit_info);
+fn log_workflow(args: LogArgs) -> FitResult<()> {
+    let since = args.since.as_deref().map(parse_log_date).transpose()?;
+    let until = args.until.as_deref().map(parse_log_date).transpose()?;
+    let commits = match (args.all, args.first_parent) {
+        (true, _) => date_ordered_history(log_tips()?, args.first_parent)?,
+        (false, true) => first_parent_history(&current_commit_or_empty()?)?,
+        (false, false) => date_ordered_history(vec![current_commit_or_empty()?], false)?,
+    };
+    if commits.is_empty() {
+        println!("no commits yet");
+        return Ok(());
+    }
+    let mut followed_path = args.path.clone();
+    let mut shown = Vec::new();
+    for commit in commits {
+        if args
+            .max_count
+            .is_some_and(|max_count| shown.len() >= max_count)
+        {
+            break;
+        }
+        let Some((_, content)) = read_object(&commit)? else {
+            break;
+        };
+        let commit_content = String::from_utf8_lossy(&content).to_string();
+        // A commit written without a message has no blank line after its headers
+        let (commit_info, message) = commit_content
+            .split_once("\n\n")
+            .unwrap_or((commit_content.trim_end(), ""));
+        if let Some(path) = &mut followed_path {
+            if !commit_touches_path(&commit, commit_info, path, args.follow)? {
+                continue;
+            }
+        }
+        if log_filters_match(&args, commit_info, message)
+            && commit_in_window(commit_info, since, until)
+        {
+            let (commit_info, message) = (commit_info.to_string(), message.to_string());
+            shown.push((commit, commit_info, message));
+        }
+    }
+    if args.reverse {
+        shown.reverse();
+    }
+    for (commit, commit_info, message) in shown {
+        if args.oneline {
+            let subject = message.trim().lines().next().unwrap_or("");
+            println!("{} {}", &commit[..7], subject);
+        } else {
+            println!("commit {}", commit);
+            let commit_content = format!("{}\n\n{}", commit_info, message);
+            match split_commit_signature(&commit_content) {
+                Some((payload, signature)) => {
+                    if args.show_signature {
+                        print_signature_check(&payload, &signature)?;
+                    }
+                    // Only the headers other than gpgsig are shown
+                    let headers = payload.split_once("\n\n").map_or(&*payload, |(h, _)| h);
+                    println!("{}", headers);
+                }
+                None => println!("{}", commit_info),
+            }
             println!("\n    {}\n", message.trim());
-            current_commit = get_parent_commit(&commit_info);
+            if let Some(note) = read_note(&commit)? {
+                println!("Notes:");
+                for line in note.lines() {
+                    println!("    {}", line);
+                }
+                println!();
+            }
+        }
+        if args.name_status {
+            print_name_status(&commit, &get_parent_commit(&commit_info))?;
+        }
+    }
+    Ok(())
+}
+
+// Without gpg.program the signature is reported as unchecked instead of failing the log
+fn print_signature_check(payload: &str, signature: &str) -> FitResult<()> {
+    if read_config_value("gpg.program")?.is_none() {
+        println!("Signature not checked: gpg.program is not set");
+    } else if run_verifier(payload, signature)? {
+        println!("Good signature");
+    } else {
+        println!("BAD signature");
+    }
+    Ok(())
+}
+
+// Groups every ancestor of the commit by author name, listing each author's subjects oldest
+// first. Commits without an author line, like the initial one, aren't counted
+fn shortlog_workflow(args: ShortlogArgs) -> FitResult<()> {
+    let commit = match &args.commit {
+        Some(commit) => resolve_ref(commit)?,
+        None => current_commit_or_empty()?,
+    };
+    if commit.is_empty() {
+        return Ok(());
+    }
+    let mut commits = Vec::new();
+    for commit in get_commit_history(&commit)?.into_iter().rev() {
+        let content = read_object_typed(&commit, "commit")?;
+        commits.push(String::from_utf8_lossy(&content).to_string());
+    }
+    commits.sort_by_key(|content| committer_timestamp(content));
+
+    let mut authors: HashMap<String, Vec<String>> = HashMap::new();
+    for content in &commits {
+        let (commit_info, message) = content.split_once("\n\n").unwrap_or((content, ""));
+        let author = commit_info
+            .lines()
+            .find_map(|line| line.strip_prefix("author "))
+            .map(|author| author.split_once(" <").map_or(author, |(name, _)| name));
+        if let Some(author) = author {
+            let subject = message.trim().lines().next().unwrap_or("").to_string();
+            authors.entry(author.to_string()).or_default().push(subject);
+        }
+    }
+
+    let mut authors: Vec<(String, Vec<String>)> = authors.into_iter().collect();
+    authors.sort_by(|(name_a, subjects_a), (name_b, subjects_b)| {
+        if args.numbered {
+            subjects_b
+                .len()
+                .cmp(&subjects_a.len())
+                .then(name_a.cmp(name_b))
         } else {
-            break;
+            name_a.cmp(name_b)
+        }
+    });
+    for (author, subjects) in authors {
+        if args.summary {
+            println!("{:>6}\t{}", subjects.len(), author);
+            continue;
+        }
+        println!("{} ({}):", author, subjects.len());
+        for subject in subjects {
+            println!("      {}", subject);
         }
+        println!();
     }
     Ok(())
 }
 
-fn write_object(content: &[u8], object_type: &str) -> io::Result<String> {
+// Whether `commit` changed `path` compared to its first parent. When following renames and the
+// commit created `path` from content its parent had under another name, `path` is switched
+// to that name so the older commits touching it are found too
+fn commit_touches_path(
+    commit: &str,
+    commit_info: &str,
+    path: &mut String,
+    follow: bool,
+) -> FitResult<bool> {
+    let files = get_tree_files(&get_commit_tree(commit)?)?;
+    let parent = get_parent_commit(commit_info);
+    let parent_files = if parent.is_empty() {
+        HashMap::new()
+    } else {
+        get_tree_files(&get_commit_tree(&parent)?)?
+    };
+    let hash = files.get(path.as_str());
+    if hash == parent_files.get(path.as_str()) {
+        return Ok(false);
+    }
+    if let (true, Some(hash), None) = (follow, hash, parent_files.get(path.as_str())) {
+        let renamed_from = parent_files
+            .iter()
+            .filter(|(old_path, old_hash)| *old_hash == hash && !files.contains_key(*old_path))
+            .map(|(old_path, _)| old_path)
+            .min();
+        if let Some(old_path) = renamed_from {
+            *path = old_path.clone();
+        }
+    }
+    Ok(true)
+}
+
+// Follows first parents from `commit`, stopping at the root or at a commit that isn't stored
+fn first_parent_history(commit: &str) -> FitResult<Vec<String>> {
+    let mut history = Vec::new();
+    let mut visited = HashSet::new();
+    let mut current_commit = commit.to_string();
+    while !current_commit.is_empty() {
+        if !visited.insert(current_commit.clone()) {
+            return Err(commit_cycle(&current_commit));
+        }
+        let Some((_, content)) = read_object(&current_commit)? else {
+            break;
+        };
+        let parent_commit = get_parent_commit(&String::from_utf8_lossy(&content));
+        history.push(current_commit);
+        current_commit = parent_commit;
+    }
+    Ok(history)
+}
+
+// HEAD and every branch and tag, where `log --all` starts from
+fn log_tips() -> FitResult<Vec<String>> {
+    let mut tips = vec![current_commit_or_empty()?];
+    for (ref_name, hash) in list_refs()? {
+        let is_tip = ref_name.starts_with("refs/heads/") || ref_name.starts_with("refs/tags/");
+        if is_tip && !hash.is_empty() {
+            tips.push(peel_tag(&hash)?);
+        }
+    }
+    Ok(tips)
+}
+
+// Every commit reachable from the tips, or with `first_parent` only along their first-parent
+// chains. Like git, the walk always continues from the newest commit queued so far, so the
+// union comes out in committer date order and commits sharing a date are listed in the order
+// they were reached
+fn date_ordered_history(tips: Vec<String>, first_parent: bool) -> FitResult<Vec<String>> {
+    let mut seen = HashSet::new();
+    let mut queue = std::collections::BinaryHeap::new();
+    let mut queued = 0;
+    let mut enqueue = |commit: String, queue: &mut std::collections::BinaryHeap<_>| {
+        let Some((object_type, content)) = read_object(&commit)? else {
+            return Err(FitError::ObjectNotFound(commit));
+        };
+        if object_type == "commit" {
+            let content = String::from_utf8_lossy(&content).to_string();
+            let timestamp = committer_timestamp(&content);
+            queue.push((timestamp, std::cmp::Reverse(queued), commit, content));
+            queued += 1;
+        }
+        Ok(())
+    };
+    for tip in tips {
+        if !tip.is_empty() && seen.insert(tip.clone()) {
+            enqueue(tip, &mut queue)?;
+        }
+    }
+
+    let mut history = Vec::new();
+    let mut followed_parents = HashMap::new();
+    while let Some((_, _, commit, content)) = queue.pop() {
+        let mut parents = get_parent_commits(&content);
+        if first_parent {
+            parents.truncate(1);
+        }
+        for parent in parents.iter().cloned() {
+            if seen.insert(parent.clone()) {
+                enqueue(parent, &mut queue)?;
+            }
+        }
+        followed_parents.insert(commit.clone(), parents);
+        history.push(commit);
+    }
+    check_history_acyclic(&history, &followed_parents)?;
+    Ok(history)
+}
+
+// The date-ordered walk never revisits a commit, so it ends even when a tampered store makes a
+// commit its own ancestor. Repeatedly peeling off commits no other walked commit points at
+// leaves exactly the commits on such a loop and those behind it
+fn check_history_acyclic(
+    history: &[String],
+    parents: &HashMap<String, Vec<String>>,
+) -> FitResult<()> {
+    let mut children: HashMap<&str, Vec<&str>> = HashMap::new();
+    for commit in history {
+        children.entry(commit).or_default();
+        for parent in &parents[commit] {
+            children.entry(parent).or_default().push(commit);
+        }
+    }
+    let mut remaining_children: HashMap<&str, usize> = children
+        .iter()
+        .map(|(commit, children)| (*commit, children.len()))
+        .collect();
+    let mut peelable: Vec<&str> = history
+        .iter()
+        .map(String::as_str)
+        .filter(|commit| remaining_children[commit] == 0)
+        .collect();
+    while let Some(commit) = peelable.pop() {
+        remaining_children.remove(commit);
+        for parent in parents.get(commit).into_iter().flatten() {
+            if let Some(count) = remaining_children.get_mut(parent.as_str()) {
+                *count -= 1;
+                if *count == 0 {
+                    peelable.push(parent);
+                }
+            }
+        }
+    }
+
+    // Every commit left still has a child left, so walking children from any of them has to
+    // come back around, and the commit it comes back to is on the loop
+    let Some(mut commit) = history
+        .iter()
+        .map(String::as_str)
+        .find(|commit| remaining_children.contains_key(commit))
+    else {
+        return Ok(());
+    };
+    let mut visited = HashSet::new();
+    while visited.insert(commit) {
+        commit = children[commit]
+            .iter()
+            .copied()
+            .find(|child| remaining_children.contains_key(child))
+            .unwrap_or(commit);
+    }
+    Err(commit_cycle(commit))
+}
+
+fn hash_object(content: &[u8], object_type: &str) -> String {
     let mut hasher = Sha1::new();
     let header = format!("{} {}\0", object_type, content.len());
     hasher.update(&header);
     hasher.update(content);
-    let hash = hasher.finalize();
-    let hash_hex = format!("{:x}", hash);
+    format!("{:x}", hasher.finalize())
+}
 
-    let dir_name = &hash_hex[0..2];
-    let file_name = &hash_hex[2..];
-    let object_dir = Path::new(".fit").join("objects").join(dir_name);
-    fs::create_dir_all(&object_dir)?;
+// --author and --grep both have to match when given
+fn log_filters_match(args: &LogArgs, commit_info: &str, message: &str) -> bool {
+    let author_matches = args.author.as_ref().is_none_or(|author| {
+        commit_info
+            .lines()
+            .find_map(|line| line.strip_prefix("author "))
+            .is_some_and(|line| line.contains(author.as_str()))
+    });
+    let message_matches = args
+        .grep
+        .as_ref()
+        .is_none_or(|pattern| message.contains(pattern.as_str()));
+    author_matches && message_matches
+}
 
-    let object_path = object_dir.join(file_name);
-    let file = File::create(object_path)?;
-    let mut encoder = ZlibEncoder::new(file, Compression::default());
-    encoder.write_all(header.as_bytes())?;
-    encoder.write_all(content)?;
-    encoder.finish()?;
+// Commits are filtered one by one rather than cut off at the first one past --since, since
+// parents aren't guaranteed to be older than their children
+fn commit_in_window(commit_info: &str, since: Option<i64>, until: Option<i64>) -> bool {
+    if since.is_none() && until.is_none() {
+        return true;
+    }
+    // Commits without a committer date (like the initial one) can't be placed in a window
+    committer_timestamp(commit_info).is_some_and(|committed_at| {
+        since.is_none_or(|since| committed_at >= since)
+            && until.is_none_or(|until| committed_at <= until)
+    })
+}
+
+fn committer_timestamp(commit_info: &str) -> Option<i64> {
+    commit_info
+        .lines()
+        .find_map(|line| line.strip_prefix("committer "))
+        .and_then(|committer| committer.rsplit(' ').nth(1))
+        .and_then(|timestamp| timestamp.parse::<i64>().ok())
+}
+
+// Accepts anything --date does, or a relative time like "2.weeks.ago" or "now"
+fn parse_log_date(date: &str) -> FitResult<i64> {
+    parse_date(date)
+        .map(|(timestamp, _)| timestamp)
+        .or_else(|_| parse_expire(date))
+        .map_err(|_| FitError::InvalidArgument(format!("invalid date '{}'", date)))
+}
+
+// Lists the files a commit changed relative to its parent, or all files for a root commit
+fn print_name_status(commit: &str, parent_commit: &str) -> FitResult<()> {
+    let files = get_tree_files(&get_commit_tree(commit)?)?;
+    let parent_files = if parent_commit.is_empty() {
+        HashMap::new()
+    } else {
+        get_tree_files(&get_commit_tree(parent_commit)?)?
+    };
+    let changes = changed_files(&parent_files, &files);
+    for (status, path) in &changes {
+        println!("{}\t{}", status, path);
+    }
+    if !changes.is_empty() {
+        println!();
+    }
+    Ok(())
+}
+
+fn write_object(content: &[u8], object_type: &str) -> FitResult<String> {
+    let header = format!("{} {}\0", object_type, content.len());
+    let hash_hex = hash_object(content, object_type);
+
+    let object_path = object_path(&hash_hex)?;
+    if object_path.is_file() {
+        touch_object(&object_path)?;
+        return Ok(hash_hex);
+    }
+    if let Some(object_dir) = object_path.parent() {
+        fs::create_dir_all(object_dir)?;
+    }
+    // Compressed into a temporary file and renamed into place once complete, so an interrupted
+    // write never leaves a truncated object under the real name
+    let temp_path = temp_object_path()?;
+    let level = compression_level()?;
+    let written = File::create(&temp_path)
+        .and_then(|file| {
+            let mut encoder = ZlibEncoder::new(file, level);
+            encoder.write_all(header.as_bytes())?;
+            encoder.write_all(content)?;
+            encoder.finish().map(drop)
+        })
+        .and_then(|_| fs::rename(&temp_path, &object_path));
+    if let Err(err) = written {
+        let _ = fs::remove_file(&temp_path);
+        return Err(err.into());
+    }
 
     Ok(hash_hex)
 }
 
-fn read_object(hash: &str) -> io::Result<Option<(String, Vec<u8>)>> {
-    let dir_name = &hash[0..2];
-    let file_name = &hash[2..];
-    let object_path = Path::new(".fit")
-        .join("objects")
-        .join(dir_name)
-        .join(file_name);
+// Writing an object that already exists only refreshes its mtime, which is how gc tells that
+// it was written again while gc was running
+fn touch_object(object_path: &Path) -> FitResult<()> {
+    File::options()
+        .write(true)
+        .open(object_path)?
+        .set_modified(std::time::SystemTime::now())?;
+    Ok(())
+}
 
-    if !object_path.exists() {
-        return Ok(None);
+// Temporary files sit directly in the objects directory, where nothing takes them for objects
+fn temp_object_path() -> FitResult<std::path::PathBuf> {
+    static COUNTER: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+    let objects_dir = fit_path("objects");
+    fs::create_dir_all(&objects_dir)?;
+    let serial = COUNTER.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    Ok(objects_dir.join(format!("tmp_obj_{}_{}", std::process::id(), serial)))
+}
+
+// Writes a blob of `size` bytes read from `reader` in fixed-size chunks, so memory use doesn't
+// grow with the file. The object is compressed into a temporary file alongside the others and
+// only renamed into place once the hash naming it is known
+fn write_blob_streaming(reader: &mut impl Read, size: u64) -> FitResult<String> {
+    let temp_path = temp_object_path()?;
+    let header = format!("blob {}\0", size);
+    let mut hasher = Sha1::new();
+    hasher.update(header.as_bytes());
+    let mut encoder = ZlibEncoder::new(File::create(&temp_path)?, compression_level()?);
+    encoder.write_all(header.as_bytes())?;
+
+    let mut buffer = vec![0; 64 * 1024];
+    let mut written = 0;
+    let copied = loop {
+        let read = match reader.read(&mut buffer) {
+            Ok(0) => break Ok(()),
+            Ok(read) => read,
+            Err(error) if error.kind() == io::ErrorKind::Interrupted => continue,
+            Err(error) => break Err(FitError::from(error)),
+        };
+        hasher.update(&buffer[..read]);
+        if let Err(error) = encoder.write_all(&buffer[..read]) {
+            break Err(FitError::from(error));
+        }
+        written += read as u64;
+    };
+    let finished = copied.and_then(|_| encoder.finish().map_err(FitError::from));
+    // The size went into the header before reading, so a file changing underneath us would
+    // produce a corrupt object
+    let result = finished.and_then(|_| {
+        if written != size {
+            return Err(FitError::Refused(format!(
+                "file changed while being added ({} bytes expected, {} read)",
+                size, written
+            )));
+        }
+        let hash_hex = format!("{:x}", hasher.finalize());
+        let object_path = object_path(&hash_hex)?;
+        if object_path.exists() {
+            fs::remove_file(&temp_path)?;
+            touch_object(&object_path)?;
+        } else {
+            if let Some(object_dir) = object_path.parent() {
+                fs::create_dir_all(object_dir)?;
+            }
+            fs::rename(&temp_path, &object_path)?;
+        }
+        Ok(hash_hex)
+    });
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result
+}
+
+// Reads core.compression once per run, falling back to the zlib default when unset or invalid
+fn compression_level() -> FitResult<Compression> {
+    static LEVEL: std::sync::OnceLock<Compression> = std::sync::OnceLock::new();
+    if let Some(level) = LEVEL.get() {
+        return Ok(*level);
+    }
+    let level = match read_config_value("core.compression")?.map(|value| value.parse::<u32>()) {
+        Some(Ok(level)) if level <= 9 => Compression::new(level),
+        _ => Compression::default(),
+    };
+    Ok(*LEVEL.get_or_init(|| level))
+}
+
+fn autocrlf() -> FitResult<AutoCrlf> {
+    static MODE: std::sync::OnceLock<AutoCrlf> = std::sync::OnceLock::new();
+    if let Some(mode) = MODE.get() {
+        return Ok(*mode);
+    }
+    let mode = match read_config_value("core.autocrlf")?.as_deref() {
+        Some("true") => AutoCrlf::True,
+        Some("input") => AutoCrlf::Input,
+        _ => AutoCrlf::False,
+    };
+    Ok(*MODE.get_or_init(|| mode))
+}
+
+// Content containing a NUL byte is treated as binary and never line-ending converted
+fn is_binary(content: &[u8]) -> bool {
+    content.contains(&0)
+}
+
+// Normalizes CRLF to LF for content entering the object store when core.autocrlf is set
+fn convert_to_blob(content: Vec<u8>) -> FitResult<Vec<u8>> {
+    if autocrlf()? == AutoCrlf::False || is_binary(&content) {
+        return Ok(content);
+    }
+    let mut converted = Vec::with_capacity(content.len());
+    for (i, &byte) in content.iter().enumerate() {
+        if byte == b'\r' && content.get(i + 1) == Some(&b'\n') {
+            continue;
+        }
+        converted.push(byte);
+    }
+    Ok(converted)
+}
+
+// Expands LF to CRLF for content written to the working tree when core.autocrlf is true
+fn convert_to_working(content: Vec<u8>) -> FitResult<Vec<u8>> {
+    if autocrlf()? != AutoCrlf::True || is_binary(&content) {
+        return Ok(content);
+    }
+    let mut converted = Vec::with_capacity(content.len());
+    for (i, &byte) in content.iter().enumerate() {
+        if byte == b'\n' && (i == 0 || content[i - 1] != b'\r') {
+            converted.push(b'\r');
+        }
+        converted.push(byte);
+    }
+    Ok(converted)
+}
+
+// Loose objects live at objects/<first two hex digits>/<remaining 38>
+fn object_path(hash: &str) -> FitResult<std::path::PathBuf> {
+    if !is_valid_hash(hash) {
+        return Err(FitError::InvalidObjectName(hash.to_string()));
+    }
+    let (dir_name, file_name) = hash.split_at(2);
+    Ok(fit_path("objects").join(dir_name).join(file_name))
+}
+
+// Reads an object the caller needs to be of a particular type, so a blob passed where a commit
+// is expected fails here rather than being misparsed further down
+fn read_object_typed(hash: &str, expected_type: &str) -> FitResult<Vec<u8>> {
+    match read_object(hash)? {
+        Some((object_type, content)) if object_type == expected_type => Ok(content),
+        Some((object_type, _)) => Err(FitError::WrongObjectType(
+            hash.to_string(),
+            expected_type.to_string(),
+            object_type,
+        )),
+        None => Err(FitError::ObjectNotFound(hash.to_string())),
+    }
+}
+
+// Looks in the local store first, loose then packed, and then in each alternate store
+fn read_object(hash: &str) -> FitResult<Option<(String, Vec<u8>)>> {
+    let object_path = object_path(hash)?;
+    if object_path.exists() {
+        return read_loose_object(&object_path, hash).map(Some);
+    }
+    if let Some(object) = read_packed_object(&fit_path("objects"), hash)? {
+        return Ok(Some(object));
+    }
+
+    let (dir_name, file_name) = hash.split_at(2);
+    for objects_dir in alternate_object_dirs()? {
+        let object_path = objects_dir.join(dir_name).join(file_name);
+        if object_path.exists() {
+            return read_loose_object(&object_path, hash).map(Some);
+        }
+        if let Some(object) = read_packed_object(&objects_dir, hash)? {
+            return Ok(Some(object));
+        }
     }
+    Ok(None)
+}
+
+// objects/info/alternates lists other repositories' object directories to read from, one per
+// line, either absolute or relative to this repository's objects directory. Objects are only
+// ever written to the local store, and alternates of an alternate aren't followed
+fn alternate_object_dirs() -> FitResult<Vec<std::path::PathBuf>> {
+    let objects_dir = fit_path("objects");
+    let content = match fs::read_to_string(objects_dir.join("info/alternates")) {
+        Ok(content) => content,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err.into()),
+    };
+    Ok(content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| objects_dir.join(line))
+        .collect())
+}
 
+fn read_loose_object(object_path: &Path, hash: &str) -> FitResult<(String, Vec<u8>)> {
     let file = File::open(object_path)?;
     let mut decoder = ZlibDecoder::new(file);
     let mut content = Vec::new();
-    decoder.read_to_end(&mut content)?;
-    let null_pos = content.iter().position(|&b| b == 0).unwrap();
+    decoder
+        .read_to_end(&mut content)
+        .map_err(|err| FitError::CorruptObject(format!("{}: {}", hash, err)))?;
+    let null_pos = content
+        .iter()
+        .position(|&b| b == 0)
+        .ok_or_else(|| FitError::CorruptObject(format!("{}: missing object header", hash)))?;
     let header = String::from_utf8_lossy(&content[..null_pos]).to_string();
     let object_content = content[null_pos + 1..].to_vec();
     let mut parts = header.splitn(2, ' ');
     let object_type = parts.next().unwrap().to_string();
 
-    Ok(Some((object_type, object_content)))
+    Ok((object_type, object_content))
 }
 
-fn add_workflow(args: AddArgs) -> io::Result<()> {
-    let path = Path::new(&args.path);
-    let mut staging_area = read_staging_area()?;
-    let mut index = read_index()?;
+// Decompresses only as far as the "<type> <size>\0" header of a loose object
+fn read_object_header(object_path: &Path) -> FitResult<(String, usize)> {
+    let mut decoder = ZlibDecoder::new(File::open(object_path)?);
+    let mut header = Vec::new();
+    let mut byte = [0u8; 1];
+    loop {
+        if decoder.read(&mut byte)? == 0 {
+            return Err(FitError::CorruptObject(format!(
+                "corrupt object header in {}",
+                object_path.display()
+            )));
+        }
+        if byte[0] == 0 {
+            break;
+        }
+        header.push(byte[0]);
+    }
+    let header = String::from_utf8_lossy(&header);
+    let (object_type, size) = header.split_once(' ').unwrap_or((&header, ""));
+    Ok((object_type.to_string(), size.parse().unwrap_or_default()))
+}
 
-    if path.is_file() {
-        add_file(path, &mut staging_area, &mut index)?;
-    } else if path.is_dir() {
-        add_directory(path, &mut staging_area, &mut index)?;
-    } else {
-        println!("'{}' is not a valid file or directory", args.path);
+// Yields the path of every loose object stored under .fit/objects/<xx>/<rest>
+fn loose_object_paths() -> FitResult<Vec<std::path::PathBuf>> {
+    let mut paths = Vec::new();
+    for dir_entry in fs::read_dir(fit_path("objects"))? {
+        let dir_entry = dir_entry?;
+        let dir_name = dir_entry.file_name().to_string_lossy().to_string();
+        if dir_name.len() != 2 || !dir_entry.path().is_dir() {
+            continue;
+        }
+        for entry in fs::read_dir(dir_entry.path())? {
+            let entry = entry?;
+            if is_valid_hash(&format!(
+                "{}{}",
+                dir_name,
+                entry.file_name().to_string_lossy()
+            )) {
+                paths.push(entry.path());
+            }
+        }
     }
+    Ok(paths)
+}
 
-    write_staging_area(&staging_area)?;
-    write_index(&index)?;
+// objects/ab/cdef... holds the object with hash abcdef...
+fn loose_object_hash(object_path: &Path) -> String {
+    let dir_name = object_path.parent().and_then(|dir| dir.file_name());
+    format!(
+        "{}{}",
+        dir_name.unwrap_or_default().to_string_lossy(),
+        object_path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+    )
+}
+
+// Prints the hash the content would be stored under, only storing it with -w. A file's
+// content goes through the same line ending conversion as `fit add`
+fn hash_object_workflow(args: HashObjectArgs) -> FitResult<()> {
+    if !["blob", "tree", "commit", "tag"].contains(&args.object_type.as_str()) {
+        return Err(FitError::InvalidArgument(format!(
+            "unknown object type '{}'",
+            args.object_type
+        )));
+    }
+    let content = match &args.path {
+        Some(path) => {
+            let content = fs::read(path)?;
+            if args.object_type == "blob" {
+                convert_to_blob(content)?
+            } else {
+                content
+            }
+        }
+        None => {
+            let mut content = Vec::new();
+            io::stdin().lock().read_to_end(&mut content)?;
+            content
+        }
+    };
+    let hash = if args.write {
+        write_object(&content, &args.object_type)?
+    } else {
+        hash_object(&content, &args.object_type)
+    };
+    println!("{}", hash);
     Ok(())
 }
 
-fn add_file(
-    path: &Path,
-    staging_area: &mut StagingArea,
-    index: &mut HashMap<String, String>,
-) -> io::Result<()> {
-    let mut file = File::open(path)?;
-    let mut contents = Vec::new();
-    file.read_to_end(&mut contents)?;
+// Prints "path:lineno:line" for every line containing the pattern, searching either the
+// working tree copies of tracked files or the blobs of a commit
+fn grep_workflow(args: GrepArgs) -> FitResult<()> {
+    let mut files = Vec::new();
+    match &args.commit {
+        Some(commit) => {
+            let commit = resolve_ref(commit)?;
+            walk_tree(&get_commit_tree(&commit)?, "", &mut |path, _, hash| {
+                files.push((path, Some(hash.to_string())));
+            })?;
+        }
+        None => files.extend(read_index()?.into_keys().map(|path| (path, None))),
+    }
+    files.sort();
 
-    let file_path = path.to_str().unwrap().to_string();
-    let hash_hex = write_object(&contents, "blob")?;
+    let pattern = if args.ignore_case {
+        args.pattern.to_lowercase()
+    } else {
+        args.pattern.clone()
+    };
+    for (path, hash) in files {
+        let content = match hash {
+            Some(hash) => read_object(&hash)?.ok_or(FitError::ObjectNotFound(hash))?.1,
+            // A tracked file deleted from the working tree has nothing to search
+            None => match fs::read(&path) {
+                Ok(content) => content,
+                Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+                Err(err) => return Err(err.into()),
+            },
+        };
+        let content = String::from_utf8_lossy(&content);
+        let matches = |line: &str| {
+            if args.ignore_case {
+                line.to_lowercase().contains(&pattern)
+            } else {
+                line.contains(&pattern)
+            }
+        };
+        if content.contains('\0') {
+            if content.lines().any(matches) {
+                println!("Binary file {} matches", path);
+            }
+            continue;
+        }
+        for (number, line) in content.lines().enumerate() {
+            if matches(line) {
+                println!("{}:{}:{}", path, number + 1, line);
+            }
+        }
+    }
+    Ok(())
+}
 
-    if let Some(old_hash) = index.get(&file_path) {
-        if old_hash != &hash_hex {
-            staging_area.modify(file_path.clone(), hash_hex.clone());
+// Writes every file in the commit's tree to a tar or zip archive, stamped with the commit's
+// date and with the permissions recorded in the tree
+fn archive_workflow(args: ArchiveArgs) -> FitResult<()> {
+    let format = match args.format.as_deref() {
+        Some(format) => format.to_string(),
+        None if args
+            .output
+            .as_ref()
+            .is_some_and(|output| output.extension().is_some_and(|ext| ext == "zip")) =>
+        {
+            "zip".to_string()
+        }
+        None => "tar".to_string(),
+    };
+    let commit = resolve_ref(&args.commit)?;
+    let (_, content) =
+        read_object(&commit)?.ok_or_else(|| FitError::ObjectNotFound(commit.clone()))?;
+    let mtime = committer_timestamp(&String::from_utf8_lossy(&content)).unwrap_or_default();
+
+    let mut entries = Vec::new();
+    walk_tree(&get_commit_tree(&commit)?, "", &mut |path, mode, hash| {
+        entries.push((path, mode.to_string(), hash.to_string()));
+    })?;
+    entries.sort();
+    let mut files = Vec::with_capacity(entries.len());
+    for (path, mode, hash) in entries {
+        let (_, blob) = read_object(&hash)?.ok_or(FitError::ObjectNotFound(hash))?;
+        let permissions = u32::from_str_radix(&mode, 8).unwrap_or(0o100644) & 0o777;
+        files.push((path, permissions, convert_to_working(blob)?));
+    }
+
+    let archive = match format.as_str() {
+        "tar" => tar_archive(&files, mtime)?,
+        "zip" => zip_archive(&files, mtime)?,
+        other => {
+            return Err(FitError::InvalidArgument(format!(
+                "unknown archive format '{}', expected tar or zip",
+                other
+            )))
         }
+    };
+    match args.output {
+        Some(output) => fs::write(output, archive)?,
+        None => io::stdout().lock().write_all(&archive)?,
+    }
+    Ok(())
+}
+
+// A POSIX ustar archive: a 512-byte header per file followed by its content padded to a
+// whole block, ending in two empty blocks
+fn tar_archive(files: &[(String, u32, Vec<u8>)], mtime: i64) -> FitResult<Vec<u8>> {
+    let mut archive = Vec::new();
+    for (path, permissions, content) in files {
+        // Paths over 100 bytes are split at a '/' into the 155-byte prefix field
+        let (prefix, name) = if path.len() <= 100 {
+            ("", path.as_str())
+        } else {
+            path.char_indices()
+                .filter(|(i, c)| *c == '/' && *i <= 155 && path.len() - i - 1 <= 100)
+                .map(|(i, _)| (&path[..i], &path[i + 1..]))
+                .next()
+                .ok_or_else(|| {
+                    FitError::Refused(format!("path too long for a tar archive: {}", path))
+                })?
+        };
+        let mut header = [0u8; 512];
+        let mut field = |offset: usize, value: &[u8]| {
+            header[offset..offset + value.len()].copy_from_slice(value);
+        };
+        field(0, name.as_bytes());
+        field(100, format!("{:07o}\0", permissions).as_bytes());
+        field(108, b"0000000\0");
+        field(116, b"0000000\0");
+        field(124, format!("{:011o}\0", content.len()).as_bytes());
+        field(136, format!("{:011o}\0", mtime.max(0)).as_bytes());
+        field(148, b"        ");
+        field(156, b"0");
+        field(257, b"ustar\0");
+        field(263, b"00");
+        field(345, prefix.as_bytes());
+        let checksum: u32 = header.iter().map(|&byte| byte as u32).sum();
+        header[148..156].copy_from_slice(format!("{:06o}\0 ", checksum).as_bytes());
+
+        archive.extend_from_slice(&header);
+        archive.extend_from_slice(content);
+        archive.resize(archive.len().div_ceil(512) * 512, 0);
+    }
+    archive.resize(archive.len() + 1024, 0);
+    Ok(archive)
+}
+
+// A zip archive of deflated files, with unix permissions in each central directory entry
+fn zip_archive(files: &[(String, u32, Vec<u8>)], mtime: i64) -> FitResult<Vec<u8>> {
+    // Zip stores MS-DOS local time, which can't go before 1980 and counts seconds in twos
+    let (year, month, day) = civil_from_days(mtime.div_euclid(86400));
+    let seconds = mtime.rem_euclid(86400);
+    let (dos_time, dos_date) = if year < 1980 {
+        (0, (1 << 5) | 1)
     } else {
-        staging_area.add(file_path.clone(), hash_hex.clone());
+        (
+            ((seconds / 3600) << 11 | (seconds % 3600 / 60) << 5 | ((seconds % 60) / 2)) as u16,
+            ((year - 1980) << 9 | month << 5 | day) as u16,
+        )
+    };
+    let too_large = |what: &str| FitError::Refused(format!("{} too large for a zip archive", what));
+
+    let mut archive = Vec::new();
+    let mut central_directory = Vec::new();
+    for (path, permissions, content) in files {
+        let mut encoder = flate2::write::DeflateEncoder::new(Vec::new(), compression_level()?);
+        encoder.write_all(content)?;
+        let compressed = encoder.finish()?;
+        let mut crc = flate2::Crc::new();
+        crc.update(content);
+        let offset = u32::try_from(archive.len()).map_err(|_| too_large("archive"))?;
+        let size = u32::try_from(content.len()).map_err(|_| too_large(path))?;
+        let compressed_size = u32::try_from(compressed.len()).map_err(|_| too_large(path))?;
+
+        // Fields shared by the local header and the central directory entry, from the
+        // version needed to extract through the extra field length. Flag 0x800 marks the
+        // name as UTF-8 and method 8 is deflate
+        let mut common = Vec::new();
+        common.extend_from_slice(&20u16.to_le_bytes());
+        common.extend_from_slice(&0x800u16.to_le_bytes());
+        common.extend_from_slice(&8u16.to_le_bytes());
+        common.extend_from_slice(&dos_time.to_le_bytes());
+        common.extend_from_slice(&dos_date.to_le_bytes());
+        common.extend_from_slice(&crc.sum().to_le_bytes());
+        common.extend_from_slice(&compressed_size.to_le_bytes());
+        common.extend_from_slice(&size.to_le_bytes());
+        common.extend_from_slice(&(path.len() as u16).to_le_bytes());
+        common.extend_from_slice(&0u16.to_le_bytes());
+
+        archive.extend_from_slice(&0x04034b50u32.to_le_bytes());
+        archive.extend_from_slice(&common);
+        archive.extend_from_slice(path.as_bytes());
+        archive.extend_from_slice(&compressed);
+
+        // "Made by" unix (3) so extractors read the permissions from the external attributes
+        central_directory.extend_from_slice(&0x02014b50u32.to_le_bytes());
+        central_directory.extend_from_slice(&((3u16 << 8) | 20).to_le_bytes());
+        central_directory.extend_from_slice(&common);
+        central_directory.extend_from_slice(&[0; 6]);
+        central_directory.extend_from_slice(&((0o100000 | permissions) << 16).to_le_bytes());
+        central_directory.extend_from_slice(&offset.to_le_bytes());
+        central_directory.extend_from_slice(path.as_bytes());
     }
 
-    index.insert(file_path, hash_hex);
+    let directory_offset = u32::try_from(archive.len()).map_err(|_| too_large("archive"))?;
+    let count = u16::try_from(files.len()).map_err(|_| too_large("file count"))?;
+    archive.extend_from_slice(&central_directory);
+    archive.extend_from_slice(&0x06054b50u32.to_le_bytes());
+    archive.extend_from_slice(&[0; 4]);
+    archive.extend_from_slice(&count.to_le_bytes());
+    archive.extend_from_slice(&count.to_le_bytes());
+    archive.extend_from_slice(&(central_directory.len() as u32).to_le_bytes());
+    archive.extend_from_slice(&directory_offset.to_le_bytes());
+    archive.extend_from_slice(&0u16.to_le_bytes());
+    Ok(archive)
+}
 
-    println!("Added {} to staging area", path.display());
-    Ok(())
+// Packs live in objects/pack as pack-<hash>.pack with a version 2 pack-<hash>.idx beside it,
+// in the same format git uses
+struct PackIndex {
+    pack_path: std::path::PathBuf,
+    // Sorted by hash, as stored in the index
+    entries: Vec<PackIndexEntry>,
+    pack_checksum: Vec<u8>,
 }
 
-fn add_directory(
-    path: &Path,
-    staging_area: &mut StagingArea,
-    index: &mut HashMap<String, String>,
-) -> io::Result<()> {
-    for entry in fs::read_dir(path)? {
-        let entry = entry?;
-        let path = entry.path();
-        if path.is_file() {
-            add_file(&path, staging_area, index)?;
-        } else if path.is_dir() {
-            add_directory(&path, staging_area, index)?;
+struct PackIndexEntry {
+    hash: String,
+    offset: u64,
+    crc: u32,
+}
+
+fn read_pack_index(idx_path: &Path) -> FitResult<PackIndex> {
+    load_pack_index(idx_path, true)
+}
+
+// Object lookups parse each index once per run and skip re-hashing it; verify-pack, gc and
+// count-objects still read it through read_pack_index, which checks the trailer checksum
+fn cached_pack_index(idx_path: &Path) -> FitResult<std::sync::Arc<PackIndex>> {
+    type Cache = std::sync::Mutex<HashMap<std::path::PathBuf, std::sync::Arc<PackIndex>>>;
+    static CACHE: std::sync::OnceLock<Cache> = std::sync::OnceLock::new();
+    let cache = CACHE.get_or_init(Default::default);
+    if let Some(pack_index) = cache.lock().unwrap().get(idx_path) {
+        return Ok(pack_index.clone());
+    }
+    let pack_index = std::sync::Arc::new(load_pack_index(idx_path, false)?);
+    cache
+        .lock()
+        .unwrap()
+        .insert(idx_path.to_path_buf(), pack_index.clone());
+    Ok(pack_index)
+}
+
+fn load_pack_index(idx_path: &Path, verify_checksum: bool) -> FitResult<PackIndex> {
+    let corrupt =
+        |problem: &str| FitError::CorruptObject(format!("{}: {}", idx_path.display(), problem));
+    let data = fs::read(idx_path)?;
+    if data.len() < 8 + 256 * 4 + 40 || &data[..4] != b"\xfftOc" {
+        return Err(corrupt("not a pack index"));
+    }
+    if be_u32(&data[4..8]) != 2 {
+        return Err(corrupt("unsupported pack index version"));
+    }
+    let (body, checksum) = data.split_at(data.len() - 20);
+    if verify_checksum && Sha1::digest(body).as_slice() != checksum {
+        return Err(corrupt("index checksum mismatch"));
+    }
+    let count = be_u32(&data[8 + 255 * 4..8 + 256 * 4]) as usize;
+    let hashes_start = 8 + 256 * 4;
+    let crcs_start = hashes_start + count * 20;
+    let offsets_start = crcs_start + count * 4;
+    let large_offsets_start = offsets_start + count * 4;
+    if data.len() < large_offsets_start + 40 {
+        return Err(corrupt("index is truncated"));
+    }
+
+    let mut entries = Vec::with_capacity(count);
+    for i in 0..count {
+        let hash: String = data[hashes_start + i * 20..hashes_start + (i + 1) * 20]
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        let crc = be_u32(&data[crcs_start + i * 4..]);
+        let offset = be_u32(&data[offsets_start + i * 4..]);
+        // Offsets past 2GiB are stored in a trailing table of 8-byte entries
+        let offset = if offset & 0x8000_0000 == 0 {
+            offset as u64
+        } else {
+            let at = large_offsets_start + (offset & 0x7fff_ffff) as usize * 8;
+            let bytes = data
+                .get(at..at + 8)
+                .filter(|_| at + 8 <= data.len() - 40)
+                .ok_or_else(|| corrupt("large offset out of range"))?;
+            (be_u32(bytes) as u64) << 32 | be_u32(&bytes[4..]) as u64
+        };
+        entries.push(PackIndexEntry { hash, offset, crc });
+    }
+    Ok(PackIndex {
+        pack_path: idx_path.with_extension("pack"),
+        entries,
+        pack_checksum: data[data.len() - 40..data.len() - 20].to_vec(),
+    })
+}
+
+fn be_u32(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+fn pack_index_paths() -> FitResult<Vec<std::path::PathBuf>> {
+    pack_index_paths_in(&fit_path("objects"))
+}
+
+fn pack_index_paths_in(objects_dir: &Path) -> FitResult<Vec<std::path::PathBuf>> {
+    let pack_dir = objects_dir.join("pack");
+    if !pack_dir.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut paths = Vec::new();
+    for entry in fs::read_dir(pack_dir)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|extension| extension == "idx") {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+// Looks `hash` up in each pack index of an object store, reading the object out of the first
+// pack holding it
+fn read_packed_object(objects_dir: &Path, hash: &str) -> FitResult<Option<(String, Vec<u8>)>> {
+    for idx_path in pack_index_paths_in(objects_dir)? {
+        let pack_index = cached_pack_index(&idx_path)?;
+        let Ok(position) = pack_index
+            .entries
+            .binary_search_by(|entry| entry.hash.as_str().cmp(hash))
+        else {
+            continue;
+        };
+        let mut pack = File::open(&pack_index.pack_path)?;
+        let offset = pack_index.entries[position].offset;
+        return read_pack_entry(&mut pack, offset, 0).map(Some);
+    }
+    Ok(None)
+}
+
+// Reads the object whose entry starts at `offset`, rebuilding it from its base if it's
+// stored as a delta; `depth` bounds chains of deltas against other packed objects
+fn read_pack_entry(pack: &mut File, offset: u64, depth: usize) -> FitResult<(String, Vec<u8>)> {
+    use std::io::{Seek, SeekFrom};
+    let corrupt =
+        |problem: &str| FitError::CorruptObject(format!("pack entry at {}: {}", offset, problem));
+    if depth > 50 {
+        return Err(corrupt("delta chain is too long"));
+    }
+    pack.seek(SeekFrom::Start(offset))?;
+    let mut next_byte = || -> FitResult<u8> {
+        let mut byte = [0u8; 1];
+        pack.read_exact(&mut byte)
+            .map_err(|_| corrupt("truncated entry header"))?;
+        Ok(byte[0])
+    };
+
+    // The header packs the type into bits 4-6 of the first byte and the size into a varint
+    let mut byte = next_byte()?;
+    let type_code = (byte >> 4) & 7;
+    let mut size = (byte & 15) as u64;
+    let mut shift = 4;
+    while byte & 0x80 != 0 {
+        byte = next_byte()?;
+        size |= ((byte & 0x7f) as u64) << shift;
+        shift += 7;
+    }
+
+    let base = match type_code {
+        1..=4 => None,
+        // An offset delta names its base by how far before this entry it starts
+        6 => {
+            let mut byte = next_byte()?;
+            let mut distance = (byte & 0x7f) as u64;
+            while byte & 0x80 != 0 {
+                byte = next_byte()?;
+                distance = ((distance + 1) << 7) | (byte & 0x7f) as u64;
+            }
+            let base_offset = offset
+                .checked_sub(distance)
+                .filter(|_| distance > 0)
+                .ok_or_else(|| corrupt("delta base offset out of range"))?;
+            Some(DeltaBase::Offset(base_offset))
+        }
+        7 => {
+            let mut base_hash = String::new();
+            for _ in 0..20 {
+                base_hash.push_str(&format!("{:02x}", next_byte()?));
+            }
+            Some(DeltaBase::Hash(base_hash))
+        }
+        _ => return Err(corrupt("unknown object type")),
+    };
+
+    let mut content = Vec::new();
+    ZlibDecoder::new(&mut *pack)
+        .read_to_end(&mut content)
+        .map_err(|err| corrupt(&err.to_string()))?;
+    if content.len() as u64 != size {
+        return Err(corrupt("inflated size doesn't match the entry header"));
+    }
+
+    let (object_type, base_content) = match base {
+        None => {
+            let object_type = ["commit", "tree", "blob", "tag"][type_code as usize - 1];
+            return Ok((object_type.to_string(), content));
+        }
+        Some(DeltaBase::Offset(base_offset)) => read_pack_entry(pack, base_offset, depth + 1)?,
+        Some(DeltaBase::Hash(base_hash)) => read_object(&base_hash)?
+            .ok_or_else(|| corrupt(&format!("delta base {} not found", base_hash)))?,
+    };
+    let content = apply_delta(&base_content, &content).map_err(corrupt)?;
+    Ok((object_type, content))
+}
+
+enum DeltaBase {
+    Offset(u64),
+    Hash(String),
+}
+
+// A delta is the base and result sizes followed by instructions either copying a range of
+// the base or inserting literal bytes
+fn apply_delta(base: &[u8], delta: &[u8]) -> Result<Vec<u8>, &'static str> {
+    let mut position = 0;
+    let mut read_size = || -> Result<usize, &'static str> {
+        let mut size = 0;
+        let mut shift = 0;
+        loop {
+            let byte = *delta.get(position).ok_or("truncated delta")?;
+            position += 1;
+            size |= ((byte & 0x7f) as usize) << shift;
+            shift += 7;
+            if byte & 0x80 == 0 {
+                return Ok(size);
+            }
+        }
+    };
+    let base_size = read_size()?;
+    let result_size = read_size()?;
+    if base_size != base.len() {
+        return Err("delta base size mismatch");
+    }
+
+    let mut result = Vec::with_capacity(result_size);
+    while position < delta.len() {
+        let op = delta[position];
+        position += 1;
+        if op & 0x80 != 0 {
+            // Bits 0-3 say which offset bytes follow, bits 4-6 which size bytes
+            let mut fields = [0usize; 2];
+            let mut bit = 0;
+            for (field, width) in fields.iter_mut().zip([4, 3]) {
+                for byte_number in 0..width {
+                    if op & (1 << bit) != 0 {
+                        let byte = *delta.get(position).ok_or("truncated delta")?;
+                        position += 1;
+                        *field |= (byte as usize) << (8 * byte_number);
+                    }
+                    bit += 1;
+                }
+            }
+            let [copy_offset, mut copy_size] = fields;
+            if copy_size == 0 {
+                copy_size = 0x10000;
+            }
+            let copied = base
+                .get(copy_offset..copy_offset + copy_size)
+                .ok_or("delta copies outside the base")?;
+            result.extend_from_slice(copied);
+        } else if op != 0 {
+            let inserted = delta
+                .get(position..position + op as usize)
+                .ok_or("truncated delta")?;
+            result.extend_from_slice(inserted);
+            position += op as usize;
+        } else {
+            return Err("invalid delta instruction");
+        }
+    }
+    if result.len() != result_size {
+        return Err("delta result size mismatch");
+    }
+    Ok(result)
+}
+
+// How many of the blobs before it each blob is tried as a delta against, and how long a chain
+// of deltas may get before a blob is stored whole again
+const DELTA_WINDOW: usize = 10;
+const MAX_DELTA_DEPTH: usize = 10;
+
+// Repacks every loose and packed object into a single new pack, then removes the loose
+// objects and the old packs it replaces
+// Deletion happens in two phases. The first lists every object with the time it was last
+// written (a packed object's is its pack's) and decides which are unreachable and older than
+// the grace period. The second deletes only those, and only if they weren't written again in
+// between, so objects another command creates while gc runs are never touched
+fn gc_workflow(args: GcArgs) -> FitResult<()> {
+    let cutoff = parse_expire(&args.prune)?;
+    let mut objects: HashMap<String, (String, Vec<u8>)> = HashMap::new();
+    let mut written_at: HashMap<String, std::time::SystemTime> = HashMap::new();
+    let loose_paths = loose_object_paths()?;
+    for object_path in &loose_paths {
+        let hash = loose_object_hash(object_path);
+        if let Some(object) = read_object(&hash)? {
+            written_at.insert(hash.clone(), fs::metadata(object_path)?.modified()?);
+            objects.insert(hash, object);
+        }
+    }
+    let old_packs = pack_index_paths()?;
+    for idx_path in &old_packs {
+        let pack_index = read_pack_index(idx_path)?;
+        let pack_written_at = fs::metadata(&pack_index.pack_path)?.modified()?;
+        let mut pack = File::open(&pack_index.pack_path)?;
+        for entry in &pack_index.entries {
+            if !objects.contains_key(&entry.hash) {
+                let object = read_pack_entry(&mut pack, entry.offset, 0)?;
+                written_at.insert(entry.hash.clone(), pack_written_at);
+                objects.insert(entry.hash.clone(), object);
+            }
+        }
+    }
+    if objects.is_empty() {
+        info!("Nothing to pack");
+        return Ok(());
+    }
+
+    let mut roots = reachability_roots()?;
+    for (_, entries) in read_reflogs()? {
+        for entry in entries {
+            roots.push(entry.old_commit);
+            roots.push(entry.new_commit);
+        }
+    }
+    let reachable = reachable_objects(roots)?;
+    let is_expired = |hash: &String| {
+        written_at[hash]
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(true, |written| (written.as_secs() as i64) <= cutoff)
+    };
+    let (packed, unreachable): (HashMap<_, _>, HashMap<_, _>) = objects
+        .into_iter()
+        .partition(|(hash, _)| reachable.contains(hash));
+    let (expired, recent): (HashMap<_, _>, HashMap<_, _>) = unreachable
+        .into_iter()
+        .partition(|(hash, _)| is_expired(hash));
+
+    // Recent unreachable objects stay loose, keeping their age so a later gc can expire them
+    for (hash, (object_type, content)) in &recent {
+        let object_path = object_path(hash)?;
+        if !object_path.is_file() {
+            write_object(content, object_type)?;
+            File::options()
+                .write(true)
+                .open(&object_path)?
+                .set_modified(written_at[hash])?;
+        }
+    }
+
+    // Blobs go last, largest first: deltas are cheapest when they shrink a larger base
+    let mut order: Vec<(String, (String, Vec<u8>))> = packed.into_iter().collect();
+    order.sort_by(
+        |(hash_a, (type_a, content_a)), (hash_b, (type_b, content_b))| {
+            (type_a == "blob")
+                .cmp(&(type_b == "blob"))
+                .then(content_b.len().cmp(&content_a.len()))
+                .then(hash_a.cmp(hash_b))
+        },
+    );
+
+    let (pack, entries, deltas) = write_pack(&order, args.aggressive)?;
+    let pack_checksum = &pack[pack.len() - 20..];
+    let pack_name: String = pack_checksum
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    let pack_path = fit_path("objects/pack").join(format!("pack-{}.pack", pack_name));
+    fs::create_dir_all(fit_path("objects/pack"))?;
+    write_atomic(&pack_path, &pack)?;
+    write_atomic(
+        &pack_path.with_extension("idx"),
+        &pack_index_bytes(entries, pack_checksum),
+    )?;
+
+    for idx_path in old_packs {
+        if idx_path != pack_path.with_extension("idx") {
+            fs::remove_file(idx_path.with_extension("pack"))?;
+            fs::remove_file(idx_path)?;
+        }
+    }
+    let mut pruned = expired.len();
+    for object_path in loose_paths {
+        let hash = loose_object_hash(&object_path);
+        if recent.contains_key(&hash) {
+            continue;
+        }
+        if expired.contains_key(&hash) {
+            // Rewritten since the first phase, so something has started using it again
+            if fs::metadata(&object_path)?.modified()? != written_at[&hash] {
+                pruned -= 1;
+                continue;
+            }
+        }
+        fs::remove_file(&object_path)?;
+        if let Some(parent) = object_path.parent() {
+            let _ = fs::remove_dir(parent);
+        }
+    }
+    info!(
+        "Packed {} objects ({} as deltas) into {}",
+        order.len(),
+        deltas,
+        pack_path.display()
+    );
+    if pruned > 0 {
+        info!("Pruned {} unreachable objects", pruned);
+    }
+    Ok(())
+}
+
+// Writes the objects, in order, as a version 2 pack, returning it with the entries for its
+// index and the number stored as deltas. Only blobs are deltified, each against whichever
+// of the blobs just before it gives the smallest delta, if that saves at least half the size
+fn write_pack(
+    objects: &[(String, (String, Vec<u8>))],
+    deltify: bool,
+) -> FitResult<(Vec<u8>, Vec<PackIndexEntry>, usize)> {
+    let mut pack = b"PACK".to_vec();
+    pack.extend_from_slice(&2u32.to_be_bytes());
+    let count = u32::try_from(objects.len())
+        .map_err(|_| FitError::InvalidArgument("too many objects for one pack".to_string()))?;
+    pack.extend_from_slice(&count.to_be_bytes());
+
+    let mut entries: Vec<PackIndexEntry> = Vec::with_capacity(objects.len());
+    let mut depths = vec![0; objects.len()];
+    let mut deltas = 0;
+    for (i, (hash, (object_type, content))) in objects.iter().enumerate() {
+        let offset = pack.len() as u64;
+        let mut best: Option<(usize, Vec<u8>)> = None;
+        if deltify && object_type == "blob" {
+            for base in i.saturating_sub(DELTA_WINDOW)..i {
+                let (base_type, base_content) = &objects[base].1;
+                if base_type != "blob" || depths[base] >= MAX_DELTA_DEPTH {
+                    continue;
+                }
+                let delta = create_delta(base_content, content);
+                let smallest = best
+                    .as_ref()
+                    .map_or(content.len() / 2, |(_, best)| best.len());
+                if delta.len() < smallest {
+                    best = Some((base, delta));
+                }
+            }
+        }
+
+        let mut entry = Vec::new();
+        let data = match &best {
+            Some((base, delta)) => {
+                depths[i] = depths[*base] + 1;
+                deltas += 1;
+                push_pack_entry_header(&mut entry, 6, delta.len());
+                push_base_distance(&mut entry, offset - entries[*base].offset);
+                delta
+            }
+            None => {
+                let type_code = match object_type.as_str() {
+                    "commit" => 1,
+                    "tree" => 2,
+                    "blob" => 3,
+                    "tag" => 4,
+                    other => {
+                        return Err(FitError::CorruptObject(format!(
+                            "{}: unknown object type '{}'",
+                            hash, other
+                        )))
+                    }
+                };
+                push_pack_entry_header(&mut entry, type_code, content.len());
+                content
+            }
+        };
+        let mut encoder = ZlibEncoder::new(entry, compression_level()?);
+        encoder.write_all(data)?;
+        let entry = encoder.finish()?;
+
+        let mut crc = flate2::Crc::new();
+        crc.update(&entry);
+        pack.extend_from_slice(&entry);
+        entries.push(PackIndexEntry {
+            hash: hash.clone(),
+            offset,
+            crc: crc.sum(),
+        });
+    }
+    let checksum = Sha1::digest(&pack);
+    pack.extend_from_slice(&checksum);
+    Ok((pack, entries, deltas))
+}
+
+// The type in bits 4-6 of the first byte, then the size as a varint starting in its low bits
+fn push_pack_entry_header(entry: &mut Vec<u8>, type_code: u8, size: usize) {
+    let mut byte = (type_code << 4) | (size & 15) as u8;
+    let mut size = size >> 4;
+    while size > 0 {
+        entry.push(byte | 0x80);
+        byte = (size & 0x7f) as u8;
+        size >>= 7;
+    }
+    entry.push(byte);
+}
+
+// The inverse of the offset decoding in read_pack_entry, where each continuation adds one
+fn push_base_distance(entry: &mut Vec<u8>, distance: u64) {
+    let mut bytes = vec![(distance & 0x7f) as u8];
+    let mut distance = distance >> 7;
+    while distance > 0 {
+        distance -= 1;
+        bytes.push(0x80 | (distance & 0x7f) as u8);
+        distance >>= 7;
+    }
+    bytes.reverse();
+    entry.extend_from_slice(&bytes);
+}
+
+// Builds a delta apply_delta turns `base` back into `target`: runs matching a 16-byte block
+// of the base are copied from it, everything else is inserted literally
+fn create_delta(base: &[u8], target: &[u8]) -> Vec<u8> {
+    const BLOCK: usize = 16;
+    let mut delta = Vec::new();
+    for size in [base.len(), target.len()] {
+        let mut size = size;
+        while size >= 0x80 {
+            delta.push(0x80 | (size & 0x7f) as u8);
+            size >>= 7;
+        }
+        delta.push(size as u8);
+    }
+
+    let mut blocks: HashMap<&[u8], usize> = HashMap::new();
+    for (number, block) in base.chunks_exact(BLOCK).enumerate() {
+        blocks.entry(block).or_insert(number * BLOCK);
+    }
+    let flush = |delta: &mut Vec<u8>, literal: &mut Vec<u8>| {
+        for chunk in literal.chunks(0x7f) {
+            delta.push(chunk.len() as u8);
+            delta.extend_from_slice(chunk);
+        }
+        literal.clear();
+    };
+
+    let mut literal = Vec::new();
+    let mut position = 0;
+    while position < target.len() {
+        let base_start = target
+            .get(position..position + BLOCK)
+            .and_then(|block| blocks.get(block));
+        let Some(&base_start) = base_start else {
+            literal.push(target[position]);
+            position += 1;
+            continue;
+        };
+        flush(&mut delta, &mut literal);
+        let length = base[base_start..]
+            .iter()
+            .zip(&target[position..])
+            .take_while(|(a, b)| a == b)
+            .count();
+        let mut copied = 0;
+        while copied < length {
+            let size = (length - copied).min(0xff_ffff);
+            // Only the non-zero bytes of the offset and size are stored, flagged in the opcode
+            let mut op = 0x80;
+            let mut arguments = Vec::new();
+            let offset_bytes = ((base_start + copied) as u32).to_le_bytes();
+            let size_bytes = (size as u32).to_le_bytes();
+            for (bit, byte) in offset_bytes.iter().chain(&size_bytes[..3]).enumerate() {
+                if *byte != 0 {
+                    op |= 1 << bit;
+                    arguments.push(*byte);
+                }
+            }
+            delta.push(op);
+            delta.extend_from_slice(&arguments);
+            copied += size;
+        }
+        position += length;
+    }
+    flush(&mut delta, &mut literal);
+    delta
+}
+
+// Serializes a version 2 index, the format read_pack_index reads
+fn pack_index_bytes(mut entries: Vec<PackIndexEntry>, pack_checksum: &[u8]) -> Vec<u8> {
+    entries.sort_by(|a, b| a.hash.cmp(&b.hash));
+    let hash_bytes: Vec<Vec<u8>> = entries
+        .iter()
+        .map(|entry| {
+            (0..40)
+                .step_by(2)
+                .map(|i| u8::from_str_radix(&entry.hash[i..i + 2], 16).unwrap_or_default())
+                .collect()
+        })
+        .collect();
+
+    let mut index = b"\xfftOc".to_vec();
+    index.extend_from_slice(&2u32.to_be_bytes());
+    let mut fanout = [0u32; 256];
+    for hash in &hash_bytes {
+        for count in &mut fanout[hash[0] as usize..] {
+            *count += 1;
+        }
+    }
+    for count in fanout {
+        index.extend_from_slice(&count.to_be_bytes());
+    }
+    for hash in &hash_bytes {
+        index.extend_from_slice(hash);
+    }
+    for entry in &entries {
+        index.extend_from_slice(&entry.crc.to_be_bytes());
+    }
+    let mut large_offsets = Vec::new();
+    for entry in &entries {
+        let offset = if entry.offset < 0x8000_0000 {
+            entry.offset as u32
+        } else {
+            large_offsets.push(entry.offset);
+            0x8000_0000 | (large_offsets.len() - 1) as u32
+        };
+        index.extend_from_slice(&offset.to_be_bytes());
+    }
+    for offset in large_offsets {
+        index.extend_from_slice(&offset.to_be_bytes());
+    }
+    index.extend_from_slice(pack_checksum);
+    let checksum = Sha1::digest(&index);
+    index.extend_from_slice(&checksum);
+    index
+}
+
+// Checks the pack and index checksums, then reads back every indexed object, comparing its
+// hash and the CRC of its raw entry against the index
+fn verify_pack_workflow(args: VerifyPackArgs) -> FitResult<()> {
+    let pack_index = read_pack_index(&args.pack.with_extension("idx"))?;
+    let pack_name = pack_index.pack_path.display().to_string();
+    let corrupt = |problem: &str| FitError::CorruptObject(format!("{}: {}", pack_name, problem));
+    let data = fs::read(&pack_index.pack_path)?;
+    if data.len() < 32 || &data[..4] != b"PACK" {
+        return Err(corrupt("not a pack file"));
+    }
+    if be_u32(&data[4..8]) != 2 {
+        return Err(corrupt("unsupported pack version"));
+    }
+    let (body, checksum) = data.split_at(data.len() - 20);
+    let mut problems = Vec::new();
+    if Sha1::digest(body).as_slice() != checksum {
+        problems.push("pack checksum mismatch".to_string());
+    }
+    if checksum != pack_index.pack_checksum.as_slice() {
+        problems.push("pack checksum doesn't match the index".to_string());
+    }
+    if be_u32(&data[8..12]) as usize != pack_index.entries.len() {
+        problems.push(format!(
+            "pack holds {} objects but the index lists {}",
+            be_u32(&data[8..12]),
+            pack_index.entries.len()
+        ));
+    }
+
+    // Each entry's raw bytes run up to the next entry, or the trailing checksum for the last
+    let mut by_offset: Vec<&PackIndexEntry> = pack_index.entries.iter().collect();
+    by_offset.sort_by_key(|entry| entry.offset);
+    let mut pack = File::open(&pack_index.pack_path)?;
+    let mut bad_objects = 0;
+    for (i, entry) in by_offset.iter().enumerate() {
+        let end = by_offset
+            .get(i + 1)
+            .map_or(body.len() as u64, |next| next.offset);
+        let mut entry_problems = Vec::new();
+        match data.get(entry.offset as usize..end as usize) {
+            Some(raw) if entry.offset >= 12 => {
+                let mut crc = flate2::Crc::new();
+                crc.update(raw);
+                if crc.sum() != entry.crc {
+                    entry_problems.push("CRC mismatch".to_string());
+                }
+            }
+            _ => entry_problems.push("offset out of range".to_string()),
+        }
+        match read_pack_entry(&mut pack, entry.offset, 0) {
+            Ok((object_type, content)) => {
+                if hash_object(&content, &object_type) != entry.hash {
+                    entry_problems.push("content doesn't match its hash".to_string());
+                } else if args.verbose {
+                    println!(
+                        "{} {} {} {}",
+                        entry.hash,
+                        object_type,
+                        content.len(),
+                        entry.offset
+                    );
+                }
+            }
+            Err(err) => entry_problems.push(err.to_string()),
+        }
+        if !entry_problems.is_empty() {
+            bad_objects += 1;
+            println!("{}: {}", entry.hash, entry_problems.join(", "));
+        }
+    }
+    if bad_objects > 0 {
+        problems.push(format!("{} corrupt objects", bad_objects));
+    }
+    if !problems.is_empty() {
+        return Err(corrupt(&problems.join("; ")));
+    }
+    println!("{}: ok, {} objects", pack_name, pack_index.entries.len());
+    Ok(())
+}
+
+// Sizes are what the objects take on disk, except the largest object's, which is its
+// uncompressed content
+fn count_objects_workflow(args: CountObjectsArgs) -> FitResult<()> {
+    let mut count = 0;
+    let mut total_size = 0;
+    let mut by_type: HashMap<String, usize> = HashMap::new();
+    let mut largest: Option<(usize, String)> = None;
+
+    for object_path in loose_object_paths()? {
+        count += 1;
+        total_size += fs::metadata(&object_path)?.len();
+        let (object_type, size) = read_object_header(&object_path)?;
+        *by_type.entry(object_type).or_default() += 1;
+        if largest
+            .as_ref()
+            .is_none_or(|(largest_size, _)| size > *largest_size)
+        {
+            largest = Some((size, loose_object_hash(&object_path)));
+        }
+    }
+
+    println!("count: {}", count);
+    println!("size: {} bytes", total_size);
+    let mut types: Vec<_> = by_type.into_iter().collect();
+    types.sort();
+    for (object_type, type_count) in types {
+        println!("{}: {}", object_type, type_count);
+    }
+    if !args.verbose {
+        return Ok(());
+    }
+
+    let mut in_pack = 0;
+    let mut pack_size = 0;
+    let idx_paths = pack_index_paths()?;
+    for idx_path in &idx_paths {
+        let pack_index = read_pack_index(idx_path)?;
+        pack_size += fs::metadata(idx_path)?.len() + fs::metadata(&pack_index.pack_path)?.len();
+        in_pack += pack_index.entries.len();
+        let mut pack = File::open(&pack_index.pack_path)?;
+        for entry in &pack_index.entries {
+            let (_, content) = read_pack_entry(&mut pack, entry.offset, 0)?;
+            if largest
+                .as_ref()
+                .is_none_or(|(largest_size, _)| content.len() > *largest_size)
+            {
+                largest = Some((content.len(), entry.hash.clone()));
+            }
+        }
+    }
+    println!("in-pack: {}", in_pack);
+    println!("packs: {}", idx_paths.len());
+    println!("size-pack: {} bytes", pack_size);
+    println!("refs: {}", list_refs()?.len());
+    match largest {
+        Some((size, hash)) => println!("largest: {} bytes ({})", size, hash),
+        None => println!("largest: none"),
+    }
+    Ok(())
+}
+
+// Deletes loose objects that can't be reached from any ref, HEAD, the stash or a reflog entry
+// newer than the expiry cutoff; expired reflog entries are dropped along the way
+fn prune_workflow(args: PruneArgs) -> FitResult<()> {
+    let cutoff = parse_expire(&args.expire)?;
+
+    let mut roots = reachability_roots()?;
+    for (log_path, entries) in read_reflogs()? {
+        let (kept, expired): (Vec<_>, Vec<_>) = entries
+            .into_iter()
+            .partition(|entry| entry.timestamp > cutoff);
+        for entry in &kept {
+            roots.push(entry.old_commit.clone());
+            roots.push(entry.new_commit.clone());
+        }
+        if !expired.is_empty() {
+            let content: String = kept
+                .iter()
+                .map(|entry| format!("{}\n", entry.line))
+                .collect();
+            write_atomic(&log_path, content.as_bytes())?;
+        }
+    }
+
+    let reachable = reachable_objects(roots)?;
+    let mut pruned = 0;
+    for object_path in loose_object_paths()? {
+        if !reachable.contains(&loose_object_hash(&object_path)) {
+            fs::remove_file(&object_path)?;
+            pruned += 1;
+        }
+    }
+    info!("Pruned {} unreachable objects", pruned);
+    Ok(())
+}
+
+// What prune and gc keep whatever its age: HEAD, a stash, every ref, and for each working tree
+// its HEAD (which may be detached at a commit no ref holds) and the blobs it has staged
+fn reachability_roots() -> FitResult<Vec<String>> {
+    let mut roots = vec![get_current_commit()?];
+    for worktree in worktrees()? {
+        roots.push(
+            fs::read_to_string(worktree.admin_dir.join("HEAD"))?
+                .trim()
+                .to_string(),
+        );
+        // Index lines start with the hash, STAGING lines with a status letter and the hash
+        for name in ["index", "STAGING"] {
+            let content = fs::read_to_string(worktree.admin_dir.join(name)).unwrap_or_default();
+            roots.extend(
+                content
+                    .lines()
+                    .flat_map(|line| line.split_whitespace().take(2))
+                    .filter(|word| is_valid_hash(word))
+                    .map(str::to_string),
+            );
+        }
+    }
+    roots.extend(read_stash_list()?);
+    roots.extend(list_refs()?.into_iter().map(|(_, hash)| hash));
+    Ok(roots)
+}
+
+// Collects every object reachable from `roots` through commit parents and trees;
+// roots that don't name a stored object are ignored
+fn reachable_objects(roots: Vec<String>) -> FitResult<HashSet<String>> {
+    let mut reachable = HashSet::new();
+    let mut pending = roots;
+    while let Some(hash) = pending.pop() {
+        if !is_valid_hash(&hash) || reachable.contains(&hash) {
+            continue;
+        }
+        let Some((object_type, content)) = read_object(&hash)? else {
+            continue;
+        };
+        // Trees may hold raw hashes, so only commits and tags are read as text
+        let text = String::from_utf8_lossy(&content);
+        match object_type.as_str() {
+            "commit" => {
+                for line in text.lines().take_while(|line| !line.is_empty()) {
+                    if let Some(hash) = line
+                        .strip_prefix("tree ")
+                        .or_else(|| line.strip_prefix("parent "))
+                    {
+                        pending.push(hash.to_string());
+                    }
+                }
+            }
+            "tag" => {
+                if let Some(hash) = text.lines().find_map(|line| line.strip_prefix("object ")) {
+                    pending.push(hash.to_string());
+                }
+            }
+            "tree" => {
+                for entry in parse_tree(&hash, &content)? {
+                    pending.push(entry.hash);
+                }
+            }
+            _ => {}
+        }
+        reachable.insert(hash);
+    }
+    Ok(reachable)
+}
+
+// Turns an expiry like "2.weeks", "2.weeks.ago", "now", "never" or a plain number of
+// seconds into the cutoff timestamp; only entries newer than it are kept
+fn parse_expire(expire: &str) -> FitResult<i64> {
+    let invalid = || FitError::InvalidArgument(format!("invalid expiry '{}'", expire));
+    let now = default_signature()?.timestamp;
+    match expire {
+        "now" => return Ok(now),
+        "never" => return Ok(i64::MIN),
+        _ => {}
+    }
+    if let Ok(seconds) = expire.parse::<i64>() {
+        return Ok(now - seconds);
+    }
+
+    let expire = expire.strip_suffix(".ago").unwrap_or(expire);
+    let (count, unit) = expire.split_once('.').ok_or_else(invalid)?;
+    let count: i64 = count.parse().map_err(|_| invalid())?;
+    let unit_seconds = match unit.strip_suffix('s').unwrap_or(unit) {
+        "second" => 1,
+        "minute" => 60,
+        "hour" => 60 * 60,
+        "day" => 24 * 60 * 60,
+        "week" => 7 * 24 * 60 * 60,
+        "month" => 30 * 24 * 60 * 60,
+        "year" => 365 * 24 * 60 * 60,
+        _ => return Err(invalid()),
+    };
+    Ok(now - count * unit_seconds)
+}
+
+const FITKEEP: &str = ".fitkeep";
+const MODE_FILE: u32 = 0o100644;
+const MODE_EXECUTABLE: u32 = 0o100755;
+const MODE_SYMLINK: u32 = 0o120000;
+
+// Like git, only the executable bit is tracked; other permission bits never change the mode.
+// A symlink is only recognized from the link's own metadata (fs::symlink_metadata)
+fn file_mode(metadata: &fs::Metadata) -> u32 {
+    if metadata.file_type().is_symlink() {
+        return MODE_SYMLINK;
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        if metadata.permissions().mode() & 0o111 != 0 {
+            return MODE_EXECUTABLE;
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = metadata;
+    MODE_FILE
+}
+
+// A symlink's blob holds its target path
+fn symlink_target(path: &Path) -> FitResult<Vec<u8>> {
+    let target = fs::read_link(path)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        Ok(target.as_os_str().as_bytes().to_vec())
+    }
+    #[cfg(not(unix))]
+    Ok(target.to_string_lossy().as_bytes().to_vec())
+}
+
+// A working file's content as it would be stored, or None if nothing is at `path`: a symlink
+// gives its target without following it, a file its content with line endings normalized
+fn read_working_blob(path: impl AsRef<Path>) -> FitResult<Option<Vec<u8>>> {
+    let path = path.as_ref();
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.is_symlink() => Ok(Some(symlink_target(path)?)),
+        Ok(_) => Ok(Some(convert_to_blob(fs::read(path)?)?)),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+// Writes a blob out to the working tree with the executable bit `mode` calls for, or as a
+// symlink to the path it holds, returning the new file's metadata for its index entry
+fn write_working_file(path: &str, blob: Vec<u8>, mode: u32) -> FitResult<fs::Metadata> {
+    // Writing through a symlink left at the path would change its target instead, and a link
+    // can't be created over an existing file
+    let existing = fs::symlink_metadata(path);
+    if existing.is_ok_and(|metadata| metadata.is_symlink() || mode == MODE_SYMLINK) {
+        fs::remove_file(path)?;
+    }
+    #[cfg(unix)]
+    if mode == MODE_SYMLINK {
+        use std::os::unix::ffi::OsStrExt;
+        let target = std::ffi::OsStr::from_bytes(&blob);
+        std::os::unix::fs::symlink(target, path)?;
+        return Ok(fs::symlink_metadata(path)?);
+    }
+    fs::write(path, convert_to_working(blob)?)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mut permissions = fs::metadata(path)?.permissions();
+        let current = permissions.mode();
+        let wanted = if mode == MODE_EXECUTABLE {
+            // Executable for whoever can read it, as git does under the default umask
+            current | ((current & 0o444) >> 2)
+        } else {
+            current & !0o111
+        };
+        if wanted != current {
+            permissions.set_mode(wanted);
+            fs::set_permissions(path, permissions)?;
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = mode;
+    Ok(fs::symlink_metadata(path)?)
+}
+
+// Maps every file under the tree to its mode, like get_tree_files does to its blob
+fn get_tree_modes(tree_hash: &str) -> FitResult<HashMap<String, u32>> {
+    let mut modes = HashMap::new();
+    walk_tree(tree_hash, "", &mut |path, mode, _| {
+        modes.insert(path, u32::from_str_radix(mode, 8).unwrap_or(MODE_FILE));
+    })?;
+    Ok(modes)
+}
+
+// A tree's files mapped to their blob hashes, alongside their modes
+type TreeListing = (HashMap<String, String>, HashMap<String, u32>);
+
+// Takes each merged file's mode from a side whose content it kept, preferring one that
+// changed the mode since `base_modes` so a chmod on either branch survives the merge
+fn merged_modes(
+    merged: &HashMap<String, String>,
+    base_modes: &HashMap<String, u32>,
+    ours: (&HashMap<String, String>, &HashMap<String, u32>),
+    theirs: (&HashMap<String, String>, &HashMap<String, u32>),
+) -> HashMap<String, u32> {
+    merged
+        .iter()
+        .map(|(path, hash)| {
+            let candidates: Vec<u32> = [ours, theirs]
+                .iter()
+                .filter(|(files, _)| files.get(path) == Some(hash))
+                .filter_map(|(_, modes)| modes.get(path).copied())
+                .collect();
+            let mode = candidates
+                .iter()
+                .find(|mode| base_modes.get(path) != Some(*mode))
+                .or(candidates.first())
+                .copied()
+                .unwrap_or(MODE_FILE);
+            (path.clone(), mode)
+        })
+        .collect()
+}
+
+// Loads the file hashes and modes of each commit's tree for a merge between them
+fn merge_sides(commits: [&str; 3]) -> FitResult<[TreeListing; 3]> {
+    let load = |commit: &str| -> FitResult<TreeListing> {
+        let tree = get_commit_tree(commit)?;
+        Ok((get_tree_files(&tree)?, get_tree_modes(&tree)?))
+    };
+    Ok([load(commits[0])?, load(commits[1])?, load(commits[2])?])
+}
+const FIT_DIR_NAME: &str = ".fit";
+const FITIGNORE: &str = ".fitignore";
+
+// Whether a repository-relative path lies in a .fit directory, which is never tracked
+fn is_fit_internal(relative_path: &str) -> bool {
+    relative_path.split('/').any(|part| part == FIT_DIR_NAME)
+}
+
+// Patterns read from .fitignore at the repository root, one per line, following gitignore:
+// `#` starts a comment, `!` re-includes, a trailing `/` matches only directories, and a
+// pattern containing another `/` is matched against the whole path from the root rather
+// than against the last path component. The last matching pattern wins
+struct IgnoreRules {
+    patterns: Vec<IgnorePattern>,
+}
+
+struct IgnorePattern {
+    glob: String,
+    negated: bool,
+    directory_only: bool,
+    anchored: bool,
+}
+
+impl IgnoreRules {
+    // .fit/info/exclude holds the repository's own patterns that aren't committed. It's read
+    // first so that, with the last matching pattern winning, .fitignore takes precedence
+    fn load() -> FitResult<Self> {
+        let mut content = String::new();
+        for source in [fit_path("info/exclude"), FITIGNORE.into()] {
+            match fs::read_to_string(source) {
+                Ok(source_content) => {
+                    content.push_str(&source_content);
+                    content.push('\n');
+                }
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+        let patterns = content
+            .lines()
+            .map(|line| line.trim_end())
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| {
+                let (negated, line) = match line.strip_prefix('!') {
+                    Some(rest) => (true, rest),
+                    None => (false, line),
+                };
+                let (directory_only, line) = match line.strip_suffix('/') {
+                    Some(rest) => (true, rest),
+                    None => (false, line),
+                };
+                IgnorePattern {
+                    glob: line.trim_start_matches('/').to_string(),
+                    negated,
+                    directory_only,
+                    anchored: line.contains('/'),
+                }
+            })
+            .collect();
+        Ok(IgnoreRules { patterns })
+    }
+
+    fn is_ignored(&self, relative_path: &str, is_dir: bool) -> bool {
+        let name = relative_path.rsplit('/').next().unwrap_or(relative_path);
+        let mut ignored = false;
+        for pattern in &self.patterns {
+            if pattern.directory_only && !is_dir {
+                continue;
+            }
+            let subject = if pattern.anchored {
+                relative_path
+            } else {
+                name
+            };
+            if glob_match(pattern.glob.as_bytes(), subject.as_bytes()) {
+                ignored = !pattern.negated;
+            }
+        }
+        ignored
+    }
+}
+
+// `*` and `?` match within one path component, `**` across any number of them
+fn glob_match(pattern: &[u8], text: &[u8]) -> bool {
+    match pattern {
+        [] => text.is_empty(),
+        [b'*', b'*', rest @ ..] => {
+            let rest = rest.strip_prefix(b"/").unwrap_or(rest);
+            (0..=text.len()).any(|skip| glob_match(rest, &text[skip..]))
+        }
+        [b'*', rest @ ..] => (0..=text.len())
+            .take_while(|&skip| skip == 0 || text[skip - 1] != b'/')
+            .any(|skip| glob_match(rest, &text[skip..])),
+        [b'?', rest @ ..] => {
+            matches!(text, [first, tail @ ..] if *first != b'/' && glob_match(rest, tail))
+        }
+        [expected, rest @ ..] => {
+            matches!(text, [first, tail @ ..] if first == expected && glob_match(rest, tail))
+        }
+    }
+}
+
+fn add_workflow(args: AddArgs) -> FitResult<()> {
+    let path = Path::new(&args.path);
+    if path.exists() && is_fit_internal(&repo_relative_path(path)?) {
+        return Err(FitError::Refused(format!(
+            "'{}' is inside the repository's {} directory",
+            args.path, FIT_DIR_NAME
+        )));
+    }
+    let mut staging_area = read_staging_area()?;
+    let mut index = read_index()?;
+    let folded = fold_tracked_paths(&index)?;
+
+    if args.patch {
+        if !path.is_file() {
+            return Err(FitError::PathNotFound(args.path));
+        }
+        add_patch(path, &mut staging_area, &mut index, &folded)?;
+    } else if args.intent_to_add {
+        if !path.is_file() {
+            return Err(FitError::PathNotFound(args.path));
+        }
+        add_intent(path, &mut staging_area, &mut index, &folded)?;
+    } else if path.is_file() || path.is_dir() || path.is_symlink() {
+        let mut progress = Progress::new("Staging files");
+        // A symlink to a directory is staged as the link, not as the directory's files
+        if path.is_file() || path.is_symlink() {
+            add_file(path, &mut staging_area, &mut index, &folded)?;
+            progress.tick();
+        } else {
+            let ignore_rules = IgnoreRules::load()?;
+            add_directory(
+                path,
+                &ignore_rules,
+                &mut staging_area,
+                &mut index,
+                &folded,
+                &mut progress,
+            )?;
+        }
+        let count = progress.finish();
+        info!("Staged {} file{}", count, if count == 1 { "" } else { "s" });
+    } else {
+        return Err(FitError::PathNotFound(args.path));
+    }
+
+    write_staging_area(&staging_area)?;
+    write_index(&index)?;
+    Ok(())
+}
+
+fn add_file(
+    path: &Path,
+    staging_area: &mut StagingArea,
+    index: &mut HashMap<String, IndexEntry>,
+    folded: &HashMap<String, String>,
+) -> FitResult<()> {
+    let metadata = fs::symlink_metadata(path)?;
+    let file_path = tracked_path(repo_relative_path(path)?, index, folded);
+    // Line ending conversion needs the whole file to tell text from binary, anything else is
+    // hashed and compressed as it's read. A symlink is stored as its target, never followed
+    let hash_hex = if metadata.is_symlink() {
+        write_object(&symlink_target(path)?, "blob")?
+    } else if autocrlf()? == AutoCrlf::False {
+        write_blob_streaming(&mut File::open(path)?, metadata.len())?
+    } else {
+        write_object(&convert_to_blob(fs::read(path)?)?, "blob")?
+    };
+    stage_blob(
+        &file_path,
+        &hash_hex,
+        file_mode(&metadata),
+        staging_area,
+        index,
+    );
+    verbose!("Added {} to staging area", file_path);
+    index.insert(file_path, IndexEntry::new(hash_hex, &metadata));
+    Ok(())
+}
+
+fn ignore_case() -> FitResult<bool> {
+    static IGNORE_CASE: std::sync::OnceLock<bool> = std::sync::OnceLock::new();
+    if let Some(ignore_case) = IGNORE_CASE.get() {
+        return Ok(*ignore_case);
+    }
+    let ignore_case = read_config_value("core.ignorecase")?.as_deref() == Some("true");
+    Ok(*IGNORE_CASE.get_or_init(|| ignore_case))
+}
+
+// With core.ignorecase set, a path differing from a tracked one only in case names the same
+// file on disk, so it's given the tracked spelling instead of becoming a second entry.
+// `folded` comes from fold_tracked_paths, built once per command rather than per path
+fn tracked_path(
+    file_path: String,
+    index: &HashMap<String, IndexEntry>,
+    folded: &HashMap<String, String>,
+) -> String {
+    if folded.is_empty() || index.contains_key(&file_path) {
+        return file_path;
+    }
+    folded
+        .get(&file_path.to_lowercase())
+        .cloned()
+        .unwrap_or(file_path)
+}
+
+// Each tracked path keyed by its lowercase form, the first in sort order where several fold
+// the same. Empty unless core.ignorecase is set, so tracked_path then leaves paths alone
+fn fold_tracked_paths(index: &HashMap<String, IndexEntry>) -> FitResult<HashMap<String, String>> {
+    let mut folded: HashMap<String, String> = HashMap::new();
+    if !ignore_case()? {
+        return Ok(folded);
+    }
+    for path in index.keys() {
+        folded
+            .entry(path.to_lowercase())
+            .and_modify(|tracked| {
+                if path < tracked {
+                    tracked.clone_from(path);
+                }
+            })
+            .or_insert_with(|| path.clone());
+    }
+    Ok(folded)
+}
+
+// Index paths are relative to the repository root, '/'-separated and free of "." and "..",
+// whatever form they were given in; anything resolving outside the root is refused
+fn repo_relative_path(path: &Path) -> FitResult<String> {
+    let not_found = || FitError::PathNotFound(path.display().to_string());
+    let root = std::env::current_dir()?.canonicalize()?;
+    // Only the parent is canonicalized so the file itself is never followed if it's a link
+    let absolute = match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) => {
+            let parent = if parent.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                parent
+            };
+            parent.canonicalize().map_err(|_| not_found())?.join(name)
+        }
+        _ => path.canonicalize().map_err(|_| not_found())?,
+    };
+    let relative = absolute.strip_prefix(&root).map_err(|_| {
+        FitError::Refused(format!("'{}' is outside the repository", path.display()))
+    })?;
+    let parts: Vec<String> = relative
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy().to_string())
+        .collect();
+    Ok(parts.join("/"))
+}
+
+// Tracks an untracked file as an empty new file, so it shows up in status and diffs while
+// its real content stays unstaged until a plain `add`
+fn add_intent(
+    path: &Path,
+    staging_area: &mut StagingArea,
+    index: &mut HashMap<String, IndexEntry>,
+    folded: &HashMap<String, String>,
+) -> FitResult<()> {
+    let file_path = tracked_path(repo_relative_path(path)?, index, folded);
+    if index.contains_key(&file_path) {
+        info!("{} is already tracked", file_path);
+        return Ok(());
+    }
+    let empty_blob = write_object(b"", "blob")?;
+    let mode = file_mode(&fs::symlink_metadata(path)?);
+    stage_blob(&file_path, &empty_blob, mode, staging_area, index);
+    index.insert(
+        file_path.clone(),
+        IndexEntry::unstatted(&empty_blob).with_mode(mode),
+    );
+    info!("Added {} to staging area as an intent to add", file_path);
+    Ok(())
+}
+
+// Records `hash_hex` as the staged content of `file_path` without touching its index entry;
+// a tracked file whose content is unchanged still counts as modified if its mode isn't
+fn stage_blob(
+    file_path: &str,
+    hash_hex: &str,
+    mode: u32,
+    staging_area: &mut StagingArea,
+    index: &HashMap<String, IndexEntry>,
+) {
+    let file_path = file_path.to_string();
+    let hash_hex = hash_hex.to_string();
+    // Keep the path in whichever single bucket already describes it
+    if staging_area.added.contains_key(&file_path) {
+        staging_area.add(file_path.clone(), hash_hex.clone());
+    } else if staging_area.modified.contains_key(&file_path) {
+        staging_area.modify(file_path.clone(), hash_hex.clone());
+    } else if staging_area.deleted.contains(&file_path) {
+        // Re-adding a removed file turns the staged deletion into a modification
+        staging_area.deleted.retain(|path| path != &file_path);
+        staging_area.modify(file_path.clone(), hash_hex.clone());
+    } else if let Some(old_entry) = index.get(&file_path) {
+        if old_entry.hash != hash_hex || old_entry.mode != mode {
+            staging_area.modify(file_path.clone(), hash_hex.clone());
+        }
+    } else {
+        staging_area.add(file_path, hash_hex);
+    }
+}
+
+// Splits the changes between the indexed and working copies of `path` into hunks, asks on
+// stdin which to stage, and stages a blob holding only the accepted ones
+fn add_patch(
+    path: &Path,
+    staging_area: &mut StagingArea,
+    index: &mut HashMap<String, IndexEntry>,
+    folded: &HashMap<String, String>,
+) -> FitResult<()> {
+    let file_path = tracked_path(repo_relative_path(path)?, index, folded);
+    if path.is_symlink() {
+        return Err(FitError::Refused(format!(
+            "cannot stage hunks of symlink '{}'",
+            file_path
+        )));
+    }
+    let working = convert_to_blob(fs::read(path)?)?;
+    let indexed = match index.get(&file_path) {
+        Some(entry) => {
+            read_object(&entry.hash)?
+                .ok_or_else(|| FitError::ObjectNotFound(entry.hash.clone()))?
+                .1
+        }
+        None => Vec::new(),
+    };
+    let (Some(indexed), Some(working)) = (patchable_text(indexed), patchable_text(working)) else {
+        return Err(FitError::Refused(format!(
+            "cannot stage hunks of binary or non-UTF-8 file '{}'",
+            file_path
+        )));
+    };
+    let changes = diff_lines(&indexed, &working, &DiffOptions::default());
+
+    let (hunks, hunk_of) = split_hunks(&changes);
+    if hunks.is_empty() {
+        info!("No changes in {}", file_path);
+        return Ok(());
+    }
+
+    let accepted = choose_hunks(&hunks, "Stage")?;
+    if !accepted.contains(&true) {
+        info!("No hunks staged for {}", file_path);
+        return Ok(());
+    }
+
+    let partial = apply_hunks(&changes, &hunk_of, &accepted);
+    let hash_hex = write_object(partial.as_bytes(), "blob")?;
+    let mode = file_mode(&fs::metadata(path)?);
+    stage_blob(&file_path, &hash_hex, mode, staging_area, index);
+    // The working file no longer matches what is staged, so no stat data is recorded
+    index.insert(
+        file_path.clone(),
+        IndexEntry::unstatted(&hash_hex).with_mode(mode),
+    );
+
+    let staged = accepted.iter().filter(|accepted| **accepted).count();
+    info!(
+        "Staged {} of {} hunks of {}",
+        staged,
+        hunks.len(),
+        file_path
+    );
+    Ok(())
+}
+
+// The text of content that hunks can be split from, or None for binary content or text
+// that isn't valid UTF-8, which a blob rebuilt from the hunks couldn't reproduce byte for byte
+fn patchable_text(content: Vec<u8>) -> Option<String> {
+    if is_binary(&content) {
+        return None;
+    }
+    String::from_utf8(content).ok()
+}
+
+// A hunk is a run of consecutive changed lines. Returns each hunk's lines, headed by its "@@"
+// line, along with the hunk each change belongs to
+fn split_hunks(changes: &[LineChange]) -> (Vec<Vec<String>>, Vec<Option<usize>>) {
+    let mut hunks: Vec<Vec<String>> = Vec::new();
+    let mut hunk_of = Vec::new();
+    let (mut old_line, mut new_line) = (0, 0);
+    let mut in_hunk = false;
+    for change in changes {
+        let line = match change {
+            LineChange::Context(_) => None,
+            LineChange::Removed(line) => Some(format_diff_line('-', line)),
+            LineChange::Added(line) => Some(format_diff_line('+', line)),
+        };
+        match line {
+            Some(line) => {
+                if !in_hunk {
+                    hunks.push(vec![format!("@@ -{} +{} @@", old_line + 1, new_line + 1)]);
+                    in_hunk = true;
+                }
+                hunks.last_mut().unwrap().push(line);
+                hunk_of.push(Some(hunks.len() - 1));
+            }
+            None => {
+                in_hunk = false;
+                hunk_of.push(None);
+            }
+        }
+        match change {
+            LineChange::Context(_) => {
+                old_line += 1;
+                new_line += 1;
+            }
+            LineChange::Removed(_) => old_line += 1,
+            LineChange::Added(_) => new_line += 1,
+        }
+    }
+    (hunks, hunk_of)
+}
+
+// Shows each hunk and asks on stdin whether to `action` it, e.g. "Stage". Running out of
+// answers stops like "q", leaving the remaining hunks unchosen
+fn choose_hunks(hunks: &[Vec<String>], action: &str) -> FitResult<Vec<bool>> {
+    let mut answers = io::stdin().lock().lines();
+    let mut accepted = vec![false; hunks.len()];
+    let mut accept_rest = false;
+    'hunks: for (number, hunk) in hunks.iter().enumerate() {
+        if accept_rest {
+            accepted[number] = true;
+            continue;
+        }
+        println!("{}", hunk.join("\n"));
+        loop {
+            print!(
+                "{} this hunk ({}/{}) [y,n,a,q]? ",
+                action,
+                number + 1,
+                hunks.len()
+            );
+            io::stdout().flush()?;
+            let Some(answer) = answers.next() else {
+                println!();
+                break 'hunks;
+            };
+            match answer?.trim() {
+                "y" => accepted[number] = true,
+                "n" => {}
+                "a" => {
+                    accepted[number] = true;
+                    accept_rest = true;
+                }
+                "q" => break 'hunks,
+                _ => continue,
+            }
+            break;
+        }
+    }
+    Ok(accepted)
+}
+
+// Rebuilds the file from the old side of `changes`, swapping in each hunk marked in `taken`
+fn apply_hunks(changes: &[LineChange], hunk_of: &[Option<usize>], taken: &[bool]) -> String {
+    let mut partial = Vec::new();
+    for (change, hunk) in changes.iter().zip(hunk_of) {
+        let take = hunk.is_some_and(|hunk| taken[hunk]);
+        match change {
+            LineChange::Context(line) => partial.push(*line),
+            LineChange::Removed(line) if !take => partial.push(*line),
+            LineChange::Added(line) if take => partial.push(*line),
+            _ => {}
+        }
+    }
+    partial.concat()
+}
+
+// Adds every file under `path`, never descending into the repository's own .fit directory
+// or into anything .fitignore excludes
+fn add_directory(
+    path: &Path,
+    ignore_rules: &IgnoreRules,
+    staging_area: &mut StagingArea,
+    index: &mut HashMap<String, IndexEntry>,
+    folded: &HashMap<String, String>,
+    progress: &mut Progress,
+) -> FitResult<()> {
+    let mut is_empty = true;
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        let path = entry.path();
+        is_empty = false;
+        let relative = repo_relative_path(&path)?;
+        if is_fit_internal(&relative) || ignore_rules.is_ignored(&relative, path.is_dir()) {
+            verbose!("Skipping ignored path {}", relative);
+            continue;
+        }
+        if path.is_file() || path.is_symlink() {
+            add_file(&path, staging_area, index, folded)?;
+            progress.tick();
+        } else if path.is_dir() {
+            add_directory(&path, ignore_rules, staging_area, index, folded, progress)?;
+        }
+    }
+    if is_empty {
+        // Only files are tracked, so an empty directory needs a placeholder to survive a reset
+        info!(
+            "Skipping empty directory {}, add a {} file to track it",
+            path.display(),
+            FITKEEP
+        );
+    }
+    Ok(())
+}
+
+fn read_staging_area() -> FitResult<StagingArea> {
+    let staging_path = fit_path("STAGING");
+    if !staging_path.exists() {
+        return Ok(StagingArea::new());
+    }
+
+    let staging_content = fs::read_to_string(&staging_path)?;
+    let mut staging_area = StagingArea::new();
+
+    for line in staging_content.lines() {
+        // Deletions are written without a hash, as "D <path>". The path is everything after
+        // the fields before it, so it may contain spaces
+        let Some((kind, rest)) = line.split_once(' ') else {
+            continue;
+        };
+        match (kind, rest.split_once(' ')) {
+            ("A", Some((hash, path))) => staging_area.add(path.to_string(), hash.to_string()),
+            ("M", Some((hash, path))) => staging_area.modify(path.to_string(), hash.to_string()),
+            ("D", _) => staging_area.delete(rest.to_string()),
+            _ => {}
+        }
+    }
+
+    Ok(staging_area)
+}
+
+fn write_staging_area(staging_area: &StagingArea) -> FitResult<()> {
+    let staging_path = fit_path("STAGING");
+    let mut content = String::new();
+
+    for (path, hash) in &staging_area.added {
+        content.push_str(&format!("A {} {}\n", hash, path));
+    }
+    for (path, hash) in &staging_area.modified {
+        content.push_str(&format!("M {} {}\n", hash, path));
+    }
+    for path in &staging_area.deleted {
+        content.push_str(&format!("D {}\n", path));
+    }
+
+    fs::write(staging_path, content)?;
+    Ok(())
+}
+
+fn read_index() -> FitResult<HashMap<String, IndexEntry>> {
+    let index_path = fit_path("index");
+    let index_content = fs::read_to_string(&index_path)?;
+    let mut index = HashMap::new();
+
+    for (line_number, line) in index_content.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let malformed = || FitError::CorruptIndex(format!("line {}: '{}'", line_number + 1, line));
+
+        // Entries are "<hash> <size> <mtime> <mode> <path>"; older indexes lack the octal
+        // mode, and the oldest only have "<hash> <path>"
+        let parts: Vec<&str> = line.splitn(5, ' ').collect();
+        let mode = match parts.as_slice() {
+            [_, _, _, mode, _] if mode.len() == 6 => u32::from_str_radix(mode, 8).ok(),
+            _ => None,
+        };
+        let parts: Vec<&str> = match mode {
+            Some(_) => parts,
+            None => line.splitn(4, ' ').collect(),
+        };
+        let stat = match parts.as_slice() {
+            [_, size, mtime, ..] if parts.len() >= 4 => size.parse().ok().zip(mtime.parse().ok()),
+            _ => None,
+        };
+        let (path, entry) = match stat {
+            Some((size, mtime)) => (
+                *parts.last().unwrap(),
+                IndexEntry {
+                    hash: parts[0].to_string(),
+                    size,
+                    mtime,
+                    mode: mode.unwrap_or(MODE_FILE),
+                },
+            ),
+            None => match line.split_once(' ') {
+                Some((hash, path)) => (path, IndexEntry::unstatted(hash)),
+                None => return Err(malformed()),
+            },
+        };
+        if path.is_empty() || !is_valid_hash(&entry.hash) {
+            return Err(malformed());
+        }
+        index.insert(path.to_string(), entry);
+    }
+
+    Ok(index)
+}
+
+fn write_index(index: &HashMap<String, IndexEntry>) -> FitResult<()> {
+    write_index_to(&fit_path("index"), index)
+}
+
+fn write_index_to(index_path: &Path, index: &HashMap<String, IndexEntry>) -> FitResult<()> {
+    let content: String = index
+        .iter()
+        .map(|(path, entry)| {
+            format!(
+                "{} {} {} {:06o} {}\n",
+                entry.hash, entry.size, entry.mtime, entry.mode, path
+            )
+        })
+        .collect();
+    write_atomic(index_path, content.as_bytes())
+}
+
+fn dir_holds_only(dir: &Path, files: &HashSet<&String>) -> FitResult<bool> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let held = if path.is_dir() {
+            dir_holds_only(&path, files)?
+        } else {
+            path.to_str()
+                .is_some_and(|path| files.contains(&path.to_string()))
+        };
+        if !held {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+// Removes directories left empty after deleting `path`, stopping at the first non-empty one
+fn remove_empty_parents(path: &Path) {
+    for ancestor in path.ancestors().skip(1) {
+        if ancestor.as_os_str().is_empty() || fs::remove_dir(ancestor).is_err() {
+            break;
+        }
+    }
+}
+
+// Writes to a sibling lock file and renames it over the target so readers never see a partial file
+fn write_atomic(path: &Path, content: &[u8]) -> FitResult<()> {
+    let mut lock_path = path.as_os_str().to_owned();
+    lock_path.push(".lock");
+    let lock_path = Path::new(&lock_path);
+    if let Err(err) = fs::write(lock_path, content).and_then(|_| fs::rename(lock_path, path)) {
+        let _ = fs::remove_file(lock_path);
+        return Err(err.into());
+    }
+    Ok(())
+}
+
+// Every ref (branches, tags, HEAD, remote-tracking refs, the stash) is written through here,
+// so a crash mid-write leaves either the old value or the new one, never a truncated ref
+fn write_ref(path: &Path, value: &str) -> FitResult<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    write_atomic(path, value.as_bytes())
+}
+
+fn file_mtime(metadata: &fs::Metadata) -> u64 {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|modified| modified.duration_since(std::time::UNIX_EPOCH).ok())
+        .map(|duration| duration.as_nanos() as u64)
+        .unwrap_or_default()
+}
+
+fn read_config_value(key: &str) -> FitResult<Option<String>> {
+    let config_path = fit_path("config");
+    if !config_path.exists() {
+        return Ok(None);
+    }
+
+    // Keys are written as "section.name" or "section.subsection.name"
+    let (section, name) = match key.rsplit_once('.') {
+        Some(parts) => parts,
+        None => return Ok(None),
+    };
+
+    let config_content = fs::read_to_string(&config_path)?;
+    let mut current_section = String::new();
+    for line in config_content.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+        if let Some(header) = line.strip_prefix('[').and_then(|h| h.strip_suffix(']')) {
+            current_section = match header.split_once(' ') {
+                Some((section, subsection)) => format!(
+                    "{}.{}",
+                    section.to_lowercase(),
+                    subsection.trim().trim_matches('"')
+                ),
+                None => header.to_lowercase(),
+            };
+            continue;
+        }
+        if let Some((entry_name, value)) = line.split_once('=') {
+            if current_section.eq_ignore_ascii_case(section)
+                && entry_name.trim().eq_ignore_ascii_case(name)
+            {
+                return Ok(Some(value.trim().to_string()));
+            }
+        }
+    }
+    Ok(None)
+}
+
+fn ls_files_workflow(args: LsFilesArgs) -> FitResult<()> {
+    let index = read_index()?;
+    let mut paths: Vec<_> = index.keys().collect();
+    paths.sort();
+
+    for path in paths {
+        if args.deleted && Path::new(path).exists() {
+            continue;
+        }
+        if args.stage {
+            println!("{:o} {} {}", index[path].mode, index[path].hash, path);
+        } else {
+            println!("{}", path);
+        }
+    }
+    Ok(())
+}
+
+fn rm_workflow(args: RmArgs) -> FitResult<()> {
+    let mut index = read_index()?;
+    let file_path = tracked_path(args.file.clone(), &index, &fold_tracked_paths(&index)?);
+    // Any tracked file can have its removal staged, even one already deleted from the working
+    // tree; an untracked one is an error like a missing one
+    if index.remove(&file_path).is_none() {
+        return Err(FitError::PathNotFound(args.file));
+    }
+    let mut staging_area = read_staging_area()?;
+    staging_area.delete(file_path);
+    write_staging_area(&staging_area)?;
+    write_index(&index)?;
+    info!("Removed {} from fit index and staging area", args.file);
+    Ok(())
+}
+
+// Removes untracked files, and with -d untracked directories, leaving ignored paths alone
+fn clean_workflow(args: CleanArgs) -> FitResult<()> {
+    if !args.force && !args.dry_run {
+        return Err(FitError::Refused(
+            "clean removes files for good; pass -f to remove them, or -n to list them".to_string(),
+        ));
+    }
+    let index = read_index()?;
+    let mut tracked_dirs = HashSet::new();
+    for path in index.keys() {
+        let mut dir = path.as_str();
+        while let Some((parent, _)) = dir.rsplit_once('/') {
+            tracked_dirs.insert(parent.to_string());
+            dir = parent;
+        }
+    }
+    let cleaner = Cleaner {
+        folded: fold_tracked_paths(&index)?,
+        index,
+        tracked_dirs,
+        ignore_rules: IgnoreRules::load()?,
+        directories: args.directories,
+    };
+    let mut doomed = Vec::new();
+    cleaner.collect("", &mut doomed)?;
+
+    for path in doomed {
+        if args.dry_run {
+            println!("Would remove {}", path);
+            continue;
+        }
+        println!("Removing {}", path);
+        match path.strip_suffix('/') {
+            Some(dir) => fs::remove_dir_all(dir)?,
+            None => fs::remove_file(&path)?,
+        }
+    }
+    Ok(())
+}
+
+struct Cleaner {
+    index: HashMap<String, IndexEntry>,
+    folded: HashMap<String, String>,
+    tracked_dirs: HashSet<String>,
+    ignore_rules: IgnoreRules,
+    directories: bool,
+}
+
+impl Cleaner {
+    // Adds the untracked paths under `dir` to `doomed`, directories with a trailing '/'.
+    // Returns whether everything under `dir` can go, so the caller can remove it whole
+    fn collect(&self, dir: &str, doomed: &mut Vec<String>) -> FitResult<bool> {
+        let mut entries =
+            fs::read_dir(if dir.is_empty() { "." } else { dir })?.collect::<Result<Vec<_>, _>>()?;
+        entries.sort_by_key(|entry| entry.file_name());
+
+        let mut everything = true;
+        for entry in entries {
+            let name = entry.file_name().to_string_lossy().to_string();
+            let path = if dir.is_empty() {
+                name
+            } else {
+                format!("{}/{}", dir, name)
+            };
+            // Symlinks are removed as links, never followed
+            let is_dir = entry.file_type()?.is_dir();
+            if is_fit_internal(&path) || self.ignore_rules.is_ignored(&path, is_dir) {
+                everything = false;
+            } else if !is_dir {
+                if self
+                    .index
+                    .contains_key(&tracked_path(path.clone(), &self.index, &self.folded))
+                {
+                    everything = false;
+                } else {
+                    doomed.push(path);
+                }
+            } else if self.is_tracked_dir(&path)? {
+                self.collect(&path, doomed)?;
+                everything = false;
+            } else if !self.directories || Path::new(&path).join(FIT_DIR_NAME).exists() {
+                // Without -d untracked directories stay, and a nested repository always does
+                everything = false;
+            } else {
+                let mut inner = Vec::new();
+                if self.collect(&path, &mut inner)? {
+                    doomed.push(format!("{}/", path));
+                } else {
+                    doomed.extend(inner);
+                    everything = false;
+                }
+            }
+        }
+        Ok(everything)
+    }
+
+    fn is_tracked_dir(&self, path: &str) -> FitResult<bool> {
+        if ignore_case()? {
+            let folded = path.to_lowercase();
+            return Ok(self
+                .tracked_dirs
+                .iter()
+                .any(|dir| dir.to_lowercase() == folded));
+        }
+        Ok(self.tracked_dirs.contains(path))
+    }
+}
+
+fn commit_workflow(args: CommitArgs) -> FitResult<()> {
+    info!("Commiting...");
+
+    let committer = committer_signature()?;
+    let mut author = default_signature()?;
+    if let Some(author_arg) = &args.author {
+        let (name, email) = parse_author(author_arg)?;
+        author.name = name;
+        author.email = email;
+    }
+    if let Some(date_arg) = &args.date {
+        let (timestamp, offset_minutes) = parse_date(date_arg)?;
+        author.timestamp = timestamp;
+        author.offset_minutes = offset_minutes;
+    }
+
+    if args.all {
+        stage_tracked_changes()?;
+    }
+    let staging_area = read_staging_area()?;
+    if staging_area.added.is_empty()
+        && staging_area.modified.is_empty()
+        && staging_area.deleted.is_empty()
+    {
+        info!("Nothing to commit. Working tree clean.");
+        return Ok(());
+    }
+
+    let mut index = read_index()?;
+    for path in staging_area.added.keys() {
+        verbose!("  new file: {}", path);
+    }
+    for path in staging_area.modified.keys() {
+        verbose!("  modified: {}", path);
+    }
+    for path in &staging_area.deleted {
+        verbose!("  deleted: {}", path);
+    }
+
+    apply_staged_changes(&mut index, &staging_area);
+
+    let tree_hash = create_tree_object(&index)?;
+    info!("Tree object created with hash: {}", tree_hash);
+
+    let parent_hash = get_current_commit()?;
+    info!("Current commit (parent) hash: {}", parent_hash);
+
+    // Committing the resolution of a conflicted merge records the merged commit as second parent
+    let merge_state = fs::read_to_string(fit_path("MERGE_STATE")).unwrap_or_default();
+    let parents: String = std::iter::once(parent_hash.as_str())
+        .chain(
+            merge_state
+                .lines()
+                .filter_map(|line| line.strip_prefix("merge-head ")),
+        )
+        .map(|parent| format!("parent {}\n", parent))
+        .collect();
+
+    let message = match (&args.fixup, &args.squash) {
+        (Some(target), _) => marker_message("fixup", target, args.message.as_deref())?,
+        (_, Some(target)) => marker_message("squash", target, args.message.as_deref())?,
+        _ => args.message.clone().unwrap_or_default(),
+    };
+    let mut commit_content = format!(
+        "tree {}\n{}author {}\ncommitter {}\n\n{}",
+        tree_hash,
+        parents,
+        author.format(),
+        committer.format(),
+        message
+    );
+    // commit.gpgSign only signs when a signer is set up; -S insists on one
+    let sign_by_default = read_config_value("commit.gpgSign")?.as_deref() == Some("true");
+    if args.gpg_sign || sign_by_default {
+        if args.gpg_sign || read_config_value("gpg.program")?.is_some() {
+            commit_content = sign_commit(&commit_content)?;
+        } else {
+            eprintln!("warning: commit.gpgSign is set but gpg.program is not, committing unsigned");
+        }
+    }
+    info!("Commit content created.");
+
+    let commit_hash = write_object(commit_content.as_bytes(), "commit")?;
+    info!("Commit object written with hash: {}", commit_hash);
+
+    let summary = message.lines().next().unwrap_or_default();
+    update_current_branch(&commit_hash, &format!("commit: {}", summary))?;
+    info!("Current branch updated.");
+
+    write_index(&index)?;
+    if !merge_state.is_empty() && parents.lines().count() > 1 {
+        fs::remove_file(fit_path("MERGE_STATE"))?;
+    }
+    // Cleared only once everything else is recorded, so a commit that fails partway leaves
+    // the staged changes in place to retry
+    fs::remove_file(fit_path("STAGING"))?;
+
+    info!("Created commit {}", commit_hash);
+    Ok(())
+}
+
+// "fixup! <summary>" or "squash! <summary>" naming the target commit by the first line of its
+// message, which is how an autosquashing rebase finds it, with any message of its own below
+fn marker_message(marker: &str, target: &str, message: Option<&str>) -> FitResult<String> {
+    let target = resolve_ref(target)?;
+    let (_, content) =
+        read_object(&target)?.ok_or_else(|| FitError::ObjectNotFound(target.clone()))?;
+    let content = String::from_utf8_lossy(&content);
+    let target_message = content
+        .split_once("\n\n")
+        .map_or("", |(_, message)| message);
+    let summary = target_message.lines().next().unwrap_or_default();
+    Ok(match message {
+        Some(message) => format!("{}! {}\n\n{}", marker, summary, message),
+        None => format!("{}! {}", marker, summary),
+    })
+}
+
+// Applies the changes in the staging area to the index, giving the index the next commit's
+// tree is built from
+fn apply_staged_changes(index: &mut HashMap<String, IndexEntry>, staging_area: &StagingArea) {
+    for (path, hash) in staging_area
+        .added
+        .iter()
+        .chain(staging_area.modified.iter())
+    {
+        if index.get(path).map(|entry| &entry.hash) != Some(hash) {
+            let mode = index.get(path).map_or(MODE_FILE, |entry| entry.mode);
+            index.insert(path.clone(), IndexEntry::unstatted(hash).with_mode(mode));
+        }
+    }
+    for path in &staging_area.deleted {
+        // A deleted directory drops every file beneath it; paths no longer indexed are ignored
+        if index.remove(path).is_none() {
+            let dir_prefix = format!("{}/", path.trim_end_matches('/'));
+            index.retain(|indexed_path, _| !indexed_path.starts_with(&dir_prefix));
+        }
+    }
+}
+
+fn default_signature() -> FitResult<Signature> {
+    let fallback_name = std::env::var("USER").unwrap_or_else(|_| "unknown".to_string());
+    let name = read_config_value("user.name")?.unwrap_or_else(|| fallback_name.clone());
+    let email =
+        read_config_value("user.email")?.unwrap_or_else(|| format!("{}@localhost", fallback_name));
+    let timestamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|duration| duration.as_secs() as i64)
+        .unwrap_or_default();
+    Ok(Signature {
+        name,
+        email,
+        timestamp,
+        offset_minutes: 0,
+    })
+}
+
+// The committer of a new commit. FIT_COMMITTER_DATE, like git's GIT_COMMITTER_DATE, replaces
+// the current time with any date `commit --date` accepts, so commits can be reproduced exactly
+fn committer_signature() -> FitResult<Signature> {
+    let mut committer = default_signature()?;
+    if let Ok(date) = std::env::var("FIT_COMMITTER_DATE") {
+        let (timestamp, offset_minutes) = parse_date(&date)?;
+        committer.timestamp = timestamp;
+        committer.offset_minutes = offset_minutes;
+    }
+    Ok(committer)
+}
+
+// Parses "Name <email>" as accepted by --author
+fn parse_author(author: &str) -> FitResult<(String, String)> {
+    let invalid = || {
+        FitError::InvalidArgument(format!(
+            "invalid author '{}', expected \"Name <email>\"",
+            author
+        ))
+    };
+    let (name, rest) = author.split_once('<').ok_or_else(invalid)?;
+    let email = rest.trim().strip_suffix('>').ok_or_else(invalid)?;
+    let name = name.trim();
+    if name.is_empty() || email.is_empty() || email.contains(['<', '>']) {
+        return Err(invalid());
+    }
+    Ok((name.to_string(), email.to_string()))
+}
+
+// Accepts a unix timestamp ("1700000000", "@1700000000", optionally followed by "+hhmm")
+// or an ISO 8601 style date ("2024-01-31", "2024-01-31T12:30:00", "2024-01-31 12:30:00 +0100")
+fn parse_date(date: &str) -> FitResult<(i64, i32)> {
+    let invalid = || FitError::InvalidArgument(format!("invalid date '{}'", date));
+    let mut datetime = date.trim();
+    let mut offset_minutes = 0;
+
+    // Split off a trailing zone, either as a separate "+hhmm" word or glued onto a "T" time
+    if let Some((rest, zone)) = datetime.rsplit_once(' ') {
+        if let Some(offset) = parse_zone(zone) {
+            datetime = rest.trim_end();
+            offset_minutes = offset;
+        }
+    }
+    if let Some(rest) = datetime.strip_suffix('Z') {
+        datetime = rest;
+    } else if let Some(time_start) = datetime.find('T') {
+        if let Some(zone_start) = datetime[time_start..].rfind(['+', '-']) {
+            let zone_start = time_start + zone_start;
+            let offset = parse_zone(&datetime[zone_start..]).ok_or_else(invalid)?;
+            datetime = &datetime[..zone_start];
+            offset_minutes = offset;
+        }
+    }
+
+    let raw = datetime.strip_prefix('@').unwrap_or(datetime);
+    if !raw.is_empty() && raw.chars().all(|c| c.is_ascii_digit()) {
+        return Ok((raw.parse().map_err(|_| invalid())?, offset_minutes));
+    }
+
+    let (day, time) = match datetime.split_once(['T', ' ']) {
+        Some((day, time)) => (day, Some(time)),
+        None => (datetime, None),
+    };
+    let day_parts: Vec<&str> = day.split('-').collect();
+    if day_parts.len() != 3 {
+        return Err(invalid());
+    }
+    let year: i64 = day_parts[0].parse().map_err(|_| invalid())?;
+    let month: i64 = day_parts[1].parse().map_err(|_| invalid())?;
+    let day: i64 = day_parts[2].parse().map_err(|_| invalid())?;
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return Err(invalid());
+    }
+
+    let mut seconds_of_day = 0;
+    if let Some(time) = time {
+        let time_parts: Vec<&str> = time.split(':').collect();
+        if time_parts.len() < 2 || time_parts.len() > 3 {
+            return Err(invalid());
+        }
+        let mut limits = [24, 60, 60].iter();
+        for part in &time_parts {
+            let value: i64 = part.parse().map_err(|_| invalid())?;
+            if value < 0 || value >= *limits.next().unwrap() {
+                return Err(invalid());
+            }
+            seconds_of_day = seconds_of_day * 60 + value;
+        }
+        if time_parts.len() == 2 {
+            seconds_of_day *= 60;
+        }
+    }
+
+    let local = days_from_civil(year, month, day) * 86400 + seconds_of_day;
+    Ok((local - offset_minutes as i64 * 60, offset_minutes))
+}
+
+fn parse_zone(zone: &str) -> Option<i32> {
+    let (sign, digits) = match zone.as_bytes().first()? {
+        b'+' => (1, zone[1..].replace(':', "")),
+        b'-' => (-1, zone[1..].replace(':', "")),
+        _ => return None,
+    };
+    if digits.len() != 4 || !digits.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let hours: i32 = digits[..2].parse().ok()?;
+    let minutes: i32 = digits[2..].parse().ok()?;
+    if minutes >= 60 {
+        return None;
+    }
+    Some(sign * (hours * 60 + minutes))
+}
+
+// Days since the unix epoch for a proleptic Gregorian calendar date
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = if year >= 0 { year } else { year - 399 } / 400;
+    let year_of_era = year - era * 400;
+    let month_index = (month + 9) % 12;
+    let day_of_year = (153 * month_index + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+// The calendar date of a day counted from the unix epoch, the inverse of days_from_civil
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = if days >= 0 { days } else { days - 146096 } / 146097;
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+fn create_tree_object(index: &HashMap<String, IndexEntry>) -> FitResult<String> {
+    let files: Vec<(&str, &str, u32)> = index
+        .iter()
+        .map(|(path, entry)| (path.as_str(), entry.hash.as_str(), entry.mode))
+        .collect();
+    write_tree(&files)
+}
+
+// Writes a tree for `files` (paths relative to this directory) and, first, one subtree per
+// directory beneath it, so each tree only lists its own entries like git's do
+fn write_tree(files: &[(&str, &str, u32)]) -> FitResult<String> {
+    let mut entries = Vec::new();
+    let mut subdirectories: HashMap<&str, Vec<(&str, &str, u32)>> = HashMap::new();
+    for (path, hash, mode) in files {
+        match path.split_once('/') {
+            Some((directory, rest)) => subdirectories
+                .entry(directory)
+                .or_default()
+                .push((rest, hash, *mode)),
+            None => entries.push((path.to_string(), format!("{:o} blob {}", mode, hash))),
+        }
+    }
+    for (directory, children) in subdirectories {
+        let subtree_hash = write_tree(&children)?;
+        entries.push((
+            directory.to_string(),
+            format!("40000 tree {}", subtree_hash),
+        ));
+    }
+
+    // Entries are sorted so the same set of files always hashes to the same tree
+    entries.sort();
+    let tree_content: String = entries
+        .iter()
+        .map(|(name, entry)| format!("{} {}\n", entry, name))
+        .collect();
+    write_object(tree_content.as_bytes(), "tree")
+}
+
+// Writes a commit of exactly the given tree, parents and message without touching any ref,
+// so scripts can assemble history and point a branch at it with update-ref
+fn commit_tree_workflow(args: CommitTreeArgs) -> FitResult<()> {
+    let tree = resolve_ref(&args.tree).unwrap_or(args.tree);
+    read_object_typed(&tree, "tree")?;
+    let mut parents = String::new();
+    for parent in &args.parents {
+        let parent = resolve_ref(parent)?;
+        read_object_typed(&parent, "commit")?;
+        parents.push_str(&format!("parent {}\n", parent));
+    }
+    let commit_content = format!(
+        "tree {}\n{}author {}\ncommitter {}\n\n{}",
+        tree,
+        parents,
+        default_signature()?.format(),
+        committer_signature()?.format(),
+        args.message
+    );
+    println!("{}", write_object(commit_content.as_bytes(), "commit")?);
+    Ok(())
+}
+
+fn read_head() -> FitResult<HeadState> {
+    let head_content = fs::read_to_string(fit_path("HEAD"))?;
+    let head_content = head_content.trim();
+    match head_content.strip_prefix("ref: ") {
+        Some(ref_path) => match ref_path.strip_prefix("refs/heads/") {
+            Some(branch) => Ok(HeadState::Symbolic(branch.to_string())),
+            None => Err(FitError::InvalidRef(format!(
+                "HEAD points at unsupported ref '{}'",
+                ref_path
+            ))),
+        },
+        None => Ok(HeadState::Detached(head_content.to_string())),
+    }
+}
+
+fn write_head(head: &HeadState) -> FitResult<()> {
+    let head_content = match head {
+        HeadState::Symbolic(branch) => format!("ref: refs/heads/{}\n", branch),
+        HeadState::Detached(hash) => format!("{}\n", hash),
+    };
+    write_ref(&fit_path("HEAD"), &head_content)?;
+    Ok(())
+}
+
+fn get_current_commit() -> FitResult<String> {
+    match read_head()? {
+        HeadState::Symbolic(branch) => {
+            let branch_path = fit_path("refs/heads").join(branch);
+            Ok(fs::read_to_string(branch_path)?.trim().to_string())
+        }
+        HeadState::Detached(hash) => Ok(hash),
+    }
+}
+
+// Like get_current_commit, but a branch whose ref file is missing has no commits rather
+// than being an error
+fn current_commit_or_empty() -> FitResult<String> {
+    match get_current_commit() {
+        Err(FitError::Io(err)) if err.kind() == io::ErrorKind::NotFound => Ok(String::new()),
+        result => result,
+    }
+}
+
+// Resolves HEAD, branch names, full ref paths, remote-tracking branches and full hashes,
+// plus `<ref>@{n}` for the value a ref had n moves ago
+fn resolve_ref(name: &str) -> FitResult<String> {
+    if name == "HEAD" {
+        return get_current_commit();
+    }
+    if let Some((base, position)) = name
+        .strip_suffix('}')
+        .and_then(|rest| rest.rsplit_once("@{"))
+    {
+        if let Ok(position) = position.parse::<usize>() {
+            return resolve_reflog_position(base, position);
+        }
+    }
+    let candidates = if name.starts_with("refs/") {
+        vec![fit_path(name)]
+    } else {
+        vec![
+            fit_path("refs/heads").join(name),
+            fit_path("refs/tags").join(name),
+            fit_path("refs/remotes").join(name),
+        ]
+    };
+    for candidate in candidates {
+        if candidate.is_file() {
+            let mut content = fs::read_to_string(candidate)?.trim().to_string();
+            // A symbolic ref written by update-ref names another ref, maybe symbolic itself
+            let mut hops = 0;
+            while let Some(target) = content.strip_prefix("ref: ") {
+                hops += 1;
+                if hops > 5 {
+                    return Err(FitError::InvalidRef(format!(
+                        "too many levels of symbolic refs resolving '{}'",
+                        name
+                    )));
+                }
+                content = fs::read_to_string(fit_path(target))
+                    .map_err(|_| {
+                        FitError::InvalidRef(format!("'{}' points at missing ref {}", name, target))
+                    })?
+                    .trim()
+                    .to_string();
+            }
+            return peel_tag(&content);
+        }
+    }
+    if is_valid_hash(name) && read_object(name)?.is_some() {
+        return Ok(name.to_string());
+    }
+    Err(FitError::InvalidRef(format!("unknown revision '{}'", name)))
+}
+
+// `HEAD@{n}` and `<branch>@{n}` read the ref's reflog, newest entry first; a bare `@{n}` means
+// the current branch, as in git
+fn resolve_reflog_position(base: &str, position: usize) -> FitResult<String> {
+    let ref_name = match base {
+        "HEAD" => "HEAD".to_string(),
+        "" => match read_head()? {
+            HeadState::Symbolic(branch) => format!("refs/heads/{}", branch),
+            HeadState::Detached(_) => "HEAD".to_string(),
+        },
+        base if base.starts_with("refs/") => base.to_string(),
+        branch => format!("refs/heads/{}", branch),
+    };
+    let log = fs::read_to_string(fit_path(Path::new("logs").join(&ref_name)))
+        .map_err(|_| FitError::InvalidRef(format!("no reflog for '{}'", ref_name)))?;
+    let moves: Vec<(&str, &str)> = log
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            Some((fields.next()?, fields.next()?))
+        })
+        .collect();
+
+    // @{0} is the latest move's new value; one past the oldest move is the value before it
+    let commit = if position < moves.len() {
+        moves[moves.len() - 1 - position].1
+    } else if position == moves.len() && !moves.is_empty() {
+        moves[0].0
+    } else {
+        ""
+    };
+    if !is_valid_hash(commit) {
+        return Err(FitError::InvalidRef(format!(
+            "log for '{}' only has {} entries",
+            ref_name,
+            moves.len()
+        )));
+    }
+    Ok(commit.to_string())
+}
+
+// Follows annotated tag objects down to the object they point at
+fn peel_tag(hash: &str) -> FitResult<String> {
+    let mut hash = hash.to_string();
+    while let Some((object_type, content)) = read_object(&hash)? {
+        if object_type != "tag" {
+            break;
+        }
+        let content = String::from_utf8_lossy(&content);
+        hash = content
+            .lines()
+            .find_map(|line| line.strip_prefix("object "))
+            .ok_or_else(|| FitError::CorruptObject(format!("tag {} has no object line", hash)))?
+            .to_string();
+    }
+    Ok(hash)
+}
+
+// Moves the current branch (or a detached HEAD) to `commit_hash`, recording the move in the reflog
+fn update_current_branch(commit_hash: &str, message: &str) -> FitResult<()> {
+    let old_commit = get_current_commit()?;
+    match read_head()? {
+        HeadState::Symbolic(branch) => {
+            let branch_ref = format!("refs/heads/{}", branch);
+            write_ref(&fit_path(&branch_ref), commit_hash)?;
+            if old_commit != commit_hash {
+                append_reflog(&branch_ref, &old_commit, commit_hash, message)?;
+            }
+        }
+        HeadState::Detached(_) => write_head(&HeadState::Detached(commit_hash.to_string()))?,
+    }
+    if old_commit != commit_hash {
+        append_reflog("HEAD", &old_commit, commit_hash, message)?;
+    }
+    Ok(())
+}
+
+// Reflog lines follow git: "<old> <new> <committer>\t<message>", one file per ref under .fit/logs
+fn append_reflog(
+    ref_name: &str,
+    old_commit: &str,
+    new_commit: &str,
+    message: &str,
+) -> FitResult<()> {
+    let log_path = fit_path(Path::new("logs").join(ref_name));
+    if let Some(parent) = log_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut log_file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log_path)?;
+    // A ref that didn't exist before is logged as moving from the null hash, as git does, so
+    // every line keeps its two hash fields
+    let old_commit = if old_commit.is_empty() {
+        "0000000000000000000000000000000000000000"
+    } else {
+        old_commit
+    };
+    writeln!(
+        log_file,
+        "{} {} {}\t{}",
+        old_commit,
+        new_commit,
+        default_signature()?.format(),
+        message
+    )?;
+    Ok(())
+}
+
+struct ReflogEntry {
+    old_commit: String,
+    new_commit: String,
+    timestamp: i64,
+    line: String,
+}
+
+// Reads every reflog under .fit/logs, keyed by the log file's path
+fn read_reflogs() -> FitResult<Vec<(std::path::PathBuf, Vec<ReflogEntry>)>> {
+    let mut log_paths = Vec::new();
+    let mut pending = vec![fit_path("logs")];
+    while let Some(dir) = pending.pop() {
+        if !dir.is_dir() {
+            continue;
+        }
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                pending.push(path);
+            } else {
+                log_paths.push(path);
+            }
+        }
+    }
+
+    let mut reflogs = Vec::new();
+    for log_path in log_paths {
+        let mut entries = Vec::new();
+        for line in fs::read_to_string(&log_path)?.lines() {
+            let (signature, _) = line.split_once('\t').unwrap_or((line, ""));
+            let fields: Vec<&str> = signature.split_whitespace().collect();
+            // old, new, then a signature ending in "<timestamp> <zone>"
+            if fields.len() < 4 {
+                continue;
+            }
+            entries.push(ReflogEntry {
+                old_commit: fields[0].to_string(),
+                new_commit: fields[1].to_string(),
+                timestamp: fields[fields.len() - 2].parse().unwrap_or_default(),
+                line: line.to_string(),
+            });
+        }
+        reflogs.push((log_path, entries));
+    }
+    Ok(reflogs)
+}
+
+fn get_parent_commits(commit_info: &str) -> Vec<String> {
+    commit_info
+        .lines()
+        .take_while(|line| !line.is_empty())
+        .filter_map(|line| line.strip_prefix("parent "))
+        .map(|parent| parent.to_string())
+        .collect()
+}
+
+fn get_parent_commit(commit_info: &str) -> String {
+    commit_info
+        .lines()
+        .find(|line| line.starts_with("parent "))
+        .map(|line| line.strip_prefix("parent ").unwrap().to_string())
+        .unwrap_or_default()
+}
+
+fn verify_commit_workflow(args: VerifyCommitArgs) -> FitResult<()> {
+    verify_commit(&args.commit)?;
+    println!("commit {} is well-formed", args.commit);
+    if args.signature {
+        let content = read_object_typed(&args.commit, "commit")?;
+        let content = String::from_utf8_lossy(&content);
+        let Some((payload, signature)) = split_commit_signature(&content) else {
+            return Err(FitError::Refused(format!(
+                "commit {} is not signed",
+                args.commit
+            )));
+        };
+        if !run_verifier(&payload, &signature)? {
+            return Err(FitError::Refused(format!(
+                "signature of commit {} could not be verified",
+                args.commit
+            )));
+        }
+        println!("Good signature on commit {}", args.commit);
+    }
+    Ok(())
+}
+
+// Checks the commit's headers and the objects they point at, failing on the first problem
+fn verify_commit(commit_hash: &str) -> FitResult<()> {
+    let malformed =
+        |problem: String| FitError::CorruptObject(format!("commit {}: {}", commit_hash, problem));
+
+    let (object_type, content) =
+        read_object(commit_hash)?.ok_or_else(|| malformed("object not found".to_string()))?;
+    if object_type != "commit" {
+        return Err(malformed(format!("expected commit, found {}", object_type)));
+    }
+    let content = String::from_utf8_lossy(&content);
+    let commit_info = content
+        .split_once("\n\n")
+        .map_or(&*content, |(info, _)| info);
+
+    let mut lines = commit_info.lines();
+    let tree_hash = lines
+        .next()
+        .and_then(|line| line.strip_prefix("tree "))
+        .ok_or_else(|| malformed("missing tree line".to_string()))?;
+    if !is_valid_hash(tree_hash) {
+        return Err(malformed(format!("malformed tree hash '{}'", tree_hash)));
+    }
+    match read_object(tree_hash)? {
+        Some((object_type, _)) if object_type == "tree" => {}
+        Some((object_type, _)) => {
+            return Err(malformed(format!(
+                "tree {} is a {}, not a tree",
+                tree_hash, object_type
+            )))
+        }
+        None => return Err(malformed(format!("tree {} does not exist", tree_hash))),
+    }
+
+    for line in lines {
+        let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+        match key {
+            "parent" => {
+                if !is_valid_hash(value) {
+                    return Err(malformed(format!("malformed parent hash '{}'", value)));
+                }
+                match read_object(value)? {
+                    Some((object_type, _)) if object_type == "commit" => {}
+                    Some((object_type, _)) => {
+                        return Err(malformed(format!(
+                            "parent {} is a {}, not a commit",
+                            value, object_type
+                        )))
+                    }
+                    None => return Err(malformed(format!("parent {} does not exist", value))),
+                }
+            }
+            "author" | "committer" if !is_valid_signature(value) => {
+                return Err(malformed(format!("malformed {} line '{}'", key, value)));
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn is_valid_hash(hash: &str) -> bool {
+    hash.len() == 40 && hash.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+// Matches the "Name <email> <timestamp> <+hhmm>" layout written by Signature::format
+fn is_valid_signature(signature: &str) -> bool {
+    let mut parts = signature.rsplitn(3, ' ');
+    let (zone, timestamp, identity) = match (parts.next(), parts.next(), parts.next()) {
+        (Some(zone), Some(timestamp), Some(identity)) => (zone, timestamp, identity),
+        _ => return false,
+    };
+    parse_zone(zone).is_some()
+        && !timestamp.is_empty()
+        && timestamp.chars().all(|c| c.is_ascii_digit())
+        && parse_author(identity).is_ok()
+}
+
+fn cat_file_workflow(args: FileArgs) -> FitResult<()> {
+    let Some(hash) = args.hash else {
+        return cat_file_batch();
+    };
+    if args.recursive {
+        return cat_file_recursive(&hash);
+    }
+    println!("Unhashing SHA: {}", hash);
+    match read_object(&hash)? {
+        // A tree written by git is binary, so trees are always shown in fit's text format
+        Some((object_type, content)) if object_type == "tree" => {
+            println!("Object type: {}", object_type);
+            println!("Content:");
+            for entry in parse_tree(&hash, &content)? {
+                let TreeEntry {
+                    mode,
+                    object_type,
+                    hash,
+                    name,
+                } = entry;
+                println!("{} {} {} {}", mode, object_type, hash, name);
+            }
+        }
+        Some((object_type, content)) => {
+            println!("Object type: {}", object_type);
+            println!("Content:");
+            println!("{}", String::from_utf8_lossy(&content));
+        }
+        None => println!("Object not found"),
+    }
+    Ok(())
+}
+
+// Prints "<mode> blob <hash>\t<path>" for every file under the tree, or under a commit's
+// tree, descending into subtrees so the whole snapshot is listed flat
+fn cat_file_recursive(hash: &str) -> FitResult<()> {
+    let (object_type, _) =
+        read_object(hash)?.ok_or_else(|| FitError::ObjectNotFound(hash.to_string()))?;
+    let tree = match object_type.as_str() {
+        "tree" => hash.to_string(),
+        "commit" => get_commit_tree(hash)?,
+        other => {
+            return Err(FitError::InvalidArgument(format!(
+                "{} is a {}, not a tree or commit",
+                hash, other
+            )))
+        }
+    };
+    let mut files = Vec::new();
+    walk_tree(&tree, "", &mut |path, mode, hash| {
+        files.push((path, mode.to_string(), hash.to_string()));
+    })?;
+    files.sort();
+    for (path, mode, hash) in files {
+        println!("{} blob {}\t{}", mode, hash, path);
+    }
+    Ok(())
+}
+
+// Reads one hash per line from stdin and writes "<hash> <type> <size>\n<content>\n" for
+// each, or "<hash> missing\n" when there's no such object
+fn cat_file_batch() -> FitResult<()> {
+    let stdout = io::stdout();
+    let mut out = io::BufWriter::new(stdout.lock());
+    for line in io::stdin().lock().lines() {
+        let line = line?;
+        let hash = line.trim();
+        if hash.is_empty() {
+            continue;
+        }
+        let object = if is_valid_hash(hash) {
+            read_object(hash)?
+        } else {
+            None
+        };
+        match object {
+            Some((object_type, content)) => {
+                writeln!(out, "{} {} {}", hash, object_type, content.len())?;
+                out.write_all(&content)?;
+                writeln!(out)?;
+            }
+            None => writeln!(out, "{} missing", hash)?,
+        }
+    }
+    out.flush()?;
+    Ok(())
+}
+
+fn status_workflow() -> FitResult<()> {
+    match get_current_branch()? {
+        Some(branch) => println!("On branch: {}", branch),
+        None => println!("HEAD detached at {}", &get_current_commit()?[..7]),
+    }
+    let staging_area = read_staging_area()?;
+    let mut index = read_index()?;
+
+    // A staged deletion's content is whatever HEAD has at that path
+    let head_commit = current_commit_or_empty()?;
+    let head_files = if head_commit.is_empty() {
+        HashMap::new()
+    } else {
+        get_tree_files(&get_commit_tree(&head_commit)?)?
+    };
+    let staged_deletions = staging_area
+        .deleted
+        .iter()
+        .map(|path| {
+            (
+                path.clone(),
+                head_files.get(path).cloned().unwrap_or_default(),
+            )
+        })
+        .collect();
+    let staged_additions = staging_area
+        .added
+        .iter()
+        .map(|(path, hash)| (path.clone(), hash.clone()))
+        .collect();
+    let (renamed, deleted, added) = pair_renames(staged_deletions, staged_additions);
+
+    println!("Changes to be committed:");
+    for path in added {
+        println!("  new file: {}", path);
+    }
+    for path in staging_area.modified.keys() {
+        println!("  modified: {}", path);
+    }
+    for path in deleted {
+        println!("  deleted: {}", path);
+    }
+    for (old_path, new_path) in renamed {
+        println!("  renamed: {} -> {}", old_path, new_path);
+    }
+
+    let ignore_rules = IgnoreRules::load()?;
+    let folded = fold_tracked_paths(&index)?;
+    let mut untracked = Vec::new();
+    for entry in fs::read_dir(".")? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        // A linked worktree's .fit is a file
+        let file_type = entry.file_type()?;
+        if (file_type.is_file() || file_type.is_symlink())
+            && !index.contains_key(&tracked_path(name.clone(), &index, &folded))
+            && !is_fit_internal(&name)
+            && !ignore_rules.is_ignored(&name, false)
+        {
+            untracked.push(name);
+        }
+    }
+
+    println!("\nChanges not staged for commit:");
+    let (unstaged, index_refreshed) = unstaged_changes(&mut index)?;
+    let mut unstaged_deletions = Vec::new();
+    for (status, path) in unstaged {
+        match status {
+            'M' => println!("  modified: {}", path),
+            _ => {
+                let hash = index[&path].hash.clone();
+                unstaged_deletions.push((path, hash));
+            }
+        }
+    }
+    // A tracked file that went missing while an untracked one appeared with its content;
+    // untracked files are only hashed when something went missing
+    let untracked = if unstaged_deletions.is_empty() {
+        untracked
+            .into_iter()
+            .map(|name| (name, String::new()))
+            .collect()
+    } else {
+        let mut hashed = Vec::new();
+        for name in untracked {
+            let content = read_working_blob(&name)?.unwrap_or_default();
+            let hash = hash_object(&content, "blob");
+            hashed.push((name, hash));
+        }
+        hashed
+    };
+    let (renamed, deleted, untracked) = pair_renames(unstaged_deletions, untracked);
+    for path in deleted {
+        println!("  deleted: {}", path);
+    }
+    for (old_path, new_path) in renamed {
+        println!("  renamed: {} -> {}", old_path, new_path);
+    }
+
+    println!("\nUntracked files:");
+    for name in untracked {
+        println!("  {}", name);
+    }
+
+    if index_refreshed {
+        write_index(&index)?;
+    }
+
+    Ok(())
+}
+
+// Pairs each deleted path with an added path holding exactly the same blob, returning the
+// renames, then the deleted and added paths left unpaired, each sorted by path
+fn pair_renames(
+    mut deleted: Vec<(String, String)>,
+    mut added: Vec<(String, String)>,
+) -> (Vec<(String, String)>, Vec<String>, Vec<String>) {
+    deleted.sort();
+    added.sort();
+    let mut renames = Vec::new();
+    let mut unpaired_deletions = Vec::new();
+    for (old_path, hash) in deleted {
+        let matching = added
+            .iter()
+            .position(|(_, added_hash)| !hash.is_empty() && *added_hash == hash);
+        match matching {
+            Some(position) => renames.push((old_path, added.remove(position).0)),
+            None => unpaired_deletions.push(old_path),
+        }
+    }
+    let unpaired_additions = added.into_iter().map(|(path, _)| path).collect();
+    (renames, unpaired_deletions, unpaired_additions)
+}
+
+// Lists tracked files whose working copy differs from the index ('M') or is gone ('D').
+// The index holds the staged blob of a staged file, so one edited again after `add` is
+// listed too. Entries found unchanged get fresh stat data; the returned flag says whether
+// any did, so the caller knows the index is worth rewriting
+fn unstaged_changes(
+    index: &mut HashMap<String, IndexEntry>,
+) -> FitResult<(Vec<(char, String)>, bool)> {
+    let mut changes = Vec::new();
+    let mut index_refreshed = false;
+    for (path, entry) in index.iter_mut() {
+        if let Ok(metadata) = fs::symlink_metadata(path) {
+            // A chmod doesn't touch the mtime, so the mode is compared first
+            if entry.mode != file_mode(&metadata) {
+                changes.push(('M', path.clone()));
+                continue;
+            }
+            // Unchanged size and mtime means the content can be trusted without re-hashing
+            if entry.matches_stat(&metadata) {
+                continue;
+            }
+            let file_content = read_working_blob(path)?.unwrap_or_default();
+            if hash_object(&file_content, "blob") != entry.hash {
+                changes.push(('M', path.clone()));
+            } else {
+                *entry = IndexEntry::new(entry.hash.clone(), &metadata);
+                index_refreshed = true;
+            }
+        } else {
+            changes.push(('D', path.clone()));
+        }
+    }
+    changes.sort_by(|a, b| a.1.cmp(&b.1));
+    Ok((changes, index_refreshed))
+}
+
+// Stages every modification and deletion of an already tracked file, as `commit -a` does
+fn stage_tracked_changes() -> FitResult<()> {
+    let mut staging_area = read_staging_area()?;
+    let mut index = read_index()?;
+    let (changes, _) = unstaged_changes(&mut index)?;
+    let folded = fold_tracked_paths(&index)?;
+    for (status, path) in changes {
+        if status == 'M' {
+            add_file(Path::new(&path), &mut staging_area, &mut index, &folded)?;
+        } else {
+            index.remove(&path);
+            staging_area.delete(path);
+        }
+    }
+    write_staging_area(&staging_area)?;
+    write_index(&index)?;
+    Ok(())
+}
+
+// The checked out branch, or None when HEAD is detached at a commit
+fn get_current_branch() -> FitResult<Option<String>> {
+    match read_head()? {
+        HeadState::Symbolic(branch) => Ok(Some(branch)),
+        HeadState::Detached(_) => Ok(None),
+    }
+}
+
+fn reset_command(args: ResetArgs) -> FitResult<()> {
+    let commit = resolve_ref(args.commit.as_deref().unwrap_or("HEAD"))?;
+    if args.patch {
+        reset_patch(&commit, &args.paths)
+    } else if args.keep {
+        reset_keep(&commit)
+    } else if args.paths.is_empty() {
+        if args.dry_run {
+            let (overwritten, deleted, _) = reset_plan(&commit)?;
+            for path in overwritten {
+                println!("Would overwrite {}", path);
+            }
+            for path in deleted {
+                println!("Would delete {}", path);
+            }
+            return Ok(());
+        }
+        let confirm = read_config_value("core.resetConfirm")?.as_deref() == Some("true");
+        let locally_modified = if confirm && !args.force {
+            reset_plan(&commit)?.2
+        } else {
+            Vec::new()
+        };
+        if !locally_modified.is_empty() {
+            print!(
+                "Reset would discard local changes to {}. Continue? [y/N] ",
+                locally_modified.join(", ")
+            );
+            io::stdout().flush()?;
+            let mut answer = String::new();
+            io::stdin().lock().read_line(&mut answer)?;
+            if !answer.trim().eq_ignore_ascii_case("y") {
+                return Err(FitError::Refused("reset aborted".to_string()));
+            }
+        }
+        reset_workflow(&commit)
+    } else {
+        reset_paths(&commit, &args.paths)
+    }
+}
+
+// Offers each hunk staged since `commit` in the given paths (all staged files by default) for
+// unstaging, then stages a blob of each file holding only the hunks kept
+fn reset_patch(commit: &str, paths: &[String]) -> FitResult<()> {
+    let commit_files = get_tree_files(&get_commit_tree(commit)?)?;
+    let head_files = get_tree_files(&get_commit_tree(&get_current_commit()?)?)?;
+    let mut staging_area = read_staging_area()?;
+    let mut index = read_index()?;
+
+    let in_scope = |file: &str| {
+        paths.is_empty()
+            || paths.iter().any(|path| {
+                let path = path.trim_end_matches('/');
+                file == path || file.starts_with(&format!("{}/", path))
+            })
+    };
+    let mut files: Vec<String> = index
+        .iter()
+        .filter(|(file, entry)| in_scope(file) && commit_files.get(*file) != Some(&entry.hash))
+        .map(|(file, _)| file.clone())
+        .collect();
+    files.sort();
+    if files.is_empty() {
+        info!("No staged changes");
+        return Ok(());
+    }
+
+    for file in files {
+        let staged = read_object_typed(&index[&file].hash, "blob")?;
+        let committed = match commit_files.get(&file) {
+            Some(hash) => read_object_typed(hash, "blob")?,
+            None => Vec::new(),
+        };
+        let (Some(committed), Some(staged)) = (patchable_text(committed), patchable_text(staged))
+        else {
+            info!("Skipping binary or non-UTF-8 file {}", file);
+            continue;
+        };
+        let changes = diff_lines(&committed, &staged, &DiffOptions::default());
+        let (hunks, hunk_of) = split_hunks(&changes);
+        if hunks.is_empty() {
+            continue;
+        }
+
+        println!("diff --fit a/{} b/{}", file, file);
+        let unstaged = choose_hunks(&hunks, "Unstage")?;
+        let kept: Vec<bool> = unstaged.iter().map(|unstaged| !unstaged).collect();
+        if !kept.contains(&false) {
+            continue;
+        }
+        if !commit_files.contains_key(&file) && !kept.contains(&true) {
+            // Unstaging all of a new file's content stops tracking it
+            index.remove(&file);
+            restage_path(&mut staging_area, &head_files, &file, None);
+        } else {
+            let remaining = apply_hunks(&changes, &hunk_of, &kept);
+            let hash = write_object(remaining.as_bytes(), "blob")?;
+            let mode = index[&file].mode;
+            // The working file no longer matches what is staged, so no stat data is recorded
+            index.insert(file.clone(), IndexEntry::unstatted(&hash).with_mode(mode));
+            restage_path(&mut staging_area, &head_files, &file, Some(&hash));
+        }
+        let count = unstaged.iter().filter(|unstaged| **unstaged).count();
+        info!("Unstaged {} of {} hunks of {}", count, hunks.len(), file);
+    }
+
+    write_staging_area(&staging_area)?;
+    write_index(&index)?;
+    Ok(())
+}
+
+// Replaces the index entries for `paths` with their versions in `commit`, leaving the
+// working tree and every other entry alone
+fn reset_paths(commit: &str, paths: &[String]) -> FitResult<()> {
+    let commit_files = get_tree_files(&get_commit_tree(commit)?)?;
+    let commit_modes = get_tree_modes(&get_commit_tree(commit)?)?;
+    let head_files = get_tree_files(&get_commit_tree(&get_current_commit()?)?)?;
+    let mut staging_area = read_staging_area()?;
+    let mut index = read_index()?;
+
+    for path in paths {
+        let path = path.trim_end_matches('/');
+        let dir_prefix = format!("{}/", path);
+        let in_scope = |file: &String| file == path || file.starts_with(&dir_prefix);
+        let mut matched: Vec<String> = commit_files
+            .keys()
+            .chain(index.keys())
+            .filter(|file| in_scope(file))
+            .cloned()
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+        if matched.is_empty() {
+            return Err(FitError::PathNotFound(path.to_string()));
+        }
+        matched.sort();
+
+        for file in matched {
+            match commit_files.get(&file) {
+                Some(hash) => {
+                    let mode = commit_modes.get(&file).copied().unwrap_or(MODE_FILE);
+                    let entry = index.get(&file).map(|entry| (&entry.hash, entry.mode));
+                    if entry != Some((hash, mode)) {
+                        index.insert(file.clone(), IndexEntry::unstatted(hash).with_mode(mode));
+                    }
+                }
+                None => {
+                    index.remove(&file);
+                }
+            }
+            restage_path(
+                &mut staging_area,
+                &head_files,
+                &file,
+                commit_files.get(&file),
+            );
+            info!("Unstaged {}", file);
+        }
+    }
+
+    write_staging_area(&staging_area)?;
+    write_index(&index)?;
+    Ok(())
+}
+
+// Moves the branch to `commit`, rewriting only the files that differ between it and HEAD
+// and carrying every other local change along. Nothing is touched if one of those files
+// has staged or unstaged changes, or is an untracked file the commit would overwrite
+fn reset_keep(commit: &str) -> FitResult<()> {
+    if read_object(commit)?.is_none() {
+        return Err(FitError::ObjectNotFound(commit.to_string()));
+    }
+    let head_files = get_tree_files(&get_commit_tree(&get_current_commit()?)?)?;
+    let target_files = get_tree_files(&get_commit_tree(commit)?)?;
+    let target_modes = get_tree_modes(&get_commit_tree(commit)?)?;
+    let changes = changed_files(&head_files, &target_files);
+
+    let staging_area = read_staging_area()?;
+    let mut index = read_index()?;
+    let (unstaged, _) = unstaged_changes(&mut index)?;
+    let conflicts: Vec<&String> = changes
+        .iter()
+        .map(|(_, path)| path)
+        .filter(|path| {
+            let staged = staging_area.added.contains_key(*path)
+                || staging_area.modified.contains_key(*path)
+                || staging_area.deleted.contains(*path)
+                || index.get(*path).map(|entry| &entry.hash) != head_files.get(*path);
+            let untracked = !index.contains_key(*path) && Path::new(path).exists();
+            staged || untracked || unstaged.iter().any(|(_, changed)| changed == *path)
+        })
+        .collect();
+    if !conflicts.is_empty() {
+        for path in &conflicts {
+            println!("Local changes to {} would be overwritten", path);
+        }
+        return Err(FitError::Refused(format!(
+            "cannot reset --keep with local changes to {}",
+            conflicts
+                .iter()
+                .map(|path| path.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        )));
+    }
+
+    // As in reset_workflow, every blob is loaded and every destination checked up front, and
+    // the branch only moves once the working tree and index match the target
+    let removed_files: HashSet<&String> = changes
+        .iter()
+        .filter(|(status, _)| *status == 'D')
+        .map(|(_, path)| path)
+        .collect();
+    let mut new_contents = Vec::new();
+    for (status, path) in &changes {
+        if *status != 'D' {
+            let hash = &target_files[path];
+            let (_, content) =
+                read_object(hash)?.ok_or_else(|| FitError::ObjectNotFound(hash.clone()))?;
+            check_path_writable(Path::new(path), &removed_files)?;
+            new_contents.push((path, hash, content));
+        }
+    }
+    for (status, path) in &changes {
+        if *status == 'D' {
+            if Path::new(path).exists() {
+                fs::remove_file(path)?;
+            }
+            remove_empty_parents(Path::new(path));
+            index.remove(path);
+        }
+    }
+    for (path, hash, content) in new_contents {
+        if let Some(parent) = Path::new(path).parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mode = target_modes.get(path).copied().unwrap_or(MODE_FILE);
+        let metadata = write_working_file(path, content, mode)?;
+        index.insert(path.clone(), IndexEntry::new(hash.clone(), &metadata));
+    }
+    write_index(&index)?;
+    update_current_branch(commit, &format!("reset: moving to {}", commit))?;
+
+    info!("Reset to commit {}, keeping local changes", commit);
+    Ok(())
+}
+
+// What a hard reset to `commit` would do to the working tree: the files whose content it would
+// replace, the tracked files it would delete, and which of either have changes since HEAD that
+// would be lost, each sorted by path
+fn reset_plan(commit: &str) -> FitResult<(Vec<String>, Vec<String>, Vec<String>)> {
+    let target_files = get_tree_files(&get_commit_tree(commit)?)?;
+    let head_commit = current_commit_or_empty()?;
+    let head_files = if head_commit.is_empty() {
+        HashMap::new()
+    } else {
+        get_tree_files(&get_commit_tree(&head_commit)?)?
+    };
+    let working_hash = |path: &str| -> FitResult<Option<String>> {
+        Ok(read_working_blob(path)?.map(|content| hash_object(&content, "blob")))
+    };
+
+    let mut overwritten = Vec::new();
+    let mut deleted = Vec::new();
+    let mut locally_modified = Vec::new();
+    for (path, hash) in &target_files {
+        if let Some(working) = working_hash(path)? {
+            if working != *hash {
+                if head_files.get(path) != Some(&working) {
+                    locally_modified.push(path.clone());
+                }
+                overwritten.push(path.clone());
+            }
+        }
+    }
+    for path in read_index()?.keys() {
+        if target_files.contains_key(path) {
+            continue;
+        }
+        if let Some(working) = working_hash(path)? {
+            if head_files.get(path) != Some(&working) {
+                locally_modified.push(path.clone());
+            }
+            deleted.push(path.clone());
+        }
+    }
+    overwritten.sort();
+    deleted.sort();
+    locally_modified.sort();
+    Ok((overwritten, deleted, locally_modified))
+}
+
+fn reset_workflow(commit_hash: &str) -> FitResult<()> {
+    reset_working_tree(commit_hash)?;
+    update_current_branch(commit_hash, &format!("reset: moving to {}", commit_hash))?;
+    info!("Reset to commit {}", commit_hash);
+    Ok(())
+}
+
+// Makes the working tree and index match `commit_hash` without moving any ref, leaving that
+// to the caller once the files are in place
+fn reset_working_tree(commit_hash: &str) -> FitResult<()> {
+    read_object_typed(commit_hash, "commit")?;
+    let target_files = get_tree_files(&get_commit_tree(commit_hash)?)?;
+    let target_modes = get_tree_modes(&get_commit_tree(commit_hash)?)?;
+
+    let current_index = read_index()?;
+    let removed_files: HashSet<_> = current_index
+        .keys()
+        .filter(|file| !target_files.contains_key(*file))
+        .collect();
+
+    // Load every blob and validate every destination before touching the repository,
+    // so a missing object or an unwritable path leaves the old state intact
+    let mut new_contents = Vec::new();
+    for (file_path, file_hash) in &target_files {
+        let (_, blob_content) = read_object(file_hash)?
+            .ok_or_else(|| FitError::ObjectNotFound(file_hash.to_string()))?;
+        check_path_writable(Path::new(file_path), &removed_files)?;
+        new_contents.push((file_path, file_hash, blob_content));
+    }
+
+    if read_config_value("core.resetBackup")?.as_deref() == Some("true") {
+        // Besides deleted files, keep any file whose content the reset is about to replace
+        let mut doomed: Vec<&String> = removed_files.iter().copied().collect();
+        for (file_path, _, blob_content) in &new_contents {
+            if read_working_blob(file_path)?.is_some_and(|working| working != *blob_content) {
+                doomed.push(file_path);
+            }
+        }
+        backup_to_trash(&doomed)?;
+    }
+
+    let mode_of = |file_path: &str| target_modes.get(file_path).copied().unwrap_or(MODE_FILE);
+    let mut new_index: HashMap<String, IndexEntry> = target_files
+        .iter()
+        .map(|(file_path, file_hash)| {
+            let entry = IndexEntry::unstatted(file_hash).with_mode(mode_of(file_path));
+            (file_path.clone(), entry)
+        })
+        .collect();
+    write_index(&new_index)?;
+
+    for file in removed_files {
+        if Path::new(file).exists() {
+            fs::remove_file(file)?;
+            info!("Removed file: {}", file);
+        }
+        remove_empty_parents(Path::new(file));
+    }
+
+    for (file_path, file_hash, blob_content) in new_contents {
+        if let Some(parent) = Path::new(file_path).parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let metadata = write_working_file(file_path, blob_content, mode_of(file_path))?;
+        verbose!("Wrote file: {}", file_path);
+        new_index.insert(
+            file_path.to_string(),
+            IndexEntry::new(file_hash.to_string(), &metadata),
+        );
+    }
+
+    // Record stat data for the files just written
+    write_index(&new_index)?;
+    if fit_path("STAGING").exists() {
+        fs::remove_file(fit_path("STAGING"))?;
+    }
+    Ok(())
+}
+
+// Copies the files among `paths` that exist into a new .fit/trash/<timestamp> directory,
+// keeping their paths, so `fit trash restore` can bring them back
+fn backup_to_trash(paths: &[&String]) -> FitResult<()> {
+    let existing: Vec<&&String> = paths
+        .iter()
+        .filter(|path| Path::new(path).is_file())
+        .collect();
+    if existing.is_empty() {
+        return Ok(());
+    }
+    let timestamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default();
+    // Two resets within a second get separate entries
+    let mut name = timestamp.to_string();
+    let mut suffix = 1;
+    while fit_path("trash").join(&name).exists() {
+        name = format!("{}-{}", timestamp, suffix);
+        suffix += 1;
+    }
+    let trash_dir = fit_path("trash").join(&name);
+    for path in existing {
+        let backup_path = trash_dir.join(path.as_str());
+        if let Some(parent) = backup_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::copy(path.as_str(), &backup_path)?;
+    }
+    info!(
+        "Saved a copy of the files being replaced in trash entry {}",
+        name
+    );
+    Ok(())
+}
+
+fn trash_workflow(args: TrashArgs) -> FitResult<()> {
+    match args.command {
+        TrashSubCommand::List => {
+            for name in trash_entries()? {
+                println!("{}", name);
+                for path in trash_files(&name)? {
+                    println!("    {}", path);
+                }
+            }
+        }
+        TrashSubCommand::Restore { entry, paths } => restore_from_trash(&entry, &paths)?,
+    }
+    Ok(())
+}
+
+// Trash entries named after the time of the reset that saved them, oldest first
+fn trash_entries() -> FitResult<Vec<String>> {
+    let trash_dir = fit_path("trash");
+    if !trash_dir.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut entries = Vec::new();
+    for entry in fs::read_dir(trash_dir)? {
+        let entry = entry?;
+        if entry.path().is_dir() {
+            entries.push(entry.file_name().to_string_lossy().to_string());
+        }
+    }
+    entries.sort_by_key(|name| {
+        let (timestamp, suffix) = name.split_once('-').unwrap_or((name, "0"));
+        (
+            timestamp.parse::<u64>().unwrap_or_default(),
+            suffix.parse::<u64>().unwrap_or_default(),
+        )
+    });
+    Ok(entries)
+}
+
+// The repository paths saved in a trash entry, '/'-separated and sorted
+fn trash_files(entry: &str) -> FitResult<Vec<String>> {
+    let entry_dir = fit_path("trash").join(entry);
+    if !entry_dir.is_dir() {
+        return Err(FitError::InvalidArgument(format!(
+            "no trash entry named '{}'",
+            entry
+        )));
+    }
+    let mut files = Vec::new();
+    let mut pending = vec![String::new()];
+    while let Some(dir) = pending.pop() {
+        for dir_entry in fs::read_dir(entry_dir.join(&dir))? {
+            let dir_entry = dir_entry?;
+            let path = format!("{}{}", dir, dir_entry.file_name().to_string_lossy());
+            if dir_entry.path().is_dir() {
+                pending.push(format!("{}/", path));
+            } else {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+// Copies the saved files (or just `paths` among them) back into the working tree, refusing
+// to overwrite a file that has since been recreated with different content
+fn restore_from_trash(entry: &str, paths: &[String]) -> FitResult<()> {
+    let files = trash_files(entry)?;
+    for path in paths {
+        if !files.contains(path) {
+            return Err(FitError::PathNotFound(path.clone()));
+        }
+    }
+    let selected: Vec<&String> = files
+        .iter()
+        .filter(|file| paths.is_empty() || paths.contains(file))
+        .collect();
+    let entry_dir = fit_path("trash").join(entry);
+    for file in &selected {
+        let saved = fs::read(entry_dir.join(file.as_str()))?;
+        if fs::read(file.as_str()).is_ok_and(|current| current != saved) {
+            return Err(FitError::Refused(format!(
+                "'{}' exists with different content, move it aside first",
+                file
+            )));
+        }
+    }
+    for file in selected {
+        if let Some(parent) = Path::new(file.as_str()).parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::copy(entry_dir.join(file.as_str()), file.as_str())?;
+        info!("Restored {}", file);
+    }
+    Ok(())
+}
+
+// Fails if the file can't be created because it or one of its parents is occupied,
+// ignoring directories that will be emptied by removing `removed_files`
+fn check_path_writable(path: &Path, removed_files: &HashSet<&String>) -> FitResult<()> {
+    if path.is_dir() && !dir_holds_only(path, removed_files)? {
+        return Err(FitError::Refused(format!(
+            "cannot write '{}': a directory is in the way",
+            path.display()
+        )));
+    }
+    if let Ok(metadata) = fs::symlink_metadata(path) {
+        if metadata.permissions().readonly() {
+            return Err(FitError::Refused(format!(
+                "cannot write '{}': file is read-only",
+                path.display()
+            )));
+        }
+    }
+    for ancestor in path.ancestors().skip(1) {
+        let tracked_removal = ancestor
+            .to_str()
+            .is_some_and(|ancestor| removed_files.contains(&ancestor.to_string()));
+        if ancestor.is_file() && !tracked_removal {
+            return Err(FitError::Refused(format!(
+                "cannot write '{}': '{}' is a file",
+                path.display(),
+                ancestor.display()
+            )));
+        }
+    }
+    Ok(())
+}
+
+fn branch_workflow(args: BranchArgs) -> FitResult<()> {
+    match args.command {
+        BranchSubcommand::List => list_branches()?,
+        BranchSubcommand::Create { name } => create_branch(&name)?,
+        BranchSubcommand::Delete { names, force } => delete_branches(&names, force)?,
+        BranchSubcommand::Checkout { name } => checkout_branch(&name)?,
+        BranchSubcommand::CheckoutNew { name } => checkout_new_branch(&name)?,
+    }
+    Ok(())
+}
+
+fn list_branches() -> FitResult<()> {
+    let branches_dir = fit_path("refs/heads");
+    for entry in fs::read_dir(branches_dir)? {
+        let entry = entry?;
+        println!("{}", entry.file_name().to_string_lossy());
+    }
+    Ok(())
+}
+
+struct Worktree {
+    path: std::path::PathBuf,
+    admin_dir: std::path::PathBuf,
+}
+
+// The main working tree followed by every linked one, each with the directory holding its
+// HEAD and index; a bare repository has no main working tree
+fn worktrees() -> FitResult<Vec<Worktree>> {
+    let common_dir = fit_path("").canonicalize()?;
+    let mut worktrees = Vec::new();
+    if !REPOSITORY.get().is_some_and(|repository| repository.bare) {
+        worktrees.push(Worktree {
+            path: common_dir
+                .parent()
+                .map(Path::to_path_buf)
+                .unwrap_or_default(),
+            admin_dir: common_dir.clone(),
+        });
+    }
+    let linked_dir = common_dir.join("worktrees");
+    if linked_dir.is_dir() {
+        let mut linked: Vec<_> = fs::read_dir(linked_dir)?.collect::<Result<_, _>>()?;
+        linked.sort_by_key(|entry| entry.file_name());
+        for entry in linked {
+            let admin_dir = entry.path();
+            let path = fs::read_to_string(admin_dir.join("worktreedir"))?;
+            worktrees.push(Worktree {
+                path: path.trim_end().into(),
+                admin_dir,
+            });
+        }
+    }
+    Ok(worktrees)
+}
+
+fn worktree_workflow(args: WorktreeArgs) -> FitResult<()> {
+    match args.command {
+        WorktreeSubCommand::Add { path, branch } => add_worktree(&path, &branch),
+        WorktreeSubCommand::List => {
+            for worktree in worktrees()? {
+                let head = fs::read_to_string(worktree.admin_dir.join("HEAD"))?;
+                let head = head.trim();
+                let checked_out = match head.strip_prefix("ref: refs/heads/") {
+                    Some(branch) => format!("[{}]", branch),
+                    None => format!("(detached {})", &head[..7.min(head.len())]),
+                };
+                println!("{} {}", worktree.path.display(), checked_out);
+            }
+            Ok(())
+        }
+    }
+}
+
+// Sets up .fit/worktrees/<name> holding the new tree's HEAD and index, plus a `commondir`
+// file pointing back at the shared repository, links the new directory to it with a .fit
+// file, and writes out the branch's files there
+fn add_worktree(path: &Path, branch: &str) -> FitResult<()> {
+    check_ref_name(&format!("refs/heads/{}", branch))?;
+    if path.exists() && fs::read_dir(path)?.next().is_some() {
+        return Err(FitError::Refused(format!(
+            "'{}' already exists and is not empty",
+            path.display()
+        )));
+    }
+    let head = format!("ref: refs/heads/{}", branch);
+    for worktree in worktrees()? {
+        if fs::read_to_string(worktree.admin_dir.join("HEAD"))?.trim() == head {
+            return Err(FitError::Refused(format!(
+                "branch '{}' is already checked out at '{}'",
+                branch,
+                worktree.path.display()
+            )));
+        }
+    }
+    let branch_path = fit_path("refs/heads").join(branch);
+    let commit = if branch_path.exists() {
+        fs::read_to_string(&branch_path)?.trim().to_string()
+    } else {
+        let commit = get_current_commit()?;
+        write_ref(&branch_path, &commit)?;
+        info!("Created branch '{}'", branch);
+        commit
+    };
+
+    fs::create_dir_all(path)?;
+    let path = path.canonicalize()?;
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| "worktree".to_string());
+    let linked_dir = fit_path("worktrees");
+    let mut admin_dir = linked_dir.join(&name);
+    let mut suffix = 1;
+    while admin_dir.exists() {
+        admin_dir = linked_dir.join(format!("{}{}", name, suffix));
+        suffix += 1;
+    }
+    fs::create_dir_all(&admin_dir)?;
+    let admin_dir = admin_dir.canonicalize()?;
+    let common_dir = fit_path("").canonicalize()?;
+    fs::write(
+        admin_dir.join("commondir"),
+        format!("{}\n", common_dir.display()),
+    )?;
+    fs::write(
+        admin_dir.join("worktreedir"),
+        format!("{}\n", path.display()),
+    )?;
+    write_ref(&admin_dir.join("HEAD"), &format!("{}\n", head))?;
+    fs::write(
+        path.join(FIT_DIR_NAME),
+        format!("fitdir: {}\n", admin_dir.display()),
+    )?;
+
+    let tree = get_commit_tree(&commit)?;
+    let modes = get_tree_modes(&tree)?;
+    let mut index = HashMap::new();
+    for (file, hash) in get_tree_files(&tree)? {
+        let (_, content) =
+            read_object(&hash)?.ok_or_else(|| FitError::ObjectNotFound(hash.clone()))?;
+        let target = path.join(&file);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mode = modes.get(&file).copied().unwrap_or(MODE_FILE);
+        let metadata = write_working_file(&target.to_string_lossy(), content, mode)?;
+        index.insert(file, IndexEntry::new(hash, &metadata));
+    }
+    write_index_to(&admin_dir.join("index"), &index)?;
+    info!(
+        "Checked out '{}' into a new working tree at {}",
+        branch,
+        path.display()
+    );
+    Ok(())
+}
+
+fn create_branch(name: &str) -> FitResult<()> {
+    if name == "master" {
+        return Err(FitError::BranchExists("master".to_string()));
+    }
+    let current_commit = get_current_commit()?;
+    let branch_path = fit_path("refs/heads").join(name);
+    if branch_path.exists() {
+        return Err(FitError::BranchExists(name.to_string()));
+    }
+    write_ref(&branch_path, &current_commit)?;
+    info!("Created branch '{}'", name);
+    Ok(())
+}
+
+fn delete_branches(names: &[String], force: bool) -> FitResult<()> {
+    let mut failures = Vec::new();
+    for name in names {
+        if let Err(err) = delete_branch(name, force) {
+            failures.push(err);
+        }
+    }
+    // A lone failure is returned as it is, keeping its exit status; several are reported
+    // together in one error so main prints each of them once
+    if failures.len() <= 1 {
+        return failures.pop().map_or(Ok(()), Err);
+    }
+    let reasons: Vec<String> = failures.iter().map(|err| err.to_string()).collect();
+    Err(FitError::Refused(format!(
+        "failed to delete {} of {} branches:\n  {}",
+        failures.len(),
+        names.len(),
+        reasons.join("\n  ")
+    )))
+}
+
+fn delete_branch(name: &str, force: bool) -> FitResult<()> {
+    if name == "master" {
+        return Err(FitError::Refused(
+            "Cannot delete the master branch".to_string(),
+        ));
+    }
+    if get_current_branch()?.as_deref() == Some(name) {
+        return Err(FitError::Refused(
+            "Cannot delete branch currently in use, please switch to master or different branch"
+                .to_string(),
+        ));
+    }
+    let branch_path = fit_path("refs/heads").join(name);
+    if !branch_path.exists() {
+        return Err(FitError::BranchNotFound(name.to_string()));
+    }
+    if !force {
+        let branch_commit = get_branch_commit(name)?;
+        let merged_commits = get_commit_history(&get_current_commit()?)?;
+        if !merged_commits.contains(&branch_commit) {
+            return Err(FitError::Refused(format!(
+                "Branch '{}' is not fully merged, use 'fit branch delete -D {}' to delete it anyway",
+                name, name
+            )));
+        }
+    }
+    fs::remove_file(branch_path)?;
+    info!("Deleted branch '{}'", name);
+    Ok(())
+}
+
+fn checkout_branch(name: &str) -> FitResult<()> {
+    let branch_path = fit_path("refs/heads").join(name);
+    if !branch_path.exists() {
+        // Not a branch, so fall back to checking out a raw commit as a detached HEAD
+        let object = if is_valid_hash(name) {
+            read_object(name)?
+        } else {
+            None
+        };
+        if let Some((object_type, _)) = object {
+            if object_type == "commit" {
+                reset_working_tree(name)?;
+                write_head(&HeadState::Detached(name.to_string()))?;
+                info!("HEAD is now detached at {}", name);
+                return Ok(());
+            }
+        }
+        return Err(FitError::BranchNotFound(name.to_string()));
+    }
+    // HEAD only moves once the tree has been switched, so a refused checkout leaves it alone
+    let commit_hash = fs::read_to_string(branch_path)?.trim().to_string();
+    reset_working_tree(&commit_hash)?;
+    write_head(&HeadState::Symbolic(name.to_string()))?;
+    info!("Switched to branch '{}'", name);
+    Ok(())
+}
+
+// BISECT_STATE holds "orig-head <branch or commit>" followed by one "bad <hash>" or
+// "good <hash>" line per mark, in the order they were given
+fn bisect_workflow(args: BisectArgs) -> FitResult<()> {
+    let state_path = fit_path("BISECT_STATE");
+    if let BisectSubCommand::Start = args.command {
+        if state_path.exists() {
+            return Err(FitError::Refused(
+                "a bisect is already in progress, run `fit bisect reset` first".to_string(),
+            ));
+        }
+        ensure_clean_work_tree("bisect")?;
+        let orig_head = match read_head()? {
+            HeadState::Symbolic(branch) => branch,
+            HeadState::Detached(hash) => hash,
+        };
+        fs::write(&state_path, format!("orig-head {}\n", orig_head))?;
+        info!("Bisect started, mark a bad and a good commit to begin");
+        return Ok(());
+    }
+
+    let state = fs::read_to_string(&state_path).map_err(|_| {
+        FitError::Refused("no bisect in progress, run `fit bisect start` first".to_string())
+    })?;
+    let (mark, commit) = match args.command {
+        BisectSubCommand::Bad { commit } => ("bad", commit),
+        BisectSubCommand::Good { commit } => ("good", commit),
+        BisectSubCommand::Reset => {
+            let orig_head = state
+                .lines()
+                .find_map(|line| line.strip_prefix("orig-head "))
+                .unwrap_or("master")
+                .to_string();
+            checkout_branch(&orig_head)?;
+            fs::remove_file(&state_path)?;
+            return Ok(());
+        }
+        BisectSubCommand::Start => unreachable!("handled above"),
+    };
+    let commit = resolve_ref(commit.as_deref().unwrap_or("HEAD"))?;
+    fs::write(&state_path, format!("{}{} {}\n", state, mark, commit))?;
+
+    // The latest bad mark bounds the range; every good mark rules out its whole history
+    let state = fs::read_to_string(&state_path)?;
+    let bad = state
+        .lines()
+        .filter_map(|line| line.strip_prefix("bad "))
+        .next_back();
+    let goods: Vec<&str> = state
+        .lines()
+        .filter_map(|line| line.strip_prefix("good "))
+        .collect();
+    let (Some(bad), false) = (bad, goods.is_empty()) else {
+        info!(
+            "Marked {} as {}, waiting for both a good and a bad commit",
+            commit, mark
+        );
+        return Ok(());
+    };
+    let mut cleared = HashSet::new();
+    for good in &goods {
+        cleared.extend(get_commit_history(good)?);
+    }
+    if cleared.contains(bad) {
+        return Err(FitError::Refused(format!(
+            "bad commit {} is an ancestor of a good commit, check your marks",
+            bad
+        )));
+    }
+    let suspects: Vec<String> = get_commit_history(bad)?
+        .into_iter()
+        .filter(|commit| !cleared.contains(commit))
+        .collect();
+
+    if suspects.len() == 1 {
+        println!("{} is the first bad commit", bad);
+        let (_, content) =
+            read_object(bad)?.ok_or_else(|| FitError::ObjectNotFound(bad.to_string()))?;
+        println!("{}", String::from_utf8_lossy(&content));
+        return Ok(());
+    }
+    // Suspects run newest first from the bad commit, so testing the middle one halves them
+    let next = &suspects[suspects.len() / 2];
+    let remaining = suspects.len() - 1;
+    info!(
+        "Bisecting: {} revisions left to test after this (roughly {} steps)",
+        remaining / 2,
+        usize::BITS - remaining.leading_zeros() - 1
+    );
+    checkout_branch(next)
+}
+
+fn checkout_new_branch(name: &str) -> FitResult<()> {
+    create_branch(name)?;
+    checkout_branch(name)?;
+    Ok(())
+}
+fn checkout_workflow(args: CheckoutArgs) -> FitResult<()> {
+    if args.ours || args.theirs {
+        return checkout_conflict_side(args.theirs, &args.paths);
+    }
+    match (args.branch, args.paths.is_empty()) {
+        (Some(branch), true) => checkout_branch(&branch),
+        (None, false) => checkout_paths(&get_current_commit()?, &args.paths),
+        (Some(commit), false) => checkout_paths(&resolve_ref(&commit)?, &args.paths),
+        (None, true) => Err(FitError::InvalidArgument(
+            "specify a branch or '-- <path>' to check out".to_string(),
+        )),
+    }
+}
+
+// Resolves conflicted paths by taking one side whole: ours is HEAD, theirs the merged commit
+// or, during a rebase, the commit being replayed. The chosen version is staged, and the path
+// is dropped from MERGE_STATE's conflict list
+fn checkout_conflict_side(theirs: bool, paths: &[String]) -> FitResult<()> {
+    let merge_state_path = fit_path("MERGE_STATE");
+    let merge_state = fs::read_to_string(&merge_state_path)
+        .map_err(|_| FitError::Refused("no merge or rebase in progress".to_string()))?;
+    let head = get_current_commit()?;
+    let side = if theirs {
+        merge_state
+            .lines()
+            .find_map(|line| {
+                line.strip_prefix("merge-head ")
+                    .or_else(|| line.strip_prefix("stopped-at "))
+            })
+            .ok_or_else(|| FitError::Refused("MERGE_STATE names no merged commit".to_string()))?
+            .to_string()
+    } else {
+        head.clone()
+    };
+    let conflicts: Vec<&str> = merge_state
+        .lines()
+        .filter_map(|line| line.strip_prefix("conflict "))
+        .collect();
+    if let Some(path) = paths
+        .iter()
+        .find(|path| !conflicts.contains(&path.as_str()))
+    {
+        return Err(FitError::Refused(format!("'{}' is not conflicted", path)));
+    }
+
+    let side_files = get_tree_files(&get_commit_tree(&side)?)?;
+    let side_modes = get_tree_modes(&get_commit_tree(&side)?)?;
+    let head_files = get_tree_files(&get_commit_tree(&head)?)?;
+    let mut staging_area = read_staging_area()?;
+    let mut index = read_index()?;
+    for path in paths {
+        // A side that deleted the file resolves the conflict as a deletion
+        match side_files.get(path) {
+            Some(hash) => {
+                let (_, blob_content) =
+                    read_object(hash)?.ok_or_else(|| FitError::ObjectNotFound(hash.clone()))?;
+                let mode = side_modes.get(path).copied().unwrap_or(MODE_FILE);
+                let metadata = write_working_file(path, blob_content, mode)?;
+                index.insert(path.clone(), IndexEntry::new(hash.clone(), &metadata));
+            }
+            None => {
+                if Path::new(path).symlink_metadata().is_ok() {
+                    fs::remove_file(path)?;
+                }
+                index.remove(path);
+            }
         }
+        restage_path(&mut staging_area, &head_files, path, side_files.get(path));
+        info!(
+            "Resolved {} with {} version",
+            path,
+            if theirs { "their" } else { "our" }
+        );
     }
+    write_staging_area(&staging_area)?;
+    write_index(&index)?;
+
+    let remaining: String = merge_state
+        .lines()
+        .filter(|line| {
+            line.strip_prefix("conflict ")
+                .is_none_or(|path| !paths.iter().any(|resolved| resolved == path))
+        })
+        .map(|line| format!("{}\n", line))
+        .collect();
+    fs::write(merge_state_path, remaining)?;
     Ok(())
 }
 
-fn read_staging_area() -> io::Result<StagingArea> {
-    let staging_path = ".fit/STAGING";
-    if !Path::new(staging_path).exists() {
-        return Ok(StagingArea::new());
-    }
+// Overwrites the given paths (files or directories) with their versions from `commit`,
+// recording them in the index and staging them relative to HEAD
+fn checkout_paths(commit: &str, paths: &[String]) -> FitResult<()> {
+    let commit_files = get_tree_files(&get_commit_tree(commit)?)?;
+    let commit_modes = get_tree_modes(&get_commit_tree(commit)?)?;
+    let head_files = get_tree_files(&get_commit_tree(&get_current_commit()?)?)?;
+    let mut staging_area = read_staging_area()?;
+    let mut index = read_index()?;
 
-    let staging_content = fs::read_to_string(staging_path)?;
-    let mut staging_area = StagingArea::new();
+    for path in paths {
+        let path = path.trim_end_matches('/');
+        let dir_prefix = format!("{}/", path);
+        let mut matched: Vec<_> = commit_files
+            .iter()
+            .filter(|(file, _)| *file == path || file.starts_with(&dir_prefix))
+            .collect();
+        if matched.is_empty() {
+            return Err(FitError::PathNotFound(path.to_string()));
+        }
+        matched.sort();
 
-    for line in staging_content.lines() {
-        let parts: Vec<&str> = line.split_whitespace().collect();
-        if parts.len() == 3 {
-            match parts[0] {
-                "A" => staging_area.add(parts[2].to_string(), parts[1].to_string()),
-                "M" => staging_area.modify(parts[2].to_string(), parts[1].to_string()),
-                "D" => staging_area.delete(parts[2].to_string()),
-                _ => {}
+        for (file, hash) in matched {
+            let blob_content = read_object_typed(hash, "blob")?;
+            check_path_writable(Path::new(file), &HashSet::new())?;
+            if let Some(parent) = Path::new(file).parent() {
+                fs::create_dir_all(parent)?;
             }
+            let mode = commit_modes.get(file).copied().unwrap_or(MODE_FILE);
+            let metadata = write_working_file(file, blob_content, mode)?;
+            index.insert(file.clone(), IndexEntry::new(hash.clone(), &metadata));
+
+            restage_path(&mut staging_area, &head_files, file, Some(hash));
+            info!("Updated {}", file);
         }
     }
 
-    Ok(staging_area)
+    write_staging_area(&staging_area)?;
+    write_index(&index)?;
+    Ok(())
 }
 
-fn write_staging_area(staging_area: &StagingArea) -> io::Result<()> {
-    let staging_path = ".fit/STAGING";
-    let mut content = String::new();
-
-    for (path, hash) in &staging_area.added {
-        content.push_str(&format!("A {} {}\n", hash, path));
-    }
-    for (path, hash) in &staging_area.modified {
-        content.push_str(&format!("M {} {}\n", hash, path));
+fn tag_workflow(args: TagArgs) -> FitResult<()> {
+    let Some(name) = args.name else {
+        return list_tags();
+    };
+    if args.verify {
+        return verify_tag(&name);
     }
-    for path in &staging_area.deleted {
-        content.push_str(&format!("D {}\n", path));
+
+    let tag_path = fit_path("refs/tags").join(&name);
+    if tag_path.exists() {
+        return Err(FitError::Refused(format!("tag '{}' already exists", name)));
     }
+    let commit = match &args.commit {
+        Some(commit) => resolve_ref(commit)?,
+        None => get_current_commit()?,
+    };
+
+    // Without a message or signature the tag is lightweight: the ref names the commit directly
+    let target = if args.message.is_some() || args.sign {
+        let message = args.message.unwrap_or_default();
+        let mut content = format!(
+            "object {}\ntype commit\ntag {}\ntagger {}\n\n{}",
+            commit,
+            name,
+            default_signature()?.format(),
+            message
+        );
+        if !content.ends_with('\n') {
+            content.push('\n');
+        }
+        if args.sign {
+            let signature = run_signer(&content)?;
+            content.push_str(&signature);
+        }
+        write_object(content.as_bytes(), "tag")?
+    } else {
+        commit
+    };
 
-    fs::write(staging_path, content)
+    write_ref(&tag_path, &target)?;
+    info!("Created tag '{}'", name);
+    Ok(())
 }
 
-fn read_index() -> io::Result<HashMap<String, String>> {
-    let index_path = ".fit/index";
-    let index_content = fs::read_to_string(index_path)?;
-    Ok(index_content
-        .lines()
-        .map(|line| {
-            let parts: Vec<&str> = line.split_whitespace().collect();
-            (parts[1].to_string(), parts[0].to_string())
-        })
-        .collect())
+// A commit's note is a blob named by refs/notes/<commit hash>, so attaching one leaves the commit
+// and its hash untouched and prune keeps the blob through the ref
+fn note_ref_path(commit: &str) -> std::path::PathBuf {
+    fit_path("refs/notes").join(commit)
 }
 
-fn write_index(index: &HashMap<String, String>) -> io::Result<()> {
-    let index_path = ".fit/index";
-    let content: String = index
-        .iter()
-        .map(|(path, hash)| format!("{} {}", hash, path))
-        .collect::<Vec<String>>()
-        .join("\n");
-    fs::write(index_path, content)
-}
-
-fn rm_workflow(args: RmArgs) -> io::Result<()> {
-    let path = Path::new(&args.file);
-    if path.exists() {
-        let mut staging_area = read_staging_area()?;
-        let mut index = read_index()?;
-
-        if index.remove(path.to_str().unwrap()).is_some() {
-            staging_area.delete(path.to_str().unwrap().to_string());
-            write_staging_area(&staging_area)?;
-            write_index(&index)?;
-            println!("Removed {} from fit index and staging area", args.file);
-        } else {
-            println!("File {} not found in fit index", args.file);
+fn read_note(commit: &str) -> FitResult<Option<String>> {
+    let note_path = note_ref_path(commit);
+    if !note_path.is_file() {
+        return Ok(None);
+    }
+    let blob_hash = fs::read_to_string(note_path)?.trim().to_string();
+    let content = read_object_typed(&blob_hash, "blob")?;
+    Ok(Some(String::from_utf8_lossy(&content).to_string()))
+}
+
+fn notes_workflow(args: NotesArgs) -> FitResult<()> {
+    let resolve_commit = |commit: &Option<String>| -> FitResult<String> {
+        let commit = match commit {
+            Some(commit) => resolve_ref(commit)?,
+            None => get_current_commit()?,
+        };
+        read_object_typed(&commit, "commit")?;
+        Ok(commit)
+    };
+    match args.command {
+        NotesSubcommand::Add {
+            commit,
+            message,
+            force,
+        } => {
+            let commit = resolve_commit(&commit)?;
+            let note_path = note_ref_path(&commit);
+            if note_path.exists() && !force {
+                return Err(FitError::Refused(format!(
+                    "commit {} already has a note, use -f to overwrite it",
+                    &commit[..7]
+                )));
+            }
+            let mut message = message;
+            if !message.ends_with('\n') {
+                message.push('\n');
+            }
+            let blob_hash = write_object(message.as_bytes(), "blob")?;
+            write_ref(&note_path, &blob_hash)?;
+            info!("Added note to {}", &commit[..7]);
+        }
+        NotesSubcommand::Show { commit } => {
+            let commit = resolve_commit(&commit)?;
+            let note = read_note(&commit)?.ok_or_else(|| {
+                FitError::InvalidRef(format!("no note found for commit {}", &commit[..7]))
+            })?;
+            print!("{}", note);
+        }
+        NotesSubcommand::Remove { commit } => {
+            let commit = resolve_commit(&commit)?;
+            let note_path = note_ref_path(&commit);
+            if !note_path.is_file() {
+                return Err(FitError::InvalidRef(format!(
+                    "no note found for commit {}",
+                    &commit[..7]
+                )));
+            }
+            fs::remove_file(note_path)?;
+            info!("Removed note from {}", &commit[..7]);
         }
-    } else {
-        println!("File {} not found", args.file);
     }
     Ok(())
 }
 
-fn commit_workflow(args: CommitArgs) -> io::Result<()> {
-    println!("Commiting...");
-
-    let staging_area = read_staging_area()?;
-    if staging_area.added.is_empty()
-        && staging_area.modified.is_empty()
-        && staging_area.deleted.is_empty()
-    {
-        println!("Nothing to commit. Working tree clean.");
+fn list_tags() -> FitResult<()> {
+    let tags_dir = fit_path("refs/tags");
+    if !tags_dir.is_dir() {
         return Ok(());
     }
+    let mut tags: Vec<String> = fs::read_dir(tags_dir)?
+        .map(|entry| Ok(entry?.file_name().to_string_lossy().to_string()))
+        .collect::<FitResult<_>>()?;
+    tags.sort();
+    for tag in tags {
+        println!("{}", tag);
+    }
+    Ok(())
+}
 
-    let mut index = read_index()?;
+// Signing is delegated to gpg.program, called like git calls gpg: the payload goes to its
+// stdin and the armored detached signature comes back on stdout
+fn signing_program() -> FitResult<String> {
+    read_config_value("gpg.program")?.ok_or_else(|| {
+        FitError::Refused(
+            "no signing program configured, set gpg.program in .fit/config".to_string(),
+        )
+    })
+}
 
-    // Apply changes from staging area to index
-    for (path, hash) in staging_area
-        .added
-        .iter()
-        .chain(staging_area.modified.iter())
-    {
-        index.insert(path.clone(), hash.clone());
+fn run_signer(payload: &str) -> FitResult<String> {
+    let program = signing_program()?;
+    let mut command = std::process::Command::new(&program);
+    command.arg("-bsa");
+    if let Some(key) = read_config_value("user.signingkey")? {
+        command.arg("-u").arg(key);
     }
-    for path in &staging_area.deleted {
-        index.remove(path);
+    let mut signer = command
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .spawn()?;
+    write_payload(&mut signer, payload)?;
+    let output = signer.wait_with_output()?;
+    let signature = String::from_utf8_lossy(&output.stdout).to_string();
+    if !output.status.success() || signature.is_empty() {
+        return Err(FitError::Refused(format!(
+            "signing with '{}' failed",
+            program
+        )));
     }
-
-    let tree_hash = create_tree_object(&index)?;
-    println!("Tree object created with hash: {}", tree_hash);
-
-    let parent_hash = get_current_commit()?;
-    println!("Current commit (parent) hash: {}", parent_hash);
-
-    let commit_content = format!(
-        "tree {}\nparent {}\n\n{}",
-        tree_hash, parent_hash, args.message
-    );
-    println!("Commit content created.");
-
-    let commit_hash = write_object(commit_content.as_bytes(), "commit")?;
-    println!("Commit object written with hash: {}", commit_hash);
-
-    update_current_branch(&commit_hash)?;
-    println!("Current branch updated.");
-
-    // Clear staging area
-    fs::remove_file(".fit/STAGING")?;
-
-    write_index(&index)?;
-
-    println!("Created commit {}", commit_hash);
-    Ok(())
+    Ok(signature)
 }
 
-fn create_tree_object(index: &HashMap<String, String>) -> io::Result<String> {
-    let mut tree_content = String::new();
-    for (path, hash) in index {
-        tree_content.push_str(&format!("100644 blob {} {}\n", hash, path));
+// A program that exits without reading all of stdin is judged by its exit status alone
+fn write_payload(child: &mut std::process::Child, payload: &str) -> io::Result<()> {
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    match stdin.write_all(payload.as_bytes()) {
+        Err(err) if err.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+        result => result,
     }
-    write_object(tree_content.as_bytes(), "tree")
 }
 
-fn get_current_commit() -> io::Result<String> {
-    let head_content = fs::read_to_string(".fit/HEAD")?;
-    let ref_path = head_content
-        .trim()
-        .strip_prefix("ref: ")
-        .unwrap_or(&head_content);
-    let full_ref_path = Path::new(".fit").join(ref_path);
-    Ok(fs::read_to_string(full_ref_path)?.trim().to_string())
+// Splits a signed tag into the signed payload and the signature block appended to it
+fn split_signature(content: &str) -> Option<(&str, &str)> {
+    let start = content.find("-----BEGIN ")?;
+    (start == 0 || content[..start].ends_with('\n')).then(|| content.split_at(start))
 }
 
-fn update_current_branch(commit_hash: &str) -> io::Result<()> {
-    let current_branch = get_current_branch()?;
-    let branch_path = Path::new(".fit/refs/heads").join(current_branch);
-    fs::write(branch_path, commit_hash)
+// The signature goes in a file and the payload through stdin, as `gpg --verify <sig> -` expects.
+// Each check gets its own file, named after the process and a counter so concurrent runs
+// don't share one, and the file is removed however the check ends
+fn run_verifier(payload: &str, signature: &str) -> FitResult<bool> {
+    static COUNTER: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+    let program = signing_program()?;
+    let serial = COUNTER.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    let signature_path = fit_path(format!("SIGNATURE_{}_{}", std::process::id(), serial));
+    let status = fs::write(&signature_path, signature).and_then(|_| {
+        let mut verifier = std::process::Command::new(&program)
+            .arg("--verify")
+            .arg(&signature_path)
+            .arg("-")
+            .stdin(std::process::Stdio::piped())
+            .spawn()?;
+        write_payload(&mut verifier, payload)?;
+        verifier.wait()
+    });
+    let _ = fs::remove_file(&signature_path);
+    Ok(status?.success())
 }
 
-fn get_parent_commit(commit_info: &str) -> String {
-    commit_info
-        .lines()
-        .find(|line| line.starts_with("parent "))
-        .map(|line| line.strip_prefix("parent ").unwrap().to_string())
-        .unwrap_or_default()
+// A commit's signature is a "gpgsig" header after the committer, as in git, with every line
+// after the first indented by a space. The signed payload is the commit without that header
+fn sign_commit(content: &str) -> FitResult<String> {
+    let signature = run_signer(content)?;
+    let (headers, message) = content.split_once("\n\n").unwrap_or((content, ""));
+    let header = signature.trim_end().replace('\n', "\n ");
+    Ok(format!("{}\ngpgsig {}\n\n{}", headers, header, message))
 }
 
-fn cat_file_workflow(args: FileArgs) -> io::Result<()> {
-    let hash = args.hash;
-    println!("Unhashing SHA: {}", hash);
-    match read_object(&hash)? {
-        Some((object_type, content)) => {
-            println!("Object type: {}", object_type);
-            println!("Content:");
-            println!("{}", String::from_utf8_lossy(&content));
+fn split_commit_signature(content: &str) -> Option<(String, String)> {
+    let (headers, message) = content.split_once("\n\n").unwrap_or((content, ""));
+    let mut payload_headers = Vec::new();
+    let mut signature = String::new();
+    let mut in_signature = false;
+    for line in headers.lines() {
+        if let Some(first) = line.strip_prefix("gpgsig ") {
+            in_signature = true;
+            signature.push_str(first);
+            signature.push('\n');
+        } else if let Some(rest) = line.strip_prefix(' ').filter(|_| in_signature) {
+            signature.push_str(rest);
+            signature.push('\n');
+        } else {
+            in_signature = false;
+            payload_headers.push(line);
         }
-        None => println!("Object not found"),
     }
-    Ok(())
+    if signature.is_empty() {
+        return None;
+    }
+    Some((
+        format!("{}\n\n{}", payload_headers.join("\n"), message),
+        signature,
+    ))
 }
 
-fn status_workflow() -> io::Result<()> {
-    let current_branch = get_current_branch()?;
-    println!("On branch: {}", current_branch);
-    let staging_area = read_staging_area()?;
-    let index = read_index()?;
-
-    println!("Changes to be committed:");
-    for (path, _) in &staging_area.added {
-        println!("  new file: {}", path);
-    }
-    for (path, _) in &staging_area.modified {
-        println!("  modified: {}", path);
+fn verify_tag(name: &str) -> FitResult<()> {
+    let tag_path = fit_path("refs/tags").join(name);
+    if !tag_path.is_file() {
+        return Err(FitError::InvalidRef(format!("tag '{}' not found", name)));
     }
-    for path in &staging_area.deleted {
-        println!("  deleted: {}", path);
+    let tag_hash = fs::read_to_string(tag_path)?.trim().to_string();
+    let (object_type, content) =
+        read_object(&tag_hash)?.ok_or_else(|| FitError::ObjectNotFound(tag_hash.clone()))?;
+    let content = String::from_utf8_lossy(&content);
+    let signature = split_signature(&content).filter(|_| object_type == "tag");
+    let Some((payload, signature)) = signature else {
+        return Err(FitError::Refused(format!("tag '{}' is not signed", name)));
+    };
+
+    if !run_verifier(payload, signature)? {
+        return Err(FitError::Refused(format!(
+            "signature of tag '{}' could not be verified",
+            name
+        )));
     }
+    println!("Good signature on tag '{}'", name);
+    Ok(())
+}
 
-    println!("\nChanges not staged for commit:");
-    for (path, hash) in &index {
-        if !staging_area.is_staged(path) {
-            if let Ok(file_content) = fs::read(path) {
-                let file_hash = write_object(&file_content, "blob")?;
-                if &file_hash != hash {
-                    println!("  modified: {}", path);
-                }
-            } else {
-                println!("  deleted: {}", path);
-            }
+// Names a commit after the closest tag it descends from, as "<tag>-<commits since>-g<short hash>"
+fn describe_workflow(args: DescribeArgs) -> FitResult<()> {
+    let commit = match &args.commit {
+        Some(commit) => resolve_ref(commit)?,
+        None => get_current_commit()?,
+    };
+    let history = get_commit_history(&commit)?;
+
+    let mut tags = Vec::new();
+    let tags_dir = fit_path("refs/tags");
+    if tags_dir.is_dir() {
+        for entry in fs::read_dir(tags_dir)? {
+            let entry = entry?;
+            let tagged = peel_tag(fs::read_to_string(entry.path())?.trim())?;
+            tags.push((entry.file_name().to_string_lossy().to_string(), tagged));
         }
     }
+    tags.sort();
 
-    println!("\nUntracked files:");
-    for entry in fs::read_dir(".")? {
-        let entry = entry?;
-        let path = entry.path();
-        if path.is_file()
-            && !path.starts_with(".fit")
-            && !index.contains_key(path.to_str().unwrap())
-        {
-            println!("  {}", path.display());
+    // The closest tag is the one leaving the fewest commits unaccounted for
+    let mut closest: Option<(usize, String)> = None;
+    for (tag, tagged) in tags {
+        if !history.contains(&tagged) {
+            continue;
+        }
+        let tag_history: HashSet<String> = get_commit_history(&tagged)?.into_iter().collect();
+        let since = history
+            .iter()
+            .filter(|commit| !tag_history.contains(*commit))
+            .count();
+        if closest.as_ref().is_none_or(|(best, _)| since < *best) {
+            closest = Some((since, tag));
         }
     }
 
+    let short_hash = &commit[..7.min(commit.len())];
+    match closest {
+        Some((0, tag)) => println!("{}", tag),
+        Some((since, tag)) => println!("{}-{}-g{}", tag, since, short_hash),
+        None => println!("{}", short_hash),
+    }
     Ok(())
 }
 
-fn get_current_branch() -> io::Result<String> {
-    let head_content = fs::read_to_string(".fit/HEAD")?;
-    Ok(head_content
-        .trim()
-        .strip_prefix("ref: refs/heads/")
-        .unwrap_or("master")
-        .to_string())
+// Every ref under refs/ as ("refs/...", hash), sorted by name
+fn list_refs() -> FitResult<Vec<(String, String)>> {
+    let mut refs = Vec::new();
+    let mut pending = vec!["refs".to_string()];
+    while let Some(dir) = pending.pop() {
+        for entry in fs::read_dir(fit_path(&dir))? {
+            let entry = entry?;
+            let ref_name = format!("{}/{}", dir, entry.file_name().to_string_lossy());
+            if entry.path().is_dir() {
+                pending.push(ref_name);
+            } else {
+                let hash = fs::read_to_string(entry.path())?.trim().to_string();
+                let hash = if hash.starts_with("ref: ") {
+                    resolve_ref(&ref_name)?
+                } else {
+                    hash
+                };
+                refs.push((ref_name, hash));
+            }
+        }
+    }
+    refs.sort();
+    Ok(refs)
 }
 
-fn reset_workflow(commit_hash: &str) -> io::Result<()> {
-    if read_object(&commit_hash)?.is_none() {
-        return Err(io::Error::new(io::ErrorKind::NotFound, "Commit not found"));
+fn show_ref_workflow(args: ShowRefArgs) -> FitResult<()> {
+    // With neither filter every ref is shown; with either, only the selected namespaces
+    let show_all = !args.heads && !args.tags;
+    for (ref_name, hash) in list_refs()? {
+        let selected = show_all
+            || (args.heads && ref_name.starts_with("refs/heads/"))
+            || (args.tags && ref_name.starts_with("refs/tags/"));
+        if selected {
+            println!("{} {}", hash, ref_name);
+        }
     }
-    update_current_branch(&commit_hash)?;
+    Ok(())
+}
 
-    let (_, commit_content) = read_object(&commit_hash)?.unwrap();
-    let commit_content = String::from_utf8_lossy(&commit_content);
-    let tree_hash = commit_content
-        .lines()
-        .next()
-        .unwrap()
-        .split_whitespace()
-        .nth(1)
-        .unwrap();
+// Locates the .fit directory of the repository configured as remote.<name>.path
+fn remote_fit_dir(remote: &str) -> FitResult<std::path::PathBuf> {
+    let remote_path = read_config_value(&format!("remote.{}.path", remote))?
+        .ok_or_else(|| FitError::RemoteNotFound(remote.to_string()))?;
+    let remote_path = Path::new(&remote_path);
+    let fit_dir = remote_path.join(".fit");
+    if fit_dir.is_dir() {
+        return Ok(fit_dir);
+    }
+    if remote_path.join("objects").is_dir() && remote_path.join("refs").is_dir() {
+        return Ok(remote_path.to_path_buf());
+    }
+    Err(FitError::InvalidArgument(format!(
+        "'{}' is not a fit repository",
+        remote_path.display()
+    )))
+}
 
-    let (_, tree_content) = read_object(tree_hash)?.unwrap();
-    let tree_content: Cow<str> = String::from_utf8_lossy(&tree_content);
+fn fetch_workflow(args: FetchArgs) -> FitResult<()> {
+    let remote_dir = remote_fit_dir(&args.remote)?;
 
-    let mut new_index = HashMap::new();
-    if Path::new(".fit/STAGING").exists() {
-        fs::remove_file(".fit/STAGING")?;
+    // Objects are immutable, so copying every one we lack never overwrites anything
+    let mut copied = 0;
+    for dir_entry in fs::read_dir(remote_dir.join("objects"))? {
+        let dir_entry = dir_entry?;
+        let dir_name = dir_entry.file_name();
+        if dir_name.len() != 2 || !dir_entry.path().is_dir() {
+            continue;
+        }
+        for entry in fs::read_dir(dir_entry.path())? {
+            let entry = entry?;
+            let local_path = fit_path("objects").join(&dir_name).join(entry.file_name());
+            if !local_path.exists() {
+                fs::create_dir_all(local_path.parent().unwrap())?;
+                fs::copy(entry.path(), &local_path)?;
+                copied += 1;
+            }
+        }
+    }
+    // Packs gc wrote on the remote are copied whole, the pack before its index so an index
+    // never names a pack that isn't there yet
+    let mut packs = 0;
+    let remote_pack_dir = remote_dir.join("objects/pack");
+    for remote_idx in pack_index_paths_in(&remote_dir.join("objects"))? {
+        let Some(idx_name) = remote_idx.file_name() else {
+            continue;
+        };
+        let local_idx = fit_path("objects/pack").join(idx_name);
+        if local_idx.exists() {
+            continue;
+        }
+        let pack_name = Path::new(idx_name).with_extension("pack");
+        fs::create_dir_all(fit_path("objects/pack"))?;
+        fs::copy(
+            remote_pack_dir.join(&pack_name),
+            fit_path("objects/pack").join(&pack_name),
+        )?;
+        fs::copy(&remote_idx, &local_idx)?;
+        packs += 1;
     }
+    info!(
+        "Fetched {} objects and {} packs from '{}'",
+        copied, packs, args.remote
+    );
 
-    let current_index = read_index()?;
-    let current_files: HashSet<_> = current_index.keys().cloned().collect();
+    let tracking_dir = fit_path("refs/remotes").join(&args.remote);
+    fs::create_dir_all(&tracking_dir)?;
+    for (branch, remote_commit) in remote_branches(&remote_dir)? {
+        let tracking_path = tracking_dir.join(&branch);
+        let old_commit = fs::read_to_string(&tracking_path).unwrap_or_default();
+        let old_commit = old_commit.trim();
+        if old_commit == remote_commit {
+            continue;
+        }
+        write_ref(&tracking_path, &remote_commit)?;
+        if old_commit.is_empty() {
+            info!(" * [new branch] {} -> {}/{}", branch, args.remote, branch);
+        } else {
+            info!(
+                "   {}..{} {} -> {}/{}",
+                &old_commit[..7.min(old_commit.len())],
+                &remote_commit[..7.min(remote_commit.len())],
+                branch,
+                args.remote,
+                branch
+            );
+        }
+    }
+    Ok(())
+}
 
-    let mut target_files = HashSet::new();
+// Every branch under the remote's refs/heads, including ones like "feature/one" kept in
+// subdirectories, with the commit it points at. All are read before any tracking ref is
+// written, so an unreadable ref doesn't leave the tracking refs half updated
+fn remote_branches(remote_dir: &Path) -> FitResult<Vec<(String, String)>> {
+    let heads_dir = remote_dir.join("refs/heads");
+    let mut branches = Vec::new();
+    let mut pending = vec![String::new()];
+    while let Some(dir) = pending.pop() {
+        for entry in fs::read_dir(heads_dir.join(&dir))? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().to_string();
+            let branch = if dir.is_empty() {
+                name
+            } else {
+                format!("{}/{}", dir, name)
+            };
+            if entry.path().is_dir() {
+                pending.push(branch);
+            } else {
+                let commit = fs::read_to_string(entry.path())?.trim().to_string();
+                branches.push((branch, commit));
+            }
+        }
+    }
+    branches.sort();
+    Ok(branches)
+}
 
-    for line in tree_content.lines() {
-        let parts: Vec<&str> = line.split_whitespace().collect();
-        let file_hash = parts[2];
-        let file_path = parts[3];
+// Fetches from `remote`, then merges its copy of the current branch into it
+fn pull_workflow(args: PullArgs) -> FitResult<()> {
+    let HeadState::Symbolic(branch) = read_head()? else {
+        return Err(FitError::Refused(
+            "cannot pull with a detached HEAD, check out a branch first".to_string(),
+        ));
+    };
+    fetch_workflow(FetchArgs {
+        remote: args.remote.clone(),
+    })?;
+    let tracking_ref = format!("refs/remotes/{}/{}", args.remote, branch);
+    let tracking_path = fit_path(&tracking_ref);
+    if !tracking_path.is_file() {
+        return Err(FitError::InvalidRef(format!(
+            "remote '{}' has no branch '{}'",
+            args.remote, branch
+        )));
+    }
+    let their_commit = fs::read_to_string(tracking_path)?.trim().to_string();
+    merge_into_current(&format!("{}/{}", args.remote, branch), &their_commit)
+}
 
-        target_files.insert(file_path.to_string());
+// Records `path` in the staging bucket matching how `hash` (None when removed) differs from HEAD
+fn restage_path(
+    staging_area: &mut StagingArea,
+    head_files: &HashMap<String, String>,
+    path: &str,
+    hash: Option<&String>,
+) {
+    staging_area.added.remove(path);
+    staging_area.modified.remove(path);
+    staging_area.deleted.retain(|deleted| deleted != path);
+    match (head_files.get(path), hash) {
+        (Some(head_hash), Some(hash)) if head_hash == hash => {}
+        (Some(_), Some(hash)) => staging_area.modify(path.to_string(), hash.clone()),
+        (None, Some(hash)) => staging_area.add(path.to_string(), hash.clone()),
+        (Some(_), None) => staging_area.delete(path.to_string()),
+        (None, None) => {}
+    }
+}
 
-        let (_, blob_content) = read_object(file_hash)?.unwrap();
+// Writes `value` to a ref file, the low-level step behind branch and tag updates
+fn update_ref_workflow(args: UpdateRefArgs) -> FitResult<()> {
+    check_ref_name(&args.ref_name)?;
+    let ref_path = fit_path(&args.ref_name);
+    let current = match fs::read_to_string(&ref_path) {
+        Ok(content) => content.trim().to_string(),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(err) => return Err(err.into()),
+    };
+    if let Some(old_value) = &args.old_value {
+        let expected = if old_value.bytes().all(|byte| byte == b'0') {
+            ""
+        } else {
+            old_value.as_str()
+        };
+        if current != expected {
+            return Err(FitError::Refused(format!(
+                "cannot update {}: expected '{}' but it is '{}'",
+                args.ref_name, expected, current
+            )));
+        }
+    }
 
-        if let Some(parent) = Path::new(file_path).parent() {
-            fs::create_dir_all(parent)?;
+    let new_value = match args.value.strip_prefix("ref: ") {
+        Some(target) => {
+            check_ref_name(target)?;
+            if !target.starts_with("refs/") {
+                return Err(FitError::InvalidRef(format!(
+                    "symbolic ref target '{}' must start with refs/",
+                    target
+                )));
+            }
+            format!("ref: {}", target)
         }
+        None => resolve_ref(&args.value)?,
+    };
+    if args.ref_name == "HEAD" {
+        write_ref(&ref_path, &format!("{}\n", new_value))?;
+    } else {
+        write_ref(&ref_path, &new_value)?;
+    }
+    if !new_value.starts_with("ref: ") && !current.starts_with("ref: ") {
+        append_reflog(&args.ref_name, &current, &new_value, &args.message)?;
+    }
+    info!("Updated {} to {}", args.ref_name, new_value);
+    Ok(())
+}
 
-        fs::write(file_path, blob_content)?;
+// Ref names are HEAD or live under refs/, made of non-empty components that don't start
+// with '.', so a ref can never escape the refs directory or shadow a lock file
+fn check_ref_name(ref_name: &str) -> FitResult<()> {
+    let valid = ref_name == "HEAD"
+        || (ref_name.starts_with("refs/")
+            && !ref_name.ends_with(".lock")
+            && ref_name.split('/').all(|part| {
+                !part.is_empty()
+                    && !part.starts_with('.')
+                    && !part.contains(|c: char| c.is_whitespace() || c.is_control() || c == '\\')
+            }));
+    if valid {
+        Ok(())
+    } else {
+        Err(FitError::InvalidRef(format!(
+            "'{}' is not a valid ref name",
+            ref_name
+        )))
+    }
+}
 
-        new_index.insert(file_path.to_string(), file_hash.to_string());
+fn symbolic_ref_workflow(args: SymbolicRefArgs) -> FitResult<()> {
+    if args.name != "HEAD" {
+        return Err(FitError::InvalidRef(
+            "only HEAD is supported as a symbolic ref".to_string(),
+        ));
+    }
+    match args.target {
+        Some(target) => {
+            let branch = target.strip_prefix("refs/heads/").ok_or_else(|| {
+                FitError::InvalidRef("symbolic ref target must start with refs/heads/".to_string())
+            })?;
+            if !fit_path("refs/heads").join(branch).exists() {
+                return Err(FitError::BranchNotFound(branch.to_string()));
+            }
+            write_head(&HeadState::Symbolic(branch.to_string()))?;
+        }
+        None => match read_head()? {
+            HeadState::Symbolic(branch) => println!("refs/heads/{}", branch),
+            HeadState::Detached(_) => {
+                return Err(FitError::InvalidRef(
+                    "ref HEAD is not a symbolic ref".to_string(),
+                ))
+            }
+        },
     }
+    Ok(())
+}
 
-    for file in current_files.difference(&target_files) {
-        if Path::new(file).exists() {
-            fs::remove_file(file)?;
-            println!("Removed file: {}", file);
+// Writes each non-merge commit in the range, oldest first, to NNNN-<subject>.patch as an
+// email: the author, date and message followed by the commit's diff against its parent
+fn format_patch_workflow(args: FormatPatchArgs) -> FitResult<()> {
+    let (since, until) = match args.range.split_once("..") {
+        Some((since, "")) => (since, "HEAD"),
+        Some((since, until)) => (since, until),
+        None => (args.range.as_str(), "HEAD"),
+    };
+    let excluded: HashSet<String> = get_commit_history(&resolve_ref(since)?)?
+        .into_iter()
+        .collect();
+    let mut commits = Vec::new();
+    for commit in get_commit_history(&resolve_ref(until)?)?.into_iter().rev() {
+        if excluded.contains(&commit) {
+            continue;
+        }
+        let content = String::from_utf8_lossy(&read_object_typed(&commit, "commit")?).to_string();
+        if get_parent_commits(&content).len() <= 1 {
+            commits.push((commit, content));
         }
     }
 
-    write_index(&new_index)?;
+    fs::create_dir_all(&args.output_directory)?;
+    let total = commits.len();
+    for (number, (commit, content)) in commits.iter().enumerate() {
+        let (commit_info, message) = content.split_once("\n\n").unwrap_or((content, ""));
+        let (subject, body) = message
+            .trim()
+            .split_once('\n')
+            .unwrap_or((message.trim(), ""));
+        let author = commit_info
+            .lines()
+            .find_map(|line| line.strip_prefix("author "))
+            .ok_or_else(|| FitError::CorruptObject(format!("commit {}: missing author", commit)))?;
+        // "Name <email> <timestamp> <zone>"
+        let (ident, date) = author.rsplit_once("> ").unwrap_or((author, ""));
+        let mut patch = format!("From {} Mon Sep 17 00:00:00 2001\n", commit);
+        patch.push_str(&format!("From: {}>\n", ident));
+        if let Some((timestamp, zone)) = date.split_once(' ') {
+            if let (Ok(timestamp), Some(offset)) = (timestamp.parse::<i64>(), parse_zone(zone)) {
+                patch.push_str(&format!(
+                    "Date: {}\n",
+                    rfc2822_date(timestamp, offset, zone)
+                ));
+            }
+        }
+        let prefix = if total > 1 {
+            format!("[PATCH {}/{}]", number + 1, total)
+        } else {
+            "[PATCH]".to_string()
+        };
+        patch.push_str(&format!("Subject: {} {}\n\n", prefix, subject));
+        let body = body.trim();
+        if !body.is_empty() {
+            patch.push_str(&format!("{}\n\n", body));
+        }
+        patch.push_str("---\n");
 
-    println!("Reset to commit {}", commit_hash);
-    Ok(())
-}
+        let parent = get_parent_commit(commit_info);
+        let parent_files = if parent.is_empty() {
+            HashMap::new()
+        } else {
+            get_tree_files(&get_commit_tree(&parent)?)?
+        };
+        let files = get_tree_files(&get_commit_tree(commit)?)?;
+        for (_, path) in changed_files(&parent_files, &files) {
+            let side = |files: &HashMap<String, String>| -> FitResult<Option<String>> {
+                files
+                    .get(&path)
+                    .map(|hash| {
+                        let content = read_object_typed(hash, "blob")?;
+                        Ok(String::from_utf8_lossy(&content).to_string())
+                    })
+                    .transpose()
+            };
+            let (old, new) = (side(&parent_files)?, side(&files)?);
+            if let Some(diff) = unified_diff(&path, old.as_deref(), new.as_deref()) {
+                patch.push_str(&diff);
+            }
+        }
+        patch.push_str("-- \nfit\n");
 
-fn branch_workflow(args: BranchArgs) -> io::Result<()> {
-    match args.command {
-        BranchSubcommand::List => list_branches()?,
-        BranchSubcommand::Create { name } => create_branch(&name)?,
-        BranchSubcommand::Delete { name } => delete_branch(&name)?,
-        BranchSubcommand::Checkout { name } => checkout_branch(&name)?,
-        BranchSubcommand::CheckoutNew { name } => checkout_new_branch(&name)?,
+        let file_name = format!("{:04}-{}.patch", number + 1, patch_slug(subject));
+        let patch_path = args.output_directory.join(file_name);
+        fs::write(&patch_path, patch)?;
+        println!("{}", patch_path.display());
     }
     Ok(())
 }
 
-fn list_branches() -> io::Result<()> {
-    let branches_dir = Path::new(".fit/refs/heads");
-    for entry in fs::read_dir(branches_dir)? {
-        let entry = entry?;
-        println!("{}", entry.file_name().to_string_lossy());
-    }
-    Ok(())
+// One file's changes in a unified diff; a path is None on the /dev/null side
+struct FilePatch {
+    old_path: Option<String>,
+    new_path: Option<String>,
+    hunks: Vec<PatchHunk>,
 }
 
-fn create_branch(name: &str) -> io::Result<()> {
-    if name == "master" {
-        return Err(io::Error::new(
-            io::ErrorKind::PermissionDenied,
-            "Cannot create a duplicate master branch",
+struct PatchHunk {
+    old_start: usize,
+    old_count: usize,
+    // Each line with its ' ', '-' or '+' marker, keeping its "\n" unless the patch says the
+    // line has none
+    lines: Vec<(char, String)>,
+}
+
+// Applies every file's hunks only once all of them are known to apply, so a patch that
+// doesn't fit leaves the working tree as it was
+fn apply_workflow(args: ApplyArgs) -> FitResult<()> {
+    let patch = match &args.patch {
+        Some(path) => fs::read(path)?,
+        None => {
+            let mut patch = Vec::new();
+            io::stdin().read_to_end(&mut patch)?;
+            patch
+        }
+    };
+    let file_patches = parse_patch(&String::from_utf8_lossy(&patch))?;
+    if file_patches.is_empty() {
+        return Err(FitError::InvalidArgument(
+            "no changes found in the patch".to_string(),
         ));
     }
-    let current_commit = get_current_commit()?;
-    let branch_path = Path::new(".fit/refs/heads").join(name);
-    if branch_path.exists() {
-        return Err(io::Error::new(
-            io::ErrorKind::AlreadyExists,
-            format!(
-                "Branch called '{}' already exists, choose a different name",
-                name
-            ),
-        ));
+
+    let mut results = Vec::new();
+    for file_patch in &file_patches {
+        let old_content = match &file_patch.old_path {
+            Some(path) => read_working_blob(path)?.ok_or_else(|| {
+                FitError::Refused(format!("patch failed: {} does not exist", path))
+            })?,
+            None => Vec::new(),
+        };
+        if let (None, Some(path)) = (&file_patch.old_path, &file_patch.new_path) {
+            if fs::symlink_metadata(path).is_ok() {
+                return Err(FitError::Refused(format!(
+                    "patch failed: {} already exists",
+                    path
+                )));
+            }
+        }
+        let path = file_patch
+            .new_path
+            .as_ref()
+            .or(file_patch.old_path.as_ref())
+            .expect("parse_patch rejects a patch without either path");
+        let content = apply_hunks_to(path, &String::from_utf8_lossy(&old_content), file_patch)?;
+        results.push((file_patch, content));
+    }
+    if args.check {
+        return Ok(());
+    }
+
+    for (file_patch, content) in results {
+        if let Some(old_path) = &file_patch.old_path {
+            if file_patch.new_path.as_ref() != Some(old_path) {
+                fs::remove_file(old_path)?;
+            }
+        }
+        if let Some(new_path) = &file_patch.new_path {
+            if let Some(parent) = Path::new(new_path).parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(new_path, convert_to_working(content.into_bytes())?)?;
+        }
+        let path = file_patch
+            .new_path
+            .as_ref()
+            .or(file_patch.old_path.as_ref());
+        info!("Applied patch to {}", path.map_or("", String::as_str));
     }
-    fs::write(branch_path, current_commit)?;
-    println!("Created branch '{}'", name);
     Ok(())
 }
 
-fn delete_branch(name: &str) -> io::Result<()> {
-    if name == "master" {
-        return Err(io::Error::new(
-            io::ErrorKind::PermissionDenied,
-            "Cannot delete the master branch",
-        ));
+fn parse_patch(patch: &str) -> FitResult<Vec<FilePatch>> {
+    let invalid = |problem: &str| FitError::InvalidArgument(format!("corrupt patch: {}", problem));
+    // "a/path" and "b/path" name the file from the repository root, as `patch -p1` reads them
+    let parse_path = |header: &str| -> FitResult<Option<String>> {
+        let path = header.split('\t').next().unwrap_or(header).trim_end();
+        if path == "/dev/null" {
+            return Ok(None);
+        }
+        let path = path
+            .strip_prefix("a/")
+            .or_else(|| path.strip_prefix("b/"))
+            .unwrap_or(path);
+        if path.is_empty()
+            || path.starts_with('/')
+            || path.split('/').any(|part| part == "..")
+            || is_fit_internal(path)
+        {
+            return Err(FitError::Refused(format!(
+                "patch names unsafe path '{}'",
+                path
+            )));
+        }
+        Ok(Some(path.to_string()))
+    };
+    let parse_range = |range: &str| -> FitResult<(usize, usize)> {
+        let (start, count) = range.split_once(',').unwrap_or((range, "1"));
+        match (start.parse(), count.parse()) {
+            (Ok(start), Ok(count)) => Ok((start, count)),
+            _ => Err(invalid(&format!("bad hunk range '{}'", range))),
+        }
+    };
+
+    let mut file_patches: Vec<FilePatch> = Vec::new();
+    let mut lines = patch.split_inclusive('\n').peekable();
+    while let Some(line) = lines.next() {
+        if let Some(old_header) = line.strip_prefix("--- ") {
+            let Some(new_header) = lines.next_if(|next| next.starts_with("+++ ")) else {
+                continue;
+            };
+            let old_path = parse_path(old_header)?;
+            let new_path = parse_path(&new_header["+++ ".len()..])?;
+            if old_path.is_none() && new_path.is_none() {
+                return Err(invalid("file patch without a path"));
+            }
+            file_patches.push(FilePatch {
+                old_path,
+                new_path,
+                hunks: Vec::new(),
+            });
+        } else if let Some(header) = line.strip_prefix("@@ -") {
+            let file_patch = file_patches
+                .last_mut()
+                .ok_or_else(|| invalid("hunk before any file header"))?;
+            let (old_range, rest) = header
+                .split_once(" +")
+                .ok_or_else(|| invalid("bad hunk header"))?;
+            let new_range = rest
+                .split_once(" @@")
+                .ok_or_else(|| invalid("bad hunk header"))?
+                .0;
+            let (old_start, old_count) = parse_range(old_range)?;
+            let (_, new_count) = parse_range(new_range)?;
+
+            // The counts say where the hunk ends, so a body line starting with "--- " or
+            // "-- " is still read as a removed line
+            let (mut old_left, mut new_left) = (old_count, new_count);
+            let mut hunk_lines: Vec<(char, String)> = Vec::new();
+            while old_left > 0
+                || new_left > 0
+                || lines.peek().is_some_and(|next| next.starts_with('\\'))
+            {
+                let body_line = lines.next().ok_or_else(|| invalid("truncated hunk"))?;
+                let marker = body_line.chars().next().unwrap_or(' ');
+                let text = body_line.get(1..).unwrap_or_default().to_string();
+                match marker {
+                    '\\' => {
+                        if let Some((_, last)) = hunk_lines.last_mut() {
+                            if last.ends_with('\n') {
+                                last.pop();
+                            }
+                        }
+                        continue;
+                    }
+                    ' ' | '\n' if old_left > 0 && new_left > 0 => {
+                        old_left -= 1;
+                        new_left -= 1;
+                    }
+                    '-' if old_left > 0 => old_left -= 1,
+                    '+' if new_left > 0 => new_left -= 1,
+                    _ => {
+                        return Err(invalid(&format!(
+                            "unexpected line '{}'",
+                            body_line.trim_end()
+                        )))
+                    }
+                }
+                // An empty context line may have lost its leading space
+                let (marker, text) = if marker == '\n' {
+                    (' ', "\n".to_string())
+                } else {
+                    (marker, text)
+                };
+                hunk_lines.push((marker, text));
+            }
+            file_patch.hunks.push(PatchHunk {
+                old_start,
+                old_count,
+                lines: hunk_lines,
+            });
+        }
     }
-    let current_branch = get_current_branch()?;
-    if current_branch == *name {
-        return Err(io::Error::new(
-            io::ErrorKind::PermissionDenied,
-            "Cannot delete branch currently in use, please switch to master or different branch",
-        ));
+    Ok(file_patches)
+}
+
+// Applies the hunks at exactly the lines they name, refusing if any context or removed line
+// differs from the file
+fn apply_hunks_to(path: &str, content: &str, file_patch: &FilePatch) -> FitResult<String> {
+    let original: Vec<&str> = content.split_inclusive('\n').collect();
+    let mut patched = String::new();
+    let mut next_line = 0;
+    for hunk in &file_patch.hunks {
+        // A hunk that only adds lines names the line it goes after
+        let start = if hunk.old_count == 0 {
+            hunk.old_start
+        } else {
+            hunk.old_start.saturating_sub(1)
+        };
+        let failed = || FitError::Refused(format!("patch failed: {}:{}", path, hunk.old_start));
+        if start < next_line || start > original.len() {
+            return Err(failed());
+        }
+        patched.extend(original[next_line..start].iter().copied());
+        let mut line = start;
+        for (marker, text) in &hunk.lines {
+            if *marker != '+' {
+                if original.get(line) != Some(&text.as_str()) {
+                    return Err(failed());
+                }
+                line += 1;
+            }
+            if *marker != '-' {
+                patched.push_str(text);
+            }
+        }
+        next_line = line;
     }
-    let branch_path = Path::new(".fit/refs/heads").join(name);
-    if !branch_path.exists() {
-        return Err(io::Error::new(
-            io::ErrorKind::NotFound,
-            "Branch not found, Cannot delete non-existent branch",
-        ));
+    patched.extend(original[next_line..].iter().copied());
+    if file_patch.new_path.is_none() && !patched.is_empty() {
+        return Err(FitError::Refused(format!(
+            "patch failed: {} is not empty after removing its lines",
+            path
+        )));
     }
-    fs::remove_file(branch_path)?;
-    println!("Deleted branch '{}'", name);
-    Ok(())
+    Ok(patched)
 }
 
-fn checkout_branch(name: &str) -> io::Result<()> {
-    let branch_path = Path::new(".fit/refs/heads").join(name);
-    if !branch_path.exists() {
-        return Err(io::Error::new(io::ErrorKind::NotFound, "Branch not found"));
-    }
-    let commit_hash = fs::read_to_string(branch_path)?;
-    fs::write(".fit/HEAD", format!("ref: refs/heads/{}\n", name))?;
-    reset_workflow(&commit_hash)?;
-    println!("Switched to branch '{}'", name);
-    Ok(())
+// A subject reduced to the letters and digits of its words joined by dashes, like git's
+// patch file names
+fn patch_slug(subject: &str) -> String {
+    let slug = subject
+        .split(|c: char| !c.is_ascii_alphanumeric() && c != '.' && c != '_')
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join("-");
+    let slug: String = slug.trim_matches('.').chars().take(52).collect();
+    slug.trim_end_matches(['-', '.']).to_string()
 }
 
-fn checkout_new_branch(name: &str) -> io::Result<()> {
-    create_branch(name)?;
-    checkout_branch(name)?;
-    Ok(())
+// "Thu, 1 Jan 1970 00:00:00 +0000", in the zone the date was recorded in
+fn rfc2822_date(timestamp: i64, offset_minutes: i32, zone: &str) -> String {
+    const WEEKDAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    let local = timestamp + i64::from(offset_minutes) * 60;
+    let days = local.div_euclid(86400);
+    let seconds = local.rem_euclid(86400);
+    let (year, month, day) = civil_from_days(days);
+    format!(
+        "{}, {} {} {} {:02}:{:02}:{:02} {}",
+        WEEKDAYS[(days + 4).rem_euclid(7) as usize],
+        day,
+        MONTHS[(month - 1) as usize],
+        year,
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60,
+        zone
+    )
 }
-fn diff_workflow(args: DiffArgs) -> io::Result<()> {
+
+fn diff_workflow(args: DiffArgs) -> FitResult<ExitCode> {
+    let options = DiffOptions {
+        ignore_all_space: args.ignore_all_space,
+        color_words: args.color_words,
+        quiet: args.quiet,
+    };
     match args.command {
         Some(DiffSubcommand::Commit { commit1, commit2 }) => {
-            diff_commits(&commit1, &commit2)?;
-        }
-        None => {
-            diff_staged_vs_latest()?;
+            diff_commits(&resolve_ref(&commit1)?, &resolve_ref(&commit2)?, &options)?;
         }
+        None => match (args.commit, args.other) {
+            (Some(commit), Some(other)) => {
+                diff_commits(&resolve_ref(&commit)?, &resolve_ref(&other)?, &options)?
+            }
+            (Some(commit), None) if args.cached => {
+                diff_staged_vs_commit(&resolve_ref(&commit)?, &options)?
+            }
+            (Some(commit), None) => diff_commit_vs_working_tree(&resolve_ref(&commit)?, &options)?,
+            _ if args.cached => diff_staged_vs_commit(&get_current_commit()?, &options)?,
+            _ => diff_working_tree_vs_index(&options)?,
+        },
     }
-    Ok(())
+    let found = DIFF_FOUND.load(std::sync::atomic::Ordering::Relaxed);
+    if found && (args.exit_code || args.quiet) {
+        return Ok(ExitCode::from(1));
+    }
+    Ok(ExitCode::SUCCESS)
 }
 
-fn diff_commits(commit1: &str, commit2: &str) -> io::Result<()> {
-    println!("Diffing commit {} and {}", commit1, commit2);
+fn diff_commits(commit1: &str, commit2: &str, options: &DiffOptions) -> FitResult<()> {
+    read_object_typed(commit1, "commit")?;
+    read_object_typed(commit2, "commit")?;
+    if !options.quiet {
+        info!("Diffing commit {} and {}", commit1, commit2);
+    }
 
     // Get tree hashes for both commits
     let tree1 = get_commit_tree(commit1)?;
@@ -718,30 +7045,55 @@ fn diff_commits(commit1: &str, commit2: &str) -> io::Result<()> {
     let files1 = get_tree_files(&tree1)?;
     let files2 = get_tree_files(&tree2)?;
 
+    diff_tree_files(&files1, &files2, options)
+}
+
+fn diff_tree_files(
+    files1: &HashMap<String, String>,
+    files2: &HashMap<String, String>,
+    options: &DiffOptions,
+) -> FitResult<()> {
     // Compare files in both trees
-    let all_files: HashSet<_> = files1.keys().chain(files2.keys()).collect();
+    let mut all_files: Vec<_> = files1
+        .keys()
+        .chain(files2.keys())
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .collect();
+    all_files.sort();
 
     for file in all_files {
         match (files1.get(file), files2.get(file)) {
             (Some(hash1), Some(hash2)) if hash1 != hash2 => {
                 // File exists in both commits but has changed
-                let (_, content1) = read_object(hash1)?.unwrap();
-                let (_, content2) = read_object(hash2)?.unwrap();
+                let content1 = read_object_typed(hash1, "blob")?;
+                let content2 = read_object_typed(hash2, "blob")?;
                 print_diff(
                     file,
-                    &String::from_utf8_lossy(&content1),
-                    &String::from_utf8_lossy(&content2),
+                    Some(&String::from_utf8_lossy(&content1)),
+                    Some(&String::from_utf8_lossy(&content2)),
+                    options,
                 );
             }
             (Some(hash), None) => {
                 // File exists in commit1 but not in commit2 (deleted)
-                let (_, content) = read_object(hash)?.unwrap();
-                print_diff(file, &String::from_utf8_lossy(&content), "");
+                let content = read_object_typed(hash, "blob")?;
+                print_diff(
+                    file,
+                    Some(&String::from_utf8_lossy(&content)),
+                    None,
+                    options,
+                );
             }
             (None, Some(hash)) => {
                 // File exists in commit2 but not in commit1 (new file)
-                let (_, content) = read_object(hash)?.unwrap();
-                print_diff(file, "", &String::from_utf8_lossy(&content));
+                let content = read_object_typed(hash, "blob")?;
+                print_diff(
+                    file,
+                    None,
+                    Some(&String::from_utf8_lossy(&content)),
+                    options,
+                );
             }
             _ => {} // File exists in both commits and hasn't changed, or doesn't exist in either
         }
@@ -750,193 +7102,1099 @@ fn diff_commits(commit1: &str, commit2: &str) -> io::Result<()> {
     Ok(())
 }
 
-fn get_commit_tree(commit_hash: &str) -> io::Result<String> {
-    let (_, commit_content) = read_object(commit_hash)?.unwrap();
-    let commit_content = String::from_utf8_lossy(&commit_content);
-    Ok(commit_content
-        .lines()
-        .next()
-        .unwrap()
-        .split_whitespace()
-        .nth(1)
-        .unwrap()
-        .to_string())
+// Lists paths that differ between two trees as (status, path), with status one of A/M/D
+fn changed_files(
+    files1: &HashMap<String, String>,
+    files2: &HashMap<String, String>,
+) -> Vec<(char, String)> {
+    let mut changes: Vec<(char, String)> = files2
+        .iter()
+        .filter_map(|(path, hash)| match files1.get(path) {
+            None => Some(('A', path.clone())),
+            Some(old_hash) if old_hash != hash => Some(('M', path.clone())),
+            Some(_) => None,
+        })
+        .chain(
+            files1
+                .keys()
+                .filter(|path| !files2.contains_key(*path))
+                .map(|path| ('D', path.clone())),
+        )
+        .collect();
+    changes.sort_by(|a, b| a.1.cmp(&b.1));
+    changes
 }
 
-fn get_tree_files(tree_hash: &str) -> io::Result<HashMap<String, String>> {
-    let (_, tree_content) = read_object(tree_hash)?.unwrap();
-    let tree_content = String::from_utf8_lossy(&tree_content);
+fn diff_commit_vs_working_tree(commit: &str, options: &DiffOptions) -> FitResult<()> {
+    if read_object(commit)?.is_none() {
+        return Err(FitError::ObjectNotFound(commit.to_string()));
+    }
+    let commit_files = get_tree_files(&get_commit_tree(commit)?)?;
+    let index = read_index()?;
+
+    // Files tracked either by the commit or by the index are compared against disk
+    let all_files: HashSet<_> = commit_files.keys().chain(index.keys()).collect();
 
-    let mut files = HashMap::new();
-    for line in tree_content.lines() {
-        let parts: Vec<&str> = line.split_whitespace().collect();
-        let file_hash = parts[2];
-        let file_path = parts[3];
-        files.insert(file_path.to_string(), file_hash.to_string());
+    for file in all_files {
+        // Files whose stat data matches the index are known to hold the indexed content
+        if let (Some(hash), Some(entry), Ok(metadata)) = (
+            commit_files.get(file),
+            index.get(file),
+            fs::symlink_metadata(file),
+        ) {
+            if entry.matches_stat(&metadata) && &entry.hash == hash {
+                continue;
+            }
+        }
+
+        // Hash the working copy without persisting it to the object store
+        let working_content = read_working_blob(file)?;
+        match (commit_files.get(file), working_content) {
+            (Some(hash), Some(working)) => {
+                if &hash_object(&working, "blob") != hash {
+                    let content = read_object_typed(hash, "blob")?;
+                    print_diff(
+                        file,
+                        Some(&String::from_utf8_lossy(&content)),
+                        Some(&String::from_utf8_lossy(&working)),
+                        options,
+                    );
+                }
+            }
+            (Some(hash), None) => {
+                // File exists in the commit but was removed from the working tree
+                let content = read_object_typed(hash, "blob")?;
+                print_diff(
+                    file,
+                    Some(&String::from_utf8_lossy(&content)),
+                    None,
+                    options,
+                );
+            }
+            (None, Some(working)) => {
+                // File is tracked in the working tree but not in the commit
+                print_diff(
+                    file,
+                    None,
+                    Some(&String::from_utf8_lossy(&working)),
+                    options,
+                );
+            }
+            (None, None) => {}
+        }
     }
 
-    Ok(files)
+    Ok(())
 }
 
-fn diff_staged_vs_latest() -> io::Result<()> {
-    let index = read_index()?;
-    let current_commit = get_current_commit()?;
-
-    // Get the tree hash from the current commit
-    let (_, commit_content) = read_object(&current_commit)?.unwrap();
+fn get_commit_tree(commit_hash: &str) -> FitResult<String> {
+    let commit_content = read_object_typed(commit_hash, "commit")?;
     let commit_content = String::from_utf8_lossy(&commit_content);
-    let tree_hash = commit_content
+    commit_content
         .lines()
         .next()
-        .unwrap()
-        .split_whitespace()
-        .nth(1)
-        .unwrap();
+        .and_then(|line| line.strip_prefix("tree "))
+        .map(str::to_string)
+        .ok_or_else(|| FitError::CorruptObject(format!("commit {}: missing tree", commit_hash)))
+}
+
+// Maps the full path of every file under the tree, descending into subtrees, to its blob
+fn get_tree_files(tree_hash: &str) -> FitResult<HashMap<String, String>> {
+    let mut files = HashMap::new();
+    walk_tree(tree_hash, "", &mut |path, _, hash| {
+        files.insert(path, hash.to_string());
+    })?;
+    Ok(files)
+}
+
+// Calls `visit` with the full path, mode and blob hash of every file under the tree
+fn walk_tree(
+    tree_hash: &str,
+    prefix: &str,
+    visit: &mut impl FnMut(String, &str, &str),
+) -> FitResult<()> {
+    let tree_content = read_object_typed(tree_hash, "tree")?;
+    for entry in parse_tree(tree_hash, &tree_content)? {
+        let path = format!("{}{}", prefix, entry.name);
+        match entry.object_type.as_str() {
+            "tree" => walk_tree(&entry.hash, &format!("{}/", path), visit)?,
+            // A submodule's commit has no content in this repository to check out
+            "commit" => {}
+            _ => visit(path, &entry.mode, &entry.hash),
+        }
+    }
+    Ok(())
+}
+
+struct TreeEntry {
+    mode: String,
+    object_type: String,
+    hash: String,
+    name: String,
+}
+
+// fit writes trees as "<mode> <type> <hash> <name>" lines, where the name may itself contain
+// spaces. Trees written by git are instead "<mode> <name>\0" followed by the raw 20-byte
+// hash, with the type implied by the mode; a NUL never appears in fit's format, so it tells
+// them apart
+fn parse_tree(tree_hash: &str, content: &[u8]) -> FitResult<Vec<TreeEntry>> {
+    let malformed = |entry: &str| {
+        FitError::CorruptObject(format!("tree {}: malformed entry '{}'", tree_hash, entry))
+    };
+    let mut entries = Vec::new();
+    if !content.contains(&0) {
+        for line in String::from_utf8_lossy(content).lines() {
+            let mut parts = line.splitn(4, ' ');
+            let (Some(mode), Some(object_type), Some(hash), Some(name)) =
+                (parts.next(), parts.next(), parts.next(), parts.next())
+            else {
+                return Err(malformed(line));
+            };
+            entries.push(TreeEntry {
+                mode: mode.to_string(),
+                object_type: object_type.to_string(),
+                hash: hash.to_string(),
+                name: name.to_string(),
+            });
+        }
+        return Ok(entries);
+    }
 
-    // Read the tree object
-    let (_, tree_content) = read_object(tree_hash)?.unwrap();
-    let tree_content = String::from_utf8_lossy(&tree_content);
+    let mut rest = content;
+    while !rest.is_empty() {
+        let header_end = rest
+            .iter()
+            .position(|&byte| byte == 0)
+            .filter(|&end| rest.len() >= end + 21)
+            .ok_or_else(|| malformed(&String::from_utf8_lossy(rest)))?;
+        let header = String::from_utf8_lossy(&rest[..header_end]);
+        let (mode, name) = header.split_once(' ').ok_or_else(|| malformed(&header))?;
+        let object_type = match mode {
+            "40000" => "tree",
+            "160000" => "commit",
+            _ => "blob",
+        };
+        entries.push(TreeEntry {
+            mode: mode.to_string(),
+            object_type: object_type.to_string(),
+            hash: rest[header_end + 1..header_end + 21]
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect(),
+            name: name.to_string(),
+        });
+        rest = &rest[header_end + 21..];
+    }
+    Ok(entries)
+}
 
-    // Parse the tree content to get file hashes
-    let mut commit_files = HashMap::new();
-    for line in tree_content.lines() {
-        let parts: Vec<&str> = line.split_whitespace().collect();
-        let file_hash = parts[2];
-        let file_path = parts[3];
-        commit_files.insert(file_path.to_string(), file_hash.to_string());
+// Changes not yet staged: each tracked file on disk against its content in the index
+fn diff_working_tree_vs_index(options: &DiffOptions) -> FitResult<()> {
+    let mut index = read_index()?;
+    let (mut changes, index_refreshed) = unstaged_changes(&mut index)?;
+    changes.sort_by(|(_, a), (_, b)| a.cmp(b));
+    for (status, file) in changes {
+        let (_, indexed) = read_object(&index[&file].hash)?
+            .ok_or_else(|| FitError::ObjectNotFound(index[&file].hash.clone()))?;
+        let working = match status {
+            'D' => None,
+            _ => read_working_blob(&file)?,
+        };
+        let working = working.as_deref().map(String::from_utf8_lossy);
+        print_diff(
+            &file,
+            Some(&String::from_utf8_lossy(&indexed)),
+            working.as_deref(),
+            options,
+        );
+    }
+    if index_refreshed {
+        write_index(&index)?;
     }
+    Ok(())
+}
+
+fn diff_staged_vs_commit(commit: &str, options: &DiffOptions) -> FitResult<()> {
+    let index = read_index()?;
+    let commit_files = get_tree_files(&get_commit_tree(commit)?)?;
 
     // Compare staged files with commit files
-    for (file_path, staged_hash) in &index {
+    for (file_path, entry) in &index {
+        let staged_hash = &entry.hash;
         if let Some(commit_hash) = commit_files.get(file_path) {
             if staged_hash != commit_hash {
-                let (_, staged_content) = read_object(staged_hash)?.unwrap();
-                let (_, commit_content) = read_object(commit_hash)?.unwrap();
+                let staged_content = read_object_typed(staged_hash, "blob")?;
+                let commit_content = read_object_typed(commit_hash, "blob")?;
                 print_diff(
                     file_path,
-                    &String::from_utf8_lossy(&commit_content),
-                    &String::from_utf8_lossy(&staged_content),
+                    Some(&String::from_utf8_lossy(&commit_content)),
+                    Some(&String::from_utf8_lossy(&staged_content)),
+                    options,
                 );
             }
         } else {
             // New file in staging
-            let (_, staged_content) = read_object(staged_hash)?.unwrap();
-            print_diff(file_path, "", &String::from_utf8_lossy(&staged_content));
+            let staged_content = read_object_typed(staged_hash, "blob")?;
+            print_diff(
+                file_path,
+                None,
+                Some(&String::from_utf8_lossy(&staged_content)),
+                options,
+            );
         }
     }
 
     // Check for deleted files
     for (file_path, commit_hash) in &commit_files {
         if !index.contains_key(file_path) {
-            let (_, commit_content) = read_object(commit_hash)?.unwrap();
-            print_diff(file_path, &String::from_utf8_lossy(&commit_content), "");
+            let commit_content = read_object_typed(commit_hash, "blob")?;
+            print_diff(
+                file_path,
+                Some(&String::from_utf8_lossy(&commit_content)),
+                None,
+                options,
+            );
         }
     }
 
     Ok(())
 }
 
-fn print_diff(file_path: &str, old_content: &str, new_content: &str) {
-    println!("Diff for file: {}", file_path);
+enum LineChange<'a> {
+    Context(&'a str),
+    Removed(&'a str),
+    Added(&'a str),
+}
+
+fn diff_lines<'a>(
+    old_content: &'a str,
+    new_content: &'a str,
+    options: &DiffOptions,
+) -> Vec<LineChange<'a>> {
+    // Lines keep their "\n", so a final line without one never matches a final line with one
+    let old_lines: Vec<&str> = old_content.split_inclusive('\n').collect();
+    let new_lines: Vec<&str> = new_content.split_inclusive('\n').collect();
+
+    // Lines are matched on a comparison key but always printed as they appear in the file
+    let key = |line: &&str| -> String {
+        if options.ignore_all_space {
+            line.chars().filter(|c| !c.is_whitespace()).collect()
+        } else {
+            line.to_string()
+        }
+    };
+    let old_keys: Vec<String> = old_lines.iter().map(key).collect();
+    let new_keys: Vec<String> = new_lines.iter().map(key).collect();
+
+    let (mut old_index, mut new_index) = (0, 0);
+    diff::slice(&old_keys, &new_keys)
+        .into_iter()
+        .map(|change| match change {
+            diff::Result::Left(_) => {
+                old_index += 1;
+                LineChange::Removed(old_lines[old_index - 1])
+            }
+            diff::Result::Both(..) => {
+                old_index += 1;
+                new_index += 1;
+                LineChange::Context(new_lines[new_index - 1])
+            }
+            diff::Result::Right(_) => {
+                new_index += 1;
+                LineChange::Added(new_lines[new_index - 1])
+            }
+        })
+        .collect()
+}
+
+// Prints a unified diff covering the whole file. A side that's None doesn't exist, so an added
+// file diffs from /dev/null and a deleted one to it
+fn print_diff(
+    file_path: &str,
+    old_content: Option<&str>,
+    new_content: Option<&str>,
+    options: &DiffOptions,
+) {
+    let changes = diff_lines(
+        old_content.unwrap_or_default(),
+        new_content.unwrap_or_default(),
+        options,
+    );
+    if old_content.is_some() == new_content.is_some()
+        && changes
+            .iter()
+            .all(|change| matches!(change, LineChange::Context(_)))
+    {
+        return;
+    }
+    DIFF_FOUND.store(true, std::sync::atomic::Ordering::Relaxed);
+    if options.quiet {
+        return;
+    }
+
+    print!(
+        "{}",
+        diff_header(
+            file_path,
+            old_content.is_some(),
+            new_content.is_some(),
+            &changes
+        )
+    );
+    if options.color_words {
+        print_word_diff(&changes);
+    } else {
+        print!("{}", diff_body(&changes));
+    }
+}
+
+// The unified diff print_diff shows, as text, or None if the two sides are the same
+fn unified_diff(
+    file_path: &str,
+    old_content: Option<&str>,
+    new_content: Option<&str>,
+) -> Option<String> {
+    let changes = diff_lines(
+        old_content.unwrap_or_default(),
+        new_content.unwrap_or_default(),
+        &DiffOptions::default(),
+    );
+    if old_content.is_some() == new_content.is_some()
+        && changes
+            .iter()
+            .all(|change| matches!(change, LineChange::Context(_)))
+    {
+        return None;
+    }
+    Some(
+        diff_header(
+            file_path,
+            old_content.is_some(),
+            new_content.is_some(),
+            &changes,
+        ) + &diff_body(&changes),
+    )
+}
+
+// The file header lines, with the hunk header when there are lines to show; a missing side
+// is /dev/null
+fn diff_header(file_path: &str, has_old: bool, has_new: bool, changes: &[LineChange]) -> String {
+    let mut header = format!("diff --fit a/{} b/{}\n", file_path, file_path);
+    match has_old {
+        true => header.push_str(&format!("--- a/{}\n", file_path)),
+        false => header.push_str("--- /dev/null\n"),
+    }
+    match has_new {
+        true => header.push_str(&format!("+++ b/{}\n", file_path)),
+        false => header.push_str("+++ /dev/null\n"),
+    }
+    if changes.is_empty() {
+        return header;
+    }
+    let old_count = changes
+        .iter()
+        .filter(|change| !matches!(change, LineChange::Added(_)))
+        .count();
+    let new_count = changes
+        .iter()
+        .filter(|change| !matches!(change, LineChange::Removed(_)))
+        .count();
+    header.push_str(&format!(
+        "@@ -{} +{} @@\n",
+        hunk_range(old_count),
+        hunk_range(new_count)
+    ));
+    header
+}
+
+fn diff_body(changes: &[LineChange]) -> String {
+    changes
+        .iter()
+        .map(|change| match change {
+            LineChange::Context(line) => format_diff_line(' ', line) + "\n",
+            LineChange::Removed(line) => format_diff_line('-', line) + "\n",
+            LineChange::Added(line) => format_diff_line('+', line) + "\n",
+        })
+        .collect()
+}
+
+// The one hunk always starts at the first line, except on an empty side
+fn hunk_range(count: usize) -> String {
+    match count {
+        0 => "0,0".to_string(),
+        count => format!("1,{}", count),
+    }
+}
+
+// Drops the line's "\n", or flags its absence the way git does
+fn format_diff_line(prefix: char, line: &str) -> String {
+    match line.strip_suffix('\n') {
+        Some(line) => format!("{}{}", prefix, line),
+        None => format!("{}{}\n\\ No newline at end of file", prefix, line),
+    }
+}
+
+const COLOR_REMOVED: &str = "\x1b[31m";
+const COLOR_ADDED: &str = "\x1b[32m";
+const COLOR_RESET: &str = "\x1b[m";
+
+// Pairs each run of removed lines with the added lines that replace it and highlights
+// only the words that changed between them
+fn print_word_diff(changes: &[LineChange]) {
+    let mut removed = Vec::new();
+    let mut added = Vec::new();
+    for (i, change) in changes.iter().enumerate() {
+        match change {
+            LineChange::Context(line) => println!("{}", line.trim_end_matches('\n')),
+            LineChange::Removed(line) => removed.push(line.trim_end_matches('\n')),
+            LineChange::Added(line) => added.push(line.trim_end_matches('\n')),
+        }
+        let run_ends = !matches!(
+            changes.get(i + 1),
+            Some(LineChange::Removed(_)) | Some(LineChange::Added(_))
+        );
+        if run_ends && (!removed.is_empty() || !added.is_empty()) {
+            for pair in 0..removed.len().max(added.len()) {
+                print_word_diff_line(removed.get(pair).copied(), added.get(pair).copied());
+            }
+            removed.clear();
+            added.clear();
+        }
+    }
+}
+
+fn print_word_diff_line(old_line: Option<&str>, new_line: Option<&str>) {
+    let (old_line, new_line) = match (old_line, new_line) {
+        (Some(old_line), Some(new_line)) => (old_line, new_line),
+        (Some(old_line), None) => return println!("{}{}{}", COLOR_REMOVED, old_line, COLOR_RESET),
+        (None, Some(new_line)) => return println!("{}{}{}", COLOR_ADDED, new_line, COLOR_RESET),
+        (None, None) => return,
+    };
 
-    let diff = diff::lines(old_content, new_content);
+    let old_words = split_words(old_line);
+    let new_words = split_words(new_line);
+    let word_changes = diff::slice(&old_words, &new_words);
+
+    // Lines that share no words read better as a plain removal and addition
+    let shares_words = word_changes
+        .iter()
+        .any(|change| matches!(change, diff::Result::Both(word, _) if !word.trim().is_empty()));
+    if !shares_words {
+        println!("{}{}{}", COLOR_REMOVED, old_line, COLOR_RESET);
+        println!("{}{}{}", COLOR_ADDED, new_line, COLOR_RESET);
+        return;
+    }
 
-    for change in diff {
+    let mut rendered = String::new();
+    for change in word_changes {
         match change {
-            diff::Result::Left(l) => println!("-{}", l),
-            diff::Result::Both(l, _) => println!(" {}", l),
-            diff::Result::Right(r) => println!("+{}", r),
+            diff::Result::Both(word, _) => rendered.push_str(word),
+            diff::Result::Left(word) => {
+                rendered.push_str(&format!("{}{}{}", COLOR_REMOVED, word, COLOR_RESET))
+            }
+            diff::Result::Right(word) => {
+                rendered.push_str(&format!("{}{}{}", COLOR_ADDED, word, COLOR_RESET))
+            }
+        }
+    }
+    println!("{}", rendered);
+}
+
+// Splits a line into alternating runs of word and whitespace characters
+fn split_words(line: &str) -> Vec<&str> {
+    let mut words = Vec::new();
+    let mut start = 0;
+    let mut chars = line.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        let is_boundary = chars
+            .peek()
+            .is_none_or(|(_, next)| next.is_whitespace() != c.is_whitespace());
+        if is_boundary {
+            let end = i + c.len_utf8();
+            words.push(&line[start..end]);
+            start = end;
         }
     }
+    words
+}
 
-    println!();
+// Refuses to start `action` over uncommitted changes or an unfinished merge or rebase
+fn ensure_clean_work_tree(action: &str) -> FitResult<()> {
+    if fit_path("MERGE_STATE").exists() {
+        return Err(FitError::Refused(format!(
+            "cannot {}, a merge or rebase is in progress (see .fit/MERGE_STATE)",
+            action
+        )));
+    }
+    let mut index = read_index()?;
+    let staging_area = read_staging_area()?;
+    let has_staged = !staging_area.added.is_empty()
+        || !staging_area.modified.is_empty()
+        || !staging_area.deleted.is_empty();
+    if has_staged || !unstaged_changes(&mut index)?.0.is_empty() {
+        return Err(FitError::Refused(format!(
+            "cannot {} with uncommitted changes, commit or stash them first",
+            action
+        )));
+    }
+    Ok(())
 }
 
-fn merge_workflow(args: MergeArgs) -> io::Result<()> {
+fn merge_workflow(args: MergeArgs) -> FitResult<()> {
     let current_branch = get_current_branch()?;
-    if current_branch == args.branch {
-        return Err(io::Error::new(
-            io::ErrorKind::InvalidInput,
-            "cannot merge a branch into itself",
+    if current_branch.as_deref() == Some(args.branch.as_str()) {
+        return Err(FitError::Refused(
+            "cannot merge a branch into itself".to_string(),
         ));
     }
-    if args.branch == "master" || current_branch != "master" {
-        return Err(io::Error::new(
-            io::ErrorKind::PermissionDenied,
-            "cannot merge master into Non-Head branch",
+    if args.branch == "master" || current_branch.as_deref() != Some("master") {
+        return Err(FitError::Refused(
+            "cannot merge master into Non-Head branch".to_string(),
         ));
     }
-    println!("Merging {} into master...", args.branch);
-    let current_commit = get_current_commit()?;
+    info!("Merging {} into master...", args.branch);
     let branch_commit = get_branch_commit(&args.branch)?;
+    if args.squash {
+        return squash_merge(&args.branch, &branch_commit);
+    }
+    merge_into_current(&args.branch, &branch_commit)
+}
 
-    if current_commit == branch_commit {
-        println!("Already up to date. Nothing to merge.");
+// Applies everything `their_commit` changed since the merge base to the index and working
+// tree as staged changes, leaving HEAD where it is so the next commit has a single parent
+fn squash_merge(name: &str, their_commit: &str) -> FitResult<()> {
+    let current_commit = get_current_commit()?;
+    let merge_base = find_merge_base(&current_commit, their_commit)?;
+    if current_commit == their_commit || merge_base == their_commit {
+        info!("Already up to date. Nothing to merge.");
         return Ok(());
     }
+    ensure_clean_work_tree("merge")?;
+
+    let [(base_files, base_modes), (our_files, our_modes), (their_files, their_modes)] =
+        merge_sides([&merge_base, &current_commit, their_commit])?;
+    let (merged, conflicts) = merge_trees(&base_files, &our_files, &their_files);
+    let modes = merged_modes(
+        &merged,
+        &base_modes,
+        (&our_files, &our_modes),
+        (&their_files, &their_modes),
+    );
+    apply_merge_result(name, &our_files, &their_files, &merged, &modes, &conflicts)?;
+    if !conflicts.is_empty() {
+        return Err(FitError::MergeConflict(format!(
+            "squash merge failed in {}; fix the conflicts, fit add the files and commit the result",
+            conflicts.join(", ")
+        )));
+    }
+    info!("Squash commit -- not updating HEAD, commit the staged changes to finish");
+    Ok(())
+}
 
-    let merge_base = find_merge_base(&current_commit, &branch_commit)?;
+// Brings `their_commit` (named `name` in messages) into the current branch: nothing if it's
+// already contained, a fast-forward if the branch hasn't diverged, otherwise a merge commit
+fn merge_into_current(name: &str, their_commit: &str) -> FitResult<()> {
+    let current_commit = get_current_commit()?;
+    if current_commit == their_commit {
+        info!("Already up to date. Nothing to merge.");
+        return Ok(());
+    }
 
-    if merge_base == branch_commit {
-        println!("Fast-forward merge possible.");
-        fast_forward_merge(&branch_commit)?;
-    } else {
-        println!("Performing three-way merge.");
-        // three_way_merge(&current_commit, &branch_commit, &merge_base)?;
+    let merge_base = find_merge_base(&current_commit, their_commit)?;
+    if merge_base == their_commit {
+        info!("Already up to date. Nothing to merge.");
+        return Ok(());
+    }
+    ensure_clean_work_tree("merge")?;
+    if merge_base == current_commit {
+        info!("Fast-forward merge possible.");
+        return fast_forward_merge(name, their_commit);
+    }
+
+    info!("Performing three-way merge.");
+    three_way_merge(name, &current_commit, their_commit, &merge_base)
+}
+
+// Commits the merge when no file was changed differently on both sides. Otherwise the clean
+// part of the merge is staged, conflicted files are written out with markers and MERGE_STATE
+// records the other parent, so the commit that resolves them becomes the merge commit
+fn three_way_merge(
+    name: &str,
+    current_commit: &str,
+    their_commit: &str,
+    merge_base: &str,
+) -> FitResult<()> {
+    let [(base_files, base_modes), (our_files, our_modes), (their_files, their_modes)] =
+        merge_sides([merge_base, current_commit, their_commit])?;
+    let (merged, conflicts) = merge_trees(&base_files, &our_files, &their_files);
+    let modes = merged_modes(
+        &merged,
+        &base_modes,
+        (&our_files, &our_modes),
+        (&their_files, &their_modes),
+    );
+
+    if conflicts.is_empty() {
+        let merged_index = merged
+            .iter()
+            .map(|(path, hash)| {
+                let entry = IndexEntry::unstatted(hash).with_mode(modes[path]);
+                (path.clone(), entry)
+            })
+            .collect();
+        let tree_hash = create_tree_object(&merged_index)?;
+        let commit_content = format!(
+            "tree {}\nparent {}\nparent {}\nauthor {}\ncommitter {}\n\nMerge branch '{}'",
+            tree_hash,
+            current_commit,
+            their_commit,
+            default_signature()?.format(),
+            committer_signature()?.format(),
+            name
+        );
+        let merge_commit = write_object(commit_content.as_bytes(), "commit")?;
+        update_current_branch(
+            &merge_commit,
+            &format!("merge {}: Merge made by three-way", name),
+        )?;
+        reset_workflow(&merge_commit)?;
+        info!("Merge made by three-way strategy: {}", merge_commit);
+        return Ok(());
+    }
+
+    apply_merge_result(name, &our_files, &their_files, &merged, &modes, &conflicts)?;
+    fs::write(
+        fit_path("MERGE_STATE"),
+        format!(
+            "merge\nmerge-head {}\n{}",
+            their_commit,
+            conflict_lines(&conflicts)
+        ),
+    )?;
+    Err(FitError::MergeConflict(format!(
+        "automatic merge failed in {}; fix the conflicts, fit add the files and commit the result",
+        conflicts.join(", ")
+    )))
+}
+
+// MERGE_STATE lists each conflicted path as "conflict <path>" until it's resolved
+fn conflict_lines(conflicts: &[String]) -> String {
+    conflicts
+        .iter()
+        .map(|path| format!("conflict {}\n", path))
+        .collect()
+}
+
+// Stages and writes out every file the merge changed on our side, and leaves conflicted
+// files in the working tree with markers for the user to resolve
+fn apply_merge_result(
+    name: &str,
+    our_files: &HashMap<String, String>,
+    their_files: &HashMap<String, String>,
+    merged: &HashMap<String, String>,
+    modes: &HashMap<String, u32>,
+    conflicts: &[String],
+) -> FitResult<()> {
+    let mut staging_area = read_staging_area()?;
+    let mut index = read_index()?;
+    for (path, hash) in merged {
+        let mode = modes[path];
+        if our_files.get(path) == Some(hash)
+            && index.get(path).map(|entry| entry.mode) == Some(mode)
+        {
+            continue;
+        }
+        let (_, content) =
+            read_object(hash)?.ok_or_else(|| FitError::ObjectNotFound(hash.clone()))?;
+        if let Some(parent) = Path::new(path).parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let metadata = write_working_file(path, content, mode)?;
+        stage_blob(path, hash, mode, &mut staging_area, &index);
+        index.insert(path.clone(), IndexEntry::new(hash.clone(), &metadata));
+    }
+    for path in our_files.keys() {
+        if !merged.contains_key(path) && !conflicts.contains(path) {
+            if Path::new(path).exists() {
+                fs::remove_file(path)?;
+            }
+            index.remove(path);
+            staging_area.delete(path.clone());
+        }
     }
+    for path in conflicts {
+        write_conflict(path, our_files.get(path), their_files.get(path), name)?;
+    }
+    write_staging_area(&staging_area)?;
+    write_index(&index)?;
+    Ok(())
+}
 
+fn merge_base_workflow(args: MergeBaseArgs) -> FitResult<()> {
+    let commit1 = resolve_ref(&args.commit1)?;
+    let commit2 = resolve_ref(&args.commit2)?;
+    println!("{}", find_merge_base(&commit1, &commit2)?);
     Ok(())
 }
 
-fn get_branch_commit(branch_name: &str) -> io::Result<String> {
-    let branch_path = Path::new(".fit/refs/heads").join(branch_name);
-    if !branch_path.exists() {
-        return Err(io::Error::new(io::ErrorKind::NotFound, "Branch not found"));
+// Replays the current branch's commits since it forked from `upstream` on top of `upstream`.
+// A conflict stops the replay with the conflicting files marked up and the state recorded
+// in MERGE_STATE, from where `--continue` carries on once they're resolved and staged, and
+// `--abort` returns the branch to where it started
+fn rebase_workflow(args: RebaseArgs) -> FitResult<()> {
+    let merge_state_path = fit_path("MERGE_STATE");
+    if args.abort {
+        let merge_state = fs::read_to_string(&merge_state_path)
+            .map_err(|_| FitError::Refused("no rebase in progress".to_string()))?;
+        let orig_head = merge_state
+            .lines()
+            .find_map(|line| line.strip_prefix("orig-head "))
+            .ok_or_else(|| FitError::Refused("MERGE_STATE has no orig-head".to_string()))?;
+        reset_workflow(orig_head)?;
+        fs::remove_file(&merge_state_path)?;
+        info!("Rebase aborted");
+        return Ok(());
     }
-    Ok(fs::read_to_string(branch_path)?.trim().to_string())
+    if args.continue_rebase {
+        return continue_rebase();
+    }
+    ensure_clean_work_tree("rebase")?;
+
+    let upstream_name = args
+        .upstream
+        .expect("clap requires upstream without --abort or --continue");
+    let upstream = resolve_ref(&upstream_name)?;
+    let orig_head = get_current_commit()?;
+    let merge_base = find_merge_base(&orig_head, &upstream)?;
+    if merge_base == upstream {
+        info!("Current branch is up to date.");
+        return Ok(());
+    }
+
+    let to_replay = commits_to_replay(&orig_head, &merge_base)?;
+    reset_workflow(&upstream)?;
+    replay_commits(&to_replay, &orig_head, &upstream)?;
+    info!("Successfully rebased onto {}", upstream_name);
+    Ok(())
 }
 
-fn find_merge_base(current_commit: &str, branch_commit: &str) -> io::Result<String> {
-    let commit_history_1 = get_commit_history(current_commit)?;
-    let commit_history_2 = get_commit_history(branch_commit)?;
+// Commits to replay, oldest first, following first parents back to the fork point
+fn commits_to_replay(orig_head: &str, merge_base: &str) -> FitResult<Vec<String>> {
+    let mut to_replay = Vec::new();
+    let mut current = orig_head.to_string();
+    while current != merge_base && !current.is_empty() {
+        let (_, content) =
+            read_object(&current)?.ok_or_else(|| FitError::ObjectNotFound(current.clone()))?;
+        let parent = get_parent_commit(&String::from_utf8_lossy(&content));
+        to_replay.push(current);
+        current = parent;
+    }
+    to_replay.reverse();
+    Ok(to_replay)
+}
 
-    for commit in commit_history_2 {
-        if commit_history_1.contains(&commit) {
-            return Ok(commit);
+// Replays each commit on top of HEAD. On a conflict the clean part of the commit is staged
+// as in a merge, the conflicted files get markers, and MERGE_STATE records where it stopped
+fn replay_commits(to_replay: &[String], orig_head: &str, upstream: &str) -> FitResult<()> {
+    for commit in to_replay {
+        let (_, content) =
+            read_object(commit)?.ok_or_else(|| FitError::ObjectNotFound(commit.clone()))?;
+        let content = String::from_utf8_lossy(&content);
+        let parent = get_parent_commit(&content);
+        let head = get_current_commit()?;
+
+        let [(base_files, base_modes), (head_files, head_modes), (commit_files, commit_modes)] =
+            merge_sides([&parent, &head, commit])?;
+        let (merged, conflicts) = merge_trees(&base_files, &head_files, &commit_files);
+        let modes = merged_modes(
+            &merged,
+            &base_modes,
+            (&head_files, &head_modes),
+            (&commit_files, &commit_modes),
+        );
+        if !conflicts.is_empty() {
+            apply_merge_result(
+                commit,
+                &head_files,
+                &commit_files,
+                &merged,
+                &modes,
+                &conflicts,
+            )?;
+            fs::write(
+                fit_path("MERGE_STATE"),
+                format!(
+                    "rebase\norig-head {}\nonto {}\nstopped-at {}\n{}",
+                    orig_head,
+                    upstream,
+                    commit,
+                    conflict_lines(&conflicts)
+                ),
+            )?;
+            return Err(FitError::MergeConflict(format!(
+                "could not apply {} ({}); fix the conflicts, fit add the files and run `fit rebase --continue`, or `fit rebase --abort` to give up",
+                &commit[..7],
+                conflicts.join(", ")
+            )));
         }
+
+        let merged_index = merged
+            .iter()
+            .map(|(path, hash)| {
+                let entry = IndexEntry::unstatted(hash).with_mode(modes[path]);
+                (path.clone(), entry)
+            })
+            .collect();
+        let tree_hash = create_tree_object(&merged_index)?;
+        let replayed_hash = write_replayed_commit(&content, &tree_hash, &head)?;
+        reset_workflow(&replayed_hash)?;
+        info!("Applied {} as {}", &commit[..7], &replayed_hash[..7]);
     }
-    Err(io::Error::new(
-        io::ErrorKind::NotFound,
-        "Merge Base not found",
-    ))
+    Ok(())
 }
 
-fn get_commit_history(commit: &str) -> io::Result<Vec<String>> {
-    let mut history = Vec::new();
-    let mut current = commit.to_string();
+// Writes `commit_content`'s author and message as a new commit of `tree_hash` on `parent`
+// and moves the current branch to it
+fn write_replayed_commit(commit_content: &str, tree_hash: &str, parent: &str) -> FitResult<String> {
+    let (commit_info, message) = commit_content
+        .split_once("\n\n")
+        .unwrap_or((commit_content, ""));
+    let mut replayed = format!("tree {}\nparent {}\n", tree_hash, parent);
+    if let Some(author) = commit_info.lines().find(|line| line.starts_with("author ")) {
+        replayed.push_str(&format!("{}\n", author));
+    }
+    replayed.push_str(&format!(
+        "committer {}\n\n{}",
+        committer_signature()?.format(),
+        message
+    ));
+    let replayed_hash = write_object(replayed.as_bytes(), "commit")?;
+    let summary = message.lines().next().unwrap_or_default();
+    update_current_branch(&replayed_hash, &format!("rebase: {}", summary))?;
+    Ok(replayed_hash)
+}
+
+// Commits the staged resolution as the commit the rebase stopped on, keeping its author and
+// message, then replays the commits after it. A resolution that leaves HEAD's tree as it is
+// drops the commit instead
+fn continue_rebase() -> FitResult<()> {
+    let merge_state = fs::read_to_string(fit_path("MERGE_STATE"))
+        .ok()
+        .filter(|state| state.starts_with("rebase\n"))
+        .ok_or_else(|| FitError::Refused("no rebase in progress".to_string()))?;
+    let field = |name: &str| -> FitResult<String> {
+        merge_state
+            .lines()
+            .find_map(|line| line.strip_prefix(name)?.strip_prefix(' '))
+            .map(str::to_string)
+            .ok_or_else(|| FitError::Refused(format!("MERGE_STATE has no {}", name)))
+    };
+    let (orig_head, onto, stopped_at) = (field("orig-head")?, field("onto")?, field("stopped-at")?);
+
+    let mut index = read_index()?;
+    if !unstaged_changes(&mut index)?.0.is_empty() {
+        return Err(FitError::Refused(
+            "cannot continue with unstaged changes, fit add the resolved files first".to_string(),
+        ));
+    }
+    apply_staged_changes(&mut index, &read_staging_area()?);
+    let head = get_current_commit()?;
+    let tree_hash = create_tree_object(&index)?;
+    if tree_hash == get_commit_tree(&head)? {
+        info!("Dropped {}, nothing left to commit", &stopped_at[..7]);
+    } else {
+        let (_, content) = read_object(&stopped_at)?
+            .ok_or_else(|| FitError::ObjectNotFound(stopped_at.clone()))?;
+        let replayed_hash =
+            write_replayed_commit(&String::from_utf8_lossy(&content), &tree_hash, &head)?;
+        reset_workflow(&replayed_hash)?;
+        info!("Applied {} as {}", &stopped_at[..7], &replayed_hash[..7]);
+    }
+    fs::remove_file(fit_path("MERGE_STATE"))?;
+
+    let remaining: Vec<String> =
+        commits_to_replay(&orig_head, &find_merge_base(&orig_head, &onto)?)?
+            .into_iter()
+            .skip_while(|commit| *commit != stopped_at)
+            .skip(1)
+            .collect();
+    replay_commits(&remaining, &orig_head, &onto)?;
+    info!("Successfully rebased onto {}", &onto[..7]);
+    Ok(())
+}
+
+// File-level three-way merge of flat path -> blob maps. A path only one side changed takes
+// that side; a path both sides changed differently is a conflict and left out of the result
+fn merge_trees(
+    base: &HashMap<String, String>,
+    ours: &HashMap<String, String>,
+    theirs: &HashMap<String, String>,
+) -> (HashMap<String, String>, Vec<String>) {
+    let mut paths: Vec<&String> = base
+        .keys()
+        .chain(ours.keys())
+        .chain(theirs.keys())
+        .collect();
+    paths.sort();
+    paths.dedup();
+
+    let mut merged = HashMap::new();
+    let mut conflicts = Vec::new();
+    for path in paths {
+        let (base_hash, our_hash, their_hash) = (base.get(path), ours.get(path), theirs.get(path));
+        let result = if our_hash == their_hash || base_hash == their_hash {
+            our_hash
+        } else if base_hash == our_hash {
+            their_hash
+        } else {
+            conflicts.push(path.clone());
+            continue;
+        };
+        if let Some(hash) = result {
+            merged.insert(path.clone(), hash.clone());
+        }
+    }
+    (merged, conflicts)
+}
+
+// Writes both sides of a conflicted file into the working tree between git-style markers
+fn write_conflict(
+    path: &str,
+    our_hash: Option<&String>,
+    their_hash: Option<&String>,
+    their_name: &str,
+) -> FitResult<()> {
+    let side = |hash: Option<&String>| -> FitResult<Vec<u8>> {
+        match hash {
+            Some(hash) => Ok(read_object(hash)?
+                .ok_or_else(|| FitError::ObjectNotFound(hash.clone()))?
+                .1),
+            None => Ok(Vec::new()),
+        }
+    };
+    let mut content = b"<<<<<<< HEAD\n".to_vec();
+    content.extend(side(our_hash)?);
+    if !content.ends_with(b"\n") {
+        content.push(b'\n');
+    }
+    content.extend(b"=======\n");
+    content.extend(side(their_hash)?);
+    if !content.ends_with(b"\n") {
+        content.push(b'\n');
+    }
+    content.extend(format!(">>>>>>> {}\n", their_name).as_bytes());
+
+    if let Some(parent) = Path::new(path).parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, convert_to_working(content)?)?;
+    Ok(())
+}
+
+fn get_branch_commit(branch_name: &str) -> FitResult<String> {
+    let branch_path = fit_path("refs/heads").join(branch_name);
+    if !branch_path.exists() {
+        return Err(FitError::BranchNotFound(branch_name.to_string()));
+    }
+    Ok(fs::read_to_string(branch_path)?.trim().to_string())
+}
+
+// Picks the best common ancestor: one that isn't itself an ancestor of another common
+// ancestor, so a merged-in branch doesn't drag the base back to an older fork point. Every
+// ancestor of a common ancestor is common too, so the ones to skip are exactly the common
+// commits that are a parent of another common commit, found with one read of each
+fn find_merge_base(current_commit: &str, branch_commit: &str) -> FitResult<String> {
+    let commit_history_1: HashSet<String> =
+        get_commit_history(current_commit)?.into_iter().collect();
+    let common: Vec<String> = get_commit_history(branch_commit)?
+        .into_iter()
+        .filter(|commit| commit_history_1.contains(commit))
+        .collect();
+
+    let mut superseded = HashSet::new();
+    for commit in &common {
+        let (_, content) =
+            read_object(commit)?.ok_or_else(|| FitError::ObjectNotFound(commit.clone()))?;
+        superseded.extend(get_parent_commits(&String::from_utf8_lossy(&content)));
+    }
+    common
+        .into_iter()
+        .find(|commit| !superseded.contains(commit))
+        .ok_or(FitError::NoMergeBase)
+}
 
-    while !current.is_empty() {
-        history.push(current.clone());
-        current = get_parent_commit(&read_object(&current)?.unwrap().0);
+// Every ancestor of `commit` (itself included) in depth-first order, following all parents
+fn get_commit_history(commit: &str) -> FitResult<Vec<String>> {
+    let parents_of = |commit: &str| -> FitResult<Vec<String>> {
+        let (_, content) =
+            read_object(commit)?.ok_or_else(|| FitError::ObjectNotFound(commit.to_string()))?;
+        let mut parents = get_parent_commits(&String::from_utf8_lossy(&content));
+        // Parents are popped off the end, so reverse to walk the first parent first
+        parents.reverse();
+        Ok(parents)
+    };
+
+    let mut history = vec![commit.to_string()];
+    // A commit maps to false while its ancestors are still being walked, true once finished
+    let mut finished = HashMap::from([(commit.to_string(), false)]);
+    let mut stack = vec![(commit.to_string(), parents_of(commit)?)];
+
+    while let Some((current, parents)) = stack.last_mut() {
+        let Some(parent) = parents.pop() else {
+            finished.insert(current.clone(), true);
+            stack.pop();
+            continue;
+        };
+        match finished.get(&parent) {
+            Some(false) => return Err(commit_cycle(&parent)),
+            Some(true) => {}
+            None => {
+                finished.insert(parent.clone(), false);
+                history.push(parent.clone());
+                let grandparents = parents_of(&parent)?;
+                stack.push((parent, grandparents));
+            }
+        }
     }
 
     Ok(history)
 }
 
-fn fast_forward_merge(branch_commit: &str) -> io::Result<()> {
-    update_current_branch(branch_commit)?;
+// A commit can only be its own ancestor if the object store has been tampered with
+fn commit_cycle(commit: &str) -> FitError {
+    FitError::CorruptObject(format!("commit {} is its own ancestor", commit))
+}
+
+fn fast_forward_merge(branch: &str, branch_commit: &str) -> FitResult<()> {
+    update_current_branch(branch_commit, &format!("merge {}: Fast-forward", branch))?;
     reset_workflow(branch_commit)?;
-    println!("Fast-forward merge completed.");
+    info!("Fast-forward merge completed.");
     Ok(())
 }
 
-fn stash_workflow(args: StashArgs) -> io::Result<()> {
+fn stash_workflow(args: StashArgs) -> FitResult<()> {
     match args.command {
         Some(StashSubCommand::Pop) => {
             pop_stashed_content()?;
         }
+        Some(StashSubCommand::Show { stash, name_only }) => {
+            show_stash(stash.as_deref(), name_only)?;
+        }
+        Some(StashSubCommand::Save { message }) => {
+            stash_content(message)?;
+        }
+        Some(StashSubCommand::List) => {
+            for (position, stash_hash) in read_stash_list()?.iter().enumerate() {
+                let content = read_object_typed(stash_hash, "commit")?;
+                let content = String::from_utf8_lossy(&content);
+                let message = content
+                    .split_once("\n\n")
+                    .map_or("", |(_, message)| message);
+                println!("stash@{{{}}}: {}", position, message.trim_end());
+            }
+        }
         None => {
-            stash_content()?;
+            stash_content(None)?;
         }
     }
     Ok(())
@@ -945,11 +8203,25 @@ fn stash_workflow(args: StashArgs) -> io::Result<()> {
 // Which represents the contents of the pwd at that given instance, then a reset is made to the previous commit leaving the STASH hash saved
 // then when stash pop is called, this STASH hash is reset, if consecutive Stashes are made then it creates a stack
 // following LIFO principle, most recent stash will be restored
-fn stash_content() -> io::Result<()> {
+fn stash_content(message: Option<String>) -> FitResult<()> {
     let index = read_index()?;
     let tree_hash = create_tree_object(&index)?;
     let parent_hash = get_current_commit()?;
-    let commit_content = format!("tree {}\nparent {}\n\n{}", tree_hash, parent_hash, "stash");
+    // Unnamed stashes are described by where they were made, as git does
+    let message = match message {
+        Some(message) => message,
+        None => {
+            let parent = read_object_typed(&parent_hash, "commit")?;
+            let parent = String::from_utf8_lossy(&parent);
+            let subject = parent
+                .split_once("\n\n")
+                .and_then(|(_, message)| message.lines().next())
+                .unwrap_or_default();
+            let branch = get_current_branch()?.unwrap_or_else(|| "(no branch)".to_string());
+            format!("WIP on {}: {} {}", branch, &parent_hash[..7], subject)
+        }
+    };
+    let commit_content = format!("tree {}\nparent {}\n\n{}", tree_hash, parent_hash, message);
 
     let stash_hash = write_object(commit_content.as_bytes(), "commit")?;
     write_stashing_area(&stash_hash)?;
@@ -957,47 +8229,88 @@ fn stash_content() -> io::Result<()> {
     Ok(())
 }
 
-fn read_stashing_area() -> io::Result<Option<String>> {
-    let st_path = ".fit/STASH";
-    if !Path::new(st_path).exists() {
+fn read_stash_list() -> FitResult<Vec<String>> {
+    let content = fs::read_to_string(fit_path("STASH")).unwrap_or_default();
+    Ok(content
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(|line| line.to_string())
+        .collect())
+}
+
+fn read_stashing_area() -> FitResult<Option<String>> {
+    let st_path = fit_path("STASH");
+    if !st_path.exists() {
         return Ok(None);
     }
 
-    let content = fs::read_to_string(st_path)?;
-
-    let mut lines: Vec<&str> = content.lines().collect();
+    let mut lines = read_stash_list()?;
 
     if lines.is_empty() {
         return Ok(None);
     }
 
-    let topmost_stash = lines.remove(0).to_string();
+    let topmost_stash = lines.remove(0);
 
     let updated_content = lines.join("\n");
-    fs::write(st_path, updated_content)?;
+    write_ref(&st_path, &updated_content)?;
 
     Ok(Some(topmost_stash))
 }
 
-fn write_stashing_area(stash_hash: &str) -> io::Result<()> {
-    let st_path = ".fit/STASH";
+fn write_stashing_area(stash_hash: &str) -> FitResult<()> {
+    let st_path = fit_path("STASH");
 
-    let existing_content = fs::read_to_string(st_path).unwrap_or_default();
+    let existing_content = fs::read_to_string(&st_path).unwrap_or_default();
 
     let updated_content = format!("{}\n{}", stash_hash, existing_content.trim());
 
-    fs::write(st_path, updated_content)
+    write_ref(&st_path, &updated_content)?;
+    Ok(())
 }
 
-fn pop_stashed_content() -> io::Result<()> {
+fn pop_stashed_content() -> FitResult<()> {
     match read_stashing_area()? {
         Some(latest_hash) => {
             reset_workflow(&latest_hash)?;
             Ok(())
         }
-        None => Err(Error::new(
-            io::ErrorKind::NotFound,
-            "cannot pop, stash something first",
+        None => Err(FitError::Refused(
+            "cannot pop, stash something first".to_string(),
         )),
     }
 }
+
+// Diffs a stash commit against the commit it was made on top of; `stash` is "stash@{n}" or "n"
+fn show_stash(stash: Option<&str>, name_only: bool) -> FitResult<()> {
+    let position = match stash {
+        Some(stash) => {
+            let position = stash
+                .strip_prefix("stash@{")
+                .and_then(|rest| rest.strip_suffix('}'))
+                .unwrap_or(stash);
+            position.parse::<usize>().map_err(|_| {
+                FitError::InvalidRef(format!("'{}' is not a valid stash reference", stash))
+            })?
+        }
+        None => 0,
+    };
+    let stashes = read_stash_list()?;
+    let stash_hash = stashes
+        .get(position)
+        .ok_or_else(|| FitError::InvalidRef(format!("stash@{{{}}} does not exist", position)))?;
+
+    let stash_content = read_object_typed(stash_hash, "commit")?;
+    let parent_hash = get_parent_commit(&String::from_utf8_lossy(&stash_content));
+    let parent_files = get_tree_files(&get_commit_tree(&parent_hash)?)?;
+    let stash_files = get_tree_files(&get_commit_tree(stash_hash)?)?;
+
+    if name_only {
+        for (_, path) in changed_files(&parent_files, &stash_files) {
+            println!("{}", path);
+        }
+        Ok(())
+    } else {
+        diff_tree_files(&parent_files, &stash_files, &DiffOptions::default())
+    }
+}
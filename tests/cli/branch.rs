@@ -0,0 +1,46 @@
+use crate::common::*;
+
+#[test]
+fn branch_delete_removes_several_branches() {
+    let repo = Repo::new();
+    repo.commit_file("a.txt", "a\n", "first");
+    repo.ok(&["branch", "create", "one"]);
+    repo.ok(&["branch", "create", "two"]);
+
+    let output = repo.ok(&["branch", "delete", "one", "two"]);
+
+    assert_eq!(output, "Deleted branch 'one'\nDeleted branch 'two'\n");
+    assert_eq!(repo.read_ref("refs/heads/one"), None);
+    assert_eq!(repo.read_ref("refs/heads/two"), None);
+}
+
+#[test]
+fn branch_delete_refuses_unmerged_branch_without_force() {
+    let repo = Repo::new();
+    repo.commit_file("a.txt", "a\n", "first");
+    repo.ok(&["branch", "checkout-new", "feature"]);
+    let feature = repo.commit_file("b.txt", "b\n", "feature work");
+    repo.ok(&["branch", "checkout", "master"]);
+
+    let error = repo.fails(&["branch", "delete", "feature"], 1);
+    assert!(error.contains("not fully merged"), "{}", error);
+    assert_eq!(repo.read_ref("refs/heads/feature"), Some(feature));
+
+    repo.ok(&["branch", "delete", "-D", "feature"]);
+    assert_eq!(repo.read_ref("refs/heads/feature"), None);
+}
+
+#[test]
+fn branch_delete_reports_each_failure_once() {
+    let repo = Repo::new();
+
+    let error = repo.fails(&["branch", "delete", "missing", "gone"], 1);
+
+    assert_eq!(error.matches("'missing' not found").count(), 1, "{}", error);
+    assert_eq!(error.matches("'gone' not found").count(), 1, "{}", error);
+    assert!(
+        error.contains("failed to delete 2 of 2 branches"),
+        "{}",
+        error
+    );
+}
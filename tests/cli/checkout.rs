@@ -0,0 +1,38 @@
+use crate::common::*;
+
+#[test]
+fn checkout_path_restores_the_committed_content() {
+    let repo = Repo::new();
+    repo.commit_file("a.txt", "committed\n", "first");
+    repo.write("a.txt", "scribbled over\n");
+    repo.write("b.txt", "untracked\n");
+
+    repo.ok(&["checkout", "--", "a.txt"]);
+
+    assert_eq!(repo.read("a.txt"), "committed\n");
+    assert_eq!(repo.read("b.txt"), "untracked\n");
+}
+
+#[test]
+fn checkout_path_from_an_older_commit_writes_and_stages_it() {
+    let repo = Repo::new();
+    let old = repo.commit_file("a.txt", "old version\n", "first");
+    repo.commit_file("a.txt", "new version\n", "second");
+
+    repo.ok(&["checkout", &old, "--", "a.txt"]);
+
+    assert_eq!(repo.read("a.txt"), "old version\n");
+    let status = repo.ok(&["status"]);
+    assert_eq!(
+        status_section(&status, "Changes to be committed:"),
+        ["modified: a.txt"]
+    );
+    assert!(
+        status_section(&status, "Changes not staged for commit:").is_empty(),
+        "{}",
+        status
+    );
+    let staged = repo.staging();
+    let hash = staged.split(' ').nth(1).unwrap();
+    assert_eq!(repo.object_text(hash), "old version\n");
+}
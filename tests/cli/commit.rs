@@ -0,0 +1,234 @@
+use crate::common::*;
+
+#[test]
+fn commit_records_author_and_date_overrides() {
+    let repo = Repo::new();
+    repo.write("a.txt", "a\n");
+    repo.ok(&["add", "a.txt"]);
+    repo.ok(&[
+        "commit",
+        "-m",
+        "imported",
+        "--author",
+        "Ada Lovelace <ada@example.com>",
+        "--date",
+        "2001-02-03T04:05:06+01:00",
+    ]);
+
+    let fields = repo.commit_fields(&repo.head());
+    assert_eq!(
+        fields["author"],
+        "Ada Lovelace <ada@example.com> 981169506 +0100"
+    );
+    assert!(fields["committer"].starts_with("Test User <test@example.com> "));
+}
+
+#[test]
+fn commit_takes_committer_date_from_environment() {
+    let repo = Repo::new();
+    repo.write("a.txt", "a\n");
+    repo.ok(&["add", "a.txt"]);
+    let output = repo.run_with(
+        &["commit", "-m", "pinned", "--date", "@1000000000 +0000"],
+        None,
+        &[("FIT_COMMITTER_DATE", "@1234567890 -0230")],
+    );
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    let fields = repo.commit_fields(&repo.head());
+    assert_eq!(
+        fields["author"],
+        "Test User <test@example.com> 1000000000 +0000"
+    );
+    assert_eq!(
+        fields["committer"],
+        "Test User <test@example.com> 1234567890 -0230"
+    );
+}
+
+#[test]
+fn commit_rejects_malformed_overrides() {
+    let repo = Repo::new();
+    repo.write("a.txt", "a\n");
+    repo.ok(&["add", "a.txt"]);
+
+    let error = repo.fails(&["commit", "-m", "x", "--date", "garbage"], 129);
+    assert!(error.contains("invalid date 'garbage'"), "{}", error);
+    let error = repo.fails(&["commit", "-m", "x", "--author", "nobody"], 129);
+    assert!(error.contains("invalid author 'nobody'"), "{}", error);
+    assert_eq!(repo.head(), INITIAL_COMMIT);
+}
+
+fn root_tree(repo: &Repo) -> String {
+    repo.commit_fields(&repo.head())["tree"].clone()
+}
+
+#[test]
+fn same_files_give_the_same_tree_however_they_were_committed() {
+    let direct = Repo::new();
+    direct.write("a.txt", "a\n");
+    direct.write("dir/b.txt", "b\n");
+    direct.ok(&["add", "."]);
+    direct.commit("two files");
+
+    let via_deletion = Repo::new();
+    via_deletion.write("a.txt", "a\n");
+    via_deletion.write("dir/b.txt", "b\n");
+    via_deletion.write("dir/c.txt", "c\n");
+    via_deletion.write("gone.txt", "gone\n");
+    via_deletion.ok(&["add", "."]);
+    via_deletion.commit("four files");
+    via_deletion.ok(&["rm", "dir/c.txt"]);
+    via_deletion.ok(&["rm", "gone.txt"]);
+    via_deletion.commit("drop two");
+
+    assert_eq!(root_tree(&via_deletion), root_tree(&direct));
+}
+
+#[test]
+fn commit_all_stages_modified_tracked_files() {
+    let repo = Repo::new();
+    repo.commit_file("a.txt", "before\n", "first");
+    repo.write("a.txt", "after\n");
+    repo.write("untracked.txt", "new\n");
+
+    repo.ok(&["commit", "-a", "-m", "second"]);
+
+    let tree = repo.commit_fields(&repo.head())["tree"].clone();
+    let files = repo.ok(&["catfile", "-r", &tree]);
+    assert_eq!(files.lines().count(), 1, "{}", files);
+    let blob = repo.ok(&["hash-object", "a.txt"]).trim().to_string();
+    assert!(files.contains(&blob), "{}", files);
+    let status = repo.ok(&["status"]);
+    assert!(
+        status_section(&status, "Changes not staged for commit:").is_empty(),
+        "{}",
+        status
+    );
+}
+
+#[test]
+fn commit_with_only_a_deletion_staged() {
+    let repo = Repo::new();
+    let first = repo.commit_file("a.txt", "a\n", "add a");
+    repo.remove("a.txt");
+    repo.ok(&["rm", "a.txt"]);
+    let second = repo.commit("remove a");
+    assert_ne!(second, first);
+    assert_eq!(
+        repo.commit_fields(&second)["tree"],
+        "4b825dc642cb6eb9a060e54bf8d69288fbee4904"
+    );
+
+    repo.ok(&["reset", &first]);
+    assert_eq!(repo.read("a.txt"), "a\n");
+    repo.ok(&["reset", &second]);
+
+    let entries: Vec<String> = std::fs::read_dir(&repo.dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .collect();
+    assert_eq!(entries, [".fit"]);
+}
+
+#[cfg(unix)]
+#[test]
+fn mode_change_is_a_modification_and_is_committed() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let repo = Repo::new();
+    repo.commit_file("script.sh", "echo hi\n", "add script");
+    std::fs::set_permissions(
+        repo.path("script.sh"),
+        std::fs::Permissions::from_mode(0o755),
+    )
+    .unwrap();
+
+    let status = repo.ok(&["status"]);
+    assert_eq!(
+        status_section(&status, "Changes not staged for commit:"),
+        ["modified: script.sh"]
+    );
+
+    repo.ok(&["add", "script.sh"]);
+    let commit = repo.commit("make executable");
+    let tree = repo.commit_fields(&commit)["tree"].clone();
+    let files = repo.ok(&["catfile", "-r", &tree]);
+    assert!(files.starts_with("100755 blob "), "{}", files);
+}
+
+#[test]
+fn quiet_commit_prints_nothing() {
+    let repo = Repo::new();
+    repo.write("a.txt", "a\n");
+    repo.ok(&["add", "a.txt"]);
+
+    let output = repo.run(&["--quiet", "commit", "-m", "silent"]);
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(stdout(&output), "");
+    assert_ne!(repo.head(), INITIAL_COMMIT);
+}
+
+#[test]
+fn verbose_commit_reports_each_step() {
+    let repo = Repo::new();
+    repo.write("a.txt", "a\n");
+    repo.ok(&["add", "a.txt"]);
+
+    let output = repo.ok(&["-v", "commit", "-m", "chatty"]);
+
+    assert!(
+        output.contains("Tree object created with hash"),
+        "{}",
+        output
+    );
+    assert!(
+        output.contains(&format!("Created commit {}", repo.head())),
+        "{}",
+        output
+    );
+}
+
+#[test]
+fn fixup_and_squash_commits_name_their_target() {
+    let repo = Repo::new();
+    let target = repo.commit_file("a.txt", "a\n", "add parser\n\nlonger body");
+    repo.write("a.txt", "a\nb\n");
+    repo.ok(&["add", "a.txt"]);
+    repo.ok(&["commit", "--fixup", &target]);
+    assert_eq!(
+        repo.commit_fields(&repo.head())[""].trim(),
+        "fixup! add parser"
+    );
+
+    repo.write("a.txt", "a\nb\nc\n");
+    repo.ok(&["add", "a.txt"]);
+    repo.ok(&["commit", "--squash", "HEAD", "-m", "extra notes"]);
+    let message = repo.commit_fields(&repo.head())[""].clone();
+    assert!(
+        message.starts_with("squash! fixup! add parser"),
+        "{}",
+        message
+    );
+    assert!(message.contains("extra notes"), "{}", message);
+}
+
+#[test]
+fn failed_ref_update_keeps_the_staged_changes() {
+    let repo = Repo::new();
+    repo.write("a.txt", "a\n");
+    repo.ok(&["add", "a.txt"]);
+    let staging = repo.staging();
+    // A directory where the branch's lock file goes makes the ref update fail
+    std::fs::create_dir(repo.path(".fit/refs/heads/master.lock")).unwrap();
+
+    repo.fails(&["commit", "-m", "blocked"], 1);
+
+    assert_eq!(repo.staging(), staging);
+    assert_eq!(repo.head(), INITIAL_COMMIT);
+    std::fs::remove_dir(repo.path(".fit/refs/heads/master.lock")).unwrap();
+    let commit = repo.commit("retried");
+    assert_ne!(commit, INITIAL_COMMIT);
+    assert!(!repo.exists(".fit/STAGING"));
+}
@@ -0,0 +1,419 @@
+#![allow(dead_code)]
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+pub const INITIAL_COMMIT: &str = "e8666b9f0084d6e0ed1a3d310c17acb3ceed800f";
+
+static NEXT_DIR: AtomicUsize = AtomicUsize::new(0);
+
+// A scratch directory under the system temp dir, removed again when dropped
+pub struct Scratch {
+    pub root: PathBuf,
+}
+
+impl Scratch {
+    pub fn new() -> Scratch {
+        let root = std::env::temp_dir().join(format!(
+            "fit-test-{}-{}",
+            std::process::id(),
+            NEXT_DIR.fetch_add(1, Ordering::SeqCst)
+        ));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        Scratch { root }
+    }
+}
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.root);
+    }
+}
+
+pub fn fit_command(
+    dir: &Path,
+    args: &[&str],
+    stdin: Option<&[u8]>,
+    env: &[(&str, &str)],
+) -> Output {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_fit"));
+    command
+        .args(args)
+        .current_dir(dir)
+        .env_remove("FIT_COMMITTER_DATE")
+        .env_remove("FIT_CEILING_DIRECTORIES")
+        .stdin(if stdin.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    for (key, value) in env {
+        command.env(key, value);
+    }
+    let mut child = command.spawn().expect("failed to run fit");
+    if let Some(input) = stdin {
+        child.stdin.take().unwrap().write_all(input).unwrap();
+    }
+    child.wait_with_output().unwrap()
+}
+
+pub fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+pub fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+// A fit repository in a fresh scratch directory, with a fixed user identity in its config
+pub struct Repo {
+    pub scratch: Scratch,
+    pub dir: PathBuf,
+}
+
+impl Repo {
+    pub fn new() -> Repo {
+        Repo::init_in("repo", &["init"])
+    }
+
+    pub fn init_in(name: &str, init_args: &[&str]) -> Repo {
+        let scratch = Scratch::new();
+        let dir = scratch.root.join(name);
+        fs::create_dir_all(&dir).unwrap();
+        let output = fit_command(&dir, init_args, None, &[]);
+        assert!(
+            output.status.success(),
+            "fit init failed: {}",
+            stderr(&output)
+        );
+        let repo = Repo { scratch, dir };
+        if init_args.contains(&"--bare") {
+            return repo;
+        }
+        repo.append_config("[user]\n\tname = Test User\n\temail = test@example.com\n");
+        repo
+    }
+
+    // Another repository inside the same scratch directory
+    pub fn sibling(&self, name: &str) -> PathBuf {
+        let dir = self.scratch.root.join(name);
+        fs::create_dir_all(&dir).unwrap();
+        let output = fit_command(&dir, &["init"], None, &[]);
+        assert!(
+            output.status.success(),
+            "fit init failed: {}",
+            stderr(&output)
+        );
+        let mut config = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(dir.join(".fit/config"))
+            .unwrap();
+        config
+            .write_all(b"[user]\n\tname = Test User\n\temail = test@example.com\n")
+            .unwrap();
+        dir
+    }
+
+    pub fn path(&self, relative: &str) -> PathBuf {
+        self.dir.join(relative)
+    }
+
+    pub fn run(&self, args: &[&str]) -> Output {
+        fit_command(&self.dir, args, None, &[])
+    }
+
+    pub fn run_with(&self, args: &[&str], stdin: Option<&[u8]>, env: &[(&str, &str)]) -> Output {
+        fit_command(&self.dir, args, stdin, env)
+    }
+
+    // Runs fit, asserts it succeeded and returns its stdout
+    pub fn ok(&self, args: &[&str]) -> String {
+        let output = self.run(args);
+        assert!(
+            output.status.success(),
+            "fit {:?} failed with {:?}\nstdout: {}\nstderr: {}",
+            args,
+            output.status.code(),
+            stdout(&output),
+            stderr(&output)
+        );
+        stdout(&output)
+    }
+
+    // Runs fit, asserts it failed with the given exit code and returns its stderr
+    pub fn fails(&self, args: &[&str], code: i32) -> String {
+        let output = self.run(args);
+        assert_eq!(
+            output.status.code(),
+            Some(code),
+            "fit {:?} exited unexpectedly\nstdout: {}\nstderr: {}",
+            args,
+            stdout(&output),
+            stderr(&output)
+        );
+        stderr(&output)
+    }
+
+    pub fn write(&self, relative: &str, content: &str) {
+        self.write_bytes(relative, content.as_bytes());
+    }
+
+    pub fn write_bytes(&self, relative: &str, content: &[u8]) {
+        let path = self.path(relative);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).unwrap();
+        }
+        fs::write(path, content).unwrap();
+    }
+
+    pub fn read(&self, relative: &str) -> String {
+        fs::read_to_string(self.path(relative)).unwrap()
+    }
+
+    pub fn exists(&self, relative: &str) -> bool {
+        self.path(relative).symlink_metadata().is_ok()
+    }
+
+    pub fn remove(&self, relative: &str) {
+        fs::remove_file(self.path(relative)).unwrap();
+    }
+
+    pub fn append_config(&self, text: &str) {
+        let mut config = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.path(".fit/config"))
+            .unwrap();
+        config.write_all(text.as_bytes()).unwrap();
+    }
+
+    // Writes a file, stages it and commits it, returning the new commit's hash
+    pub fn commit_file(&self, relative: &str, content: &str, message: &str) -> String {
+        self.write(relative, content);
+        self.ok(&["add", relative]);
+        self.commit(message)
+    }
+
+    pub fn commit(&self, message: &str) -> String {
+        self.ok(&["commit", "-m", message]);
+        self.head()
+    }
+
+    // The commit HEAD points at, following a symbolic HEAD to its branch
+    pub fn head(&self) -> String {
+        let head = self.read(".fit/HEAD");
+        match head.trim().strip_prefix("ref: ") {
+            Some(reference) => self
+                .read_ref(reference)
+                .expect("HEAD points at a missing branch"),
+            None => head.trim().to_string(),
+        }
+    }
+
+    pub fn read_ref(&self, reference: &str) -> Option<String> {
+        fs::read_to_string(self.path(&format!(".fit/{}", reference)))
+            .ok()
+            .map(|content| content.trim().to_string())
+    }
+
+    // The type and raw content of an object, read through `catfile --batch`
+    pub fn object(&self, hash: &str) -> (String, Vec<u8>) {
+        let output = self.run_with(
+            &["catfile", "--batch"],
+            Some(format!("{}\n", hash).as_bytes()),
+            &[],
+        );
+        assert!(
+            output.status.success(),
+            "catfile --batch failed: {}",
+            stderr(&output)
+        );
+        let text = output.stdout;
+        let newline = text.iter().position(|byte| *byte == b'\n').unwrap();
+        let header = String::from_utf8_lossy(&text[..newline]).into_owned();
+        let fields: Vec<&str> = header.split(' ').collect();
+        assert_eq!(fields.len(), 3, "unexpected batch header {:?}", header);
+        let size: usize = fields[2].parse().unwrap();
+        let content = text[newline + 1..newline + 1 + size].to_vec();
+        (fields[1].to_string(), content)
+    }
+
+    pub fn object_text(&self, hash: &str) -> String {
+        String::from_utf8(self.object(hash).1).unwrap()
+    }
+
+    // The headers of a commit object keyed by name, with the message under ""
+    pub fn commit_fields(&self, hash: &str) -> HashMap<String, String> {
+        let text = self.object_text(hash);
+        let (headers, message) = text.split_once("\n\n").unwrap_or((&text, ""));
+        let mut fields = HashMap::new();
+        for line in headers.lines() {
+            if let Some((key, value)) = line.split_once(' ') {
+                fields
+                    .entry(key.to_string())
+                    .or_insert_with(|| value.to_string());
+            }
+        }
+        fields.insert(String::new(), message.to_string());
+        fields
+    }
+
+    pub fn loose_object_path(&self, hash: &str) -> PathBuf {
+        self.path(&format!(".fit/objects/{}/{}", &hash[..2], &hash[2..]))
+    }
+
+    // Every loose object hash in the store
+    pub fn loose_objects(&self) -> Vec<String> {
+        let mut hashes = Vec::new();
+        for shard in fs::read_dir(self.path(".fit/objects")).unwrap() {
+            let shard = shard.unwrap();
+            let name = shard.file_name().to_string_lossy().into_owned();
+            if name.len() != 2 || !shard.path().is_dir() {
+                continue;
+            }
+            for object in fs::read_dir(shard.path()).unwrap() {
+                let object = object.unwrap().file_name().to_string_lossy().into_owned();
+                hashes.push(format!("{}{}", name, object));
+            }
+        }
+        hashes.sort();
+        hashes
+    }
+
+    pub fn staging(&self) -> String {
+        fs::read_to_string(self.path(".fit/STAGING")).unwrap_or_default()
+    }
+}
+
+// The lines of one section of `fit status` output, e.g. "Changes not staged for commit:"
+pub fn status_section(status: &str, heading: &str) -> Vec<String> {
+    status
+        .lines()
+        .skip_while(|line| *line != heading)
+        .skip(1)
+        .take_while(|line| line.starts_with("  "))
+        .map(|line| line.trim().to_string())
+        .collect()
+}
+
+pub fn set_mtime(path: &Path, seconds_from_now: u64) {
+    let time = std::time::SystemTime::now() + std::time::Duration::from_secs(seconds_from_now);
+    fs::File::options()
+        .write(true)
+        .open(path)
+        .unwrap()
+        .set_modified(time)
+        .unwrap();
+}
+
+// Points `remote` in the repo's config at another repository on disk
+pub fn add_remote(repo: &Repo, remote: &str, path: &Path) {
+    repo.append_config(&format!(
+        "[remote \"{}\"]\n\tpath = {}\n",
+        remote,
+        path.display()
+    ));
+}
+
+pub fn fit_in(dir: &Path, args: &[&str]) -> String {
+    let output = fit_command(dir, args, None, &[]);
+    assert!(
+        output.status.success(),
+        "fit {:?} failed in {}: {}",
+        args,
+        dir.display(),
+        stderr(&output)
+    );
+    stdout(&output)
+}
+
+pub fn ref_in(dir: &Path, reference: &str) -> Option<String> {
+    fs::read_to_string(dir.join(".fit").join(reference))
+        .ok()
+        .map(|content| content.trim().to_string())
+}
+
+// A stand-in for gpg: signing prints an armored block holding the sha1 of the payload, and
+// `--verify <signature> -` succeeds only if the payload on stdin still has that sha1
+#[cfg(unix)]
+pub fn fake_signer(repo: &Repo) -> PathBuf {
+    use std::os::unix::fs::PermissionsExt;
+
+    let path = repo.scratch.root.join("fake-gpg");
+    fs::write(
+        &path,
+        "#!/bin/sh\n\
+         if [ \"$1\" = \"--verify\" ]; then\n\
+         \twant=$(sed -n 3p \"$2\"); got=$(sha1sum | cut -d' ' -f1)\n\
+         \t[ \"$want\" = \"$got\" ]\n\
+         else\n\
+         \tsum=$(sha1sum | cut -d' ' -f1)\n\
+         \tprintf -- '-----BEGIN PGP SIGNATURE-----\\n\\n%s\\n-----END PGP SIGNATURE-----\\n' \"$sum\"\n\
+         fi\n",
+    )
+    .unwrap();
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+    repo.append_config(&format!("[gpg]\n\tprogram = {}\n", path.display()));
+    path
+}
+
+// Stores an object of the given type through `hash-object -w`, returning its hash
+pub fn store_object(repo: &Repo, object_type: &str, content: &str) -> String {
+    let output = repo.run_with(
+        &["hash-object", "-w", "-t", object_type, "--stdin"],
+        Some(content.as_bytes()),
+        &[],
+    );
+    assert!(output.status.success(), "{}", stderr(&output));
+    stdout(&output).trim().to_string()
+}
+
+// Writes a loose object under a hash of the caller's choosing, as a corrupted or tampered
+// store would hold it
+pub fn write_raw_object(repo: &Repo, hash: &str, object_type: &str, content: &[u8]) {
+    use flate2::write::ZlibEncoder;
+
+    let mut data = format!("{} {}\0", object_type, content.len()).into_bytes();
+    data.extend_from_slice(content);
+    let mut encoder = ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(&data).unwrap();
+    let path = repo.loose_object_path(hash);
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(path, encoder.finish().unwrap()).unwrap();
+}
+
+// The single pack in the repo after a `gc`
+pub fn only_pack(repo: &Repo) -> PathBuf {
+    let packs: Vec<PathBuf> = fs::read_dir(repo.path(".fit/objects/pack"))
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|extension| extension == "pack")
+        })
+        .collect();
+    assert_eq!(packs.len(), 1, "{:?}", packs);
+    packs[0].clone()
+}
+
+// Deterministic pseudo-random text, so large test files don't compress to nothing
+pub fn noise(length: usize, seed: u64) -> String {
+    let alphabet = b"abcdefghij \n";
+    let mut state = seed;
+    (0..length)
+        .map(|_| {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            alphabet[(state >> 33) as usize % alphabet.len()] as char
+        })
+        .collect()
+}
@@ -0,0 +1,145 @@
+use crate::common::*;
+
+#[test]
+fn diff_compares_commit_against_working_tree() {
+    let repo = Repo::new();
+    let commit = repo.commit_file("notes.txt", "first line\nsecond line\n", "add notes");
+    repo.write("notes.txt", "first line\nchanged line\n");
+
+    let diff = repo.ok(&["diff", &commit]);
+
+    assert!(diff.contains("--- a/notes.txt"), "{}", diff);
+    assert!(diff.contains("+++ b/notes.txt"), "{}", diff);
+    assert!(diff.contains("-second line"), "{}", diff);
+    assert!(diff.contains("+changed line"), "{}", diff);
+}
+
+#[test]
+fn diff_against_commit_is_empty_for_unchanged_tree() {
+    let repo = Repo::new();
+    let commit = repo.commit_file("notes.txt", "same\n", "add notes");
+
+    assert_eq!(repo.ok(&["diff", &commit]), "");
+}
+
+#[test]
+fn diff_ignore_all_space_hides_indentation_changes() {
+    let repo = Repo::new();
+    repo.commit_file("main.rs", "fn main() {\n    call();\n}\n", "first");
+    repo.write("main.rs", "fn main() {\n\tcall();\n}\n");
+
+    let diff = repo.ok(&["diff"]);
+    assert!(diff.contains("@@"), "{}", diff);
+    assert!(diff.contains("+\tcall();"), "{}", diff);
+
+    assert_eq!(repo.ok(&["diff", "-w"]), "");
+}
+
+#[test]
+fn diff_color_words_marks_only_the_changed_word() {
+    let repo = Repo::new();
+    let line = "the quick brown fox jumps over the lazy dog again and again";
+    repo.commit_file("story.txt", &format!("{}\n", line), "first");
+    repo.write("story.txt", &format!("{}\n", line.replace("fox", "cat")));
+
+    let diff = repo.ok(&["diff", "--color-words"]);
+
+    assert!(
+        diff.contains(
+            "the quick brown \x1b[31mfox\x1b[m\x1b[32mcat\x1b[m jumps over the lazy dog again and again"
+        ),
+        "{:?}",
+        diff
+    );
+    assert_eq!(diff.matches("\x1b[31m").count(), 1, "{:?}", diff);
+    assert_eq!(diff.matches("\x1b[32m").count(), 1, "{:?}", diff);
+}
+
+#[test]
+fn diff_of_an_added_file_uses_dev_null() {
+    let repo = Repo::new();
+    repo.write("new.txt", "fresh\n");
+    repo.ok(&["add", "new.txt"]);
+
+    let diff = repo.ok(&["diff", "--cached"]);
+
+    assert_eq!(
+        diff,
+        "diff --fit a/new.txt b/new.txt\n--- /dev/null\n+++ b/new.txt\n@@ -0,0 +1,1 @@\n+fresh\n"
+    );
+}
+
+#[test]
+fn diff_accepts_a_branch_name() {
+    let repo = Repo::new();
+    repo.commit_file("a.txt", "committed\n", "first");
+    repo.write("a.txt", "committed\nworking\n");
+
+    let by_branch = repo.ok(&["diff", "master"]);
+    assert!(by_branch.contains("+working"), "{}", by_branch);
+    assert_eq!(repo.ok(&["diff", "HEAD"]), by_branch);
+}
+
+#[test]
+fn cached_diff_shows_staged_changes_only() {
+    let repo = Repo::new();
+    repo.commit_file("a.txt", "a\n", "first");
+    repo.write("a.txt", "staged\n");
+    repo.ok(&["add", "a.txt"]);
+
+    let cached = repo.ok(&["diff", "--cached"]);
+    assert!(cached.contains("-a\n+staged"), "{}", cached);
+    assert_eq!(repo.ok(&["diff", "--staged"]), cached);
+    assert_eq!(repo.ok(&["diff"]), "");
+}
+
+#[test]
+fn plain_diff_shows_unstaged_changes_only() {
+    let repo = Repo::new();
+    repo.commit_file("a.txt", "a\n", "first");
+    repo.write("a.txt", "staged\n");
+    repo.ok(&["add", "a.txt"]);
+    repo.write("a.txt", "staged\nthen edited\n");
+
+    let plain = repo.ok(&["diff"]);
+    assert!(plain.contains(" staged\n+then edited"), "{}", plain);
+    assert!(!plain.contains("-a\n"), "{}", plain);
+}
+
+#[test]
+fn diff_exit_code_reflects_whether_the_tree_is_dirty() {
+    let repo = Repo::new();
+    repo.commit_file("a.txt", "a\n", "first");
+
+    assert_eq!(repo.run(&["diff", "--exit-code"]).status.code(), Some(0));
+
+    repo.write("a.txt", "changed\n");
+    let dirty = repo.run(&["diff", "--exit-code"]);
+    assert_eq!(dirty.status.code(), Some(1));
+    assert!(stdout(&dirty).contains("+changed"), "{}", stdout(&dirty));
+    let quiet = repo.run(&["diff", "--quiet"]);
+    assert_eq!(quiet.status.code(), Some(1));
+    assert_eq!(stdout(&quiet), "");
+}
+
+#[test]
+fn apply_reapplies_a_generated_diff() {
+    let repo = Repo::new();
+    repo.commit_file("a", "old\n", "first");
+    repo.write("a", "new\n");
+    let patch = repo.ok(&["diff"]);
+    repo.write("changes.patch", &patch);
+    repo.write("a", "old\n");
+
+    repo.ok(&["apply", "--check", "changes.patch"]);
+    assert_eq!(repo.read("a"), "old\n");
+
+    let output = repo.run_with(&["apply"], Some(patch.as_bytes()), &[]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(stdout(&output), "Applied patch to a\n");
+    assert_eq!(repo.read("a"), "new\n");
+
+    let error = repo.fails(&["apply", "changes.patch"], 1);
+    assert!(error.contains("patch failed: a:1"), "{}", error);
+    assert_eq!(repo.read("a"), "new\n");
+}
@@ -0,0 +1,128 @@
+use crate::common::*;
+
+#[test]
+fn outside_a_repository_exits_128() {
+    let scratch = Scratch::new();
+
+    let output = fit_command(&scratch.root, &["status"], None, &[]);
+
+    assert_eq!(output.status.code(), Some(128));
+    assert!(
+        stderr(&output).contains("not a fit repository"),
+        "{}",
+        stderr(&output)
+    );
+}
+
+#[test]
+fn unknown_revision_and_branch_exit_1() {
+    let repo = Repo::new();
+
+    let error = repo.fails(&["reset", "nope"], 1);
+    assert_eq!(error, "error: unknown revision 'nope'\n");
+    let error = repo.fails(&["branch", "checkout", "nope"], 1);
+    assert_eq!(error, "error: Branch 'nope' not found\n");
+}
+
+#[test]
+fn add_and_rm_of_a_missing_path_exit_1() {
+    let repo = Repo::new();
+    repo.write("untracked.txt", "u\n");
+
+    let error = repo.fails(&["add", "nothere"], 1);
+    assert_eq!(
+        error,
+        "error: pathspec 'nothere' did not match any file(s) known to fit\n"
+    );
+    let error = repo.fails(&["rm", "nothere"], 1);
+    assert_eq!(
+        error,
+        "error: pathspec 'nothere' did not match any file(s) known to fit\n"
+    );
+    let error = repo.fails(&["rm", "untracked.txt"], 1);
+    assert!(error.contains("'untracked.txt' did not match"), "{}", error);
+    assert!(!repo.exists(".fit/STAGING"));
+}
+
+#[test]
+fn wrong_object_type_is_reported_not_panicked() {
+    let repo = Repo::new();
+    repo.commit_file("a.txt", "a\n", "first");
+    let blob = repo.ok(&["hash-object", "a.txt"]).trim().to_string();
+
+    let error = repo.fails(&["reset", &blob], 128);
+
+    assert_eq!(
+        error,
+        format!("error: object {}: expected commit, found blob\n", blob)
+    );
+}
+
+#[test]
+fn corrupt_object_exits_128() {
+    let repo = Repo::new();
+    let commit = repo.commit_file("a.txt", "a\n", "first");
+    let path = repo.loose_object_path(&commit);
+    let mut permissions = std::fs::metadata(&path).unwrap().permissions();
+    #[allow(clippy::permissions_set_readonly_false)]
+    permissions.set_readonly(false);
+    std::fs::set_permissions(&path, permissions).unwrap();
+    std::fs::write(&path, b"not zlib").unwrap();
+
+    let error = repo.fails(&["log"], 128);
+
+    assert!(
+        error.contains(&format!("corrupt object: {}", commit)),
+        "{}",
+        error
+    );
+}
+
+#[test]
+fn blob_given_where_a_commit_is_expected() {
+    let repo = Repo::new();
+    repo.commit_file("a.txt", "a\n", "first");
+    let blob = repo.ok(&["hash-object", "a.txt"]).trim().to_string();
+    let expected = format!("error: object {}: expected commit, found blob\n", blob);
+
+    assert_eq!(repo.fails(&["diff", &blob], 128), expected);
+    assert_eq!(repo.fails(&["diff", "master", &blob], 128), expected);
+    assert_eq!(
+        repo.fails(&["checkout", &blob, "--", "a.txt"], 128),
+        expected
+    );
+}
+
+#[test]
+fn tree_entry_naming_a_commit_is_not_read_as_a_blob() {
+    let repo = Repo::new();
+    let good = repo.commit_file("a.txt", "a\n", "first");
+    let mut tree = b"100644 a.txt\0".to_vec();
+    tree.extend(
+        (0..20).map(|i| u8::from_str_radix(&INITIAL_COMMIT[i * 2..i * 2 + 2], 16).unwrap()),
+    );
+    let output = repo.run_with(
+        &["hash-object", "-w", "-t", "tree", "--stdin"],
+        Some(&tree),
+        &[],
+    );
+    let tree = stdout(&output).trim().to_string();
+    let bad = store_object(
+        &repo,
+        "commit",
+        &format!(
+            "tree {}\nauthor A <a@example.com> 1 +0000\ncommitter A <a@example.com> 1 +0000\n\nbad\n",
+            tree
+        ),
+    );
+
+    let error = repo.fails(&["diff", &good, &bad], 128);
+
+    assert_eq!(
+        error,
+        format!(
+            "error: object {}: expected blob, found commit\n",
+            INITIAL_COMMIT
+        )
+    );
+}
@@ -0,0 +1,364 @@
+use crate::common::*;
+
+#[test]
+fn ls_files_lists_tracked_paths_sorted() {
+    let repo = Repo::new();
+    for name in ["c.txt", "a.txt", "b.txt"] {
+        repo.write(name, name);
+    }
+    repo.ok(&["add", "."]);
+
+    assert_eq!(repo.ok(&["ls-files"]), "a.txt\nb.txt\nc.txt\n");
+}
+
+#[test]
+fn ls_files_deleted_flags_missing_files() {
+    let repo = Repo::new();
+    for name in ["a.txt", "b.txt", "c.txt"] {
+        repo.write(name, name);
+    }
+    repo.ok(&["add", "."]);
+    repo.commit("three files");
+    repo.remove("b.txt");
+
+    assert_eq!(repo.ok(&["ls-files", "--deleted"]), "b.txt\n");
+    assert_eq!(repo.ok(&["ls-files"]), "a.txt\nb.txt\nc.txt\n");
+}
+
+// The size and mtime fields recorded for a path in .fit/index
+fn index_stat(repo: &Repo, path: &str) -> String {
+    let index = repo.read(".fit/index");
+    let line = index
+        .lines()
+        .find(|line| line.ends_with(&format!(" {}", path)))
+        .unwrap_or_else(|| panic!("{} is not in the index", path));
+    let fields: Vec<&str> = line.split(' ').collect();
+    format!("{} {}", fields[1], fields[2])
+}
+
+#[test]
+fn status_trusts_unchanged_stat_data() {
+    let repo = Repo::new();
+    repo.commit_file("a.txt", "content\n", "first");
+    let objects = repo.loose_objects();
+
+    let status = repo.ok(&["status"]);
+
+    assert!(
+        status_section(&status, "Changes not staged for commit:").is_empty(),
+        "{}",
+        status
+    );
+    assert_eq!(repo.loose_objects(), objects);
+}
+
+#[test]
+fn status_rehashes_touched_files() {
+    let repo = Repo::new();
+    repo.commit_file("a.txt", "content\n", "first");
+    let before = index_stat(&repo, "a.txt");
+
+    // Same content with a new mtime is re-hashed, found unchanged and its stat data refreshed
+    set_mtime(&repo.path("a.txt"), 100);
+    let status = repo.ok(&["status"]);
+    assert!(
+        status_section(&status, "Changes not staged for commit:").is_empty(),
+        "{}",
+        status
+    );
+    assert_ne!(index_stat(&repo, "a.txt"), before);
+
+    // Same size with different content is only caught by the re-hash
+    repo.write("a.txt", "CONTENT\n");
+    set_mtime(&repo.path("a.txt"), 200);
+    let status = repo.ok(&["status"]);
+    assert_eq!(
+        status_section(&status, "Changes not staged for commit:"),
+        ["modified: a.txt"]
+    );
+}
+
+#[test]
+fn autocrlf_stores_lf_and_restores_crlf() {
+    let repo = Repo::new();
+    repo.append_config("[core]\n\tautocrlf = true\n");
+    repo.write("windows.txt", "one\r\ntwo\r\n");
+    repo.ok(&["add", "windows.txt"]);
+    let commit = repo.commit("crlf file");
+
+    let blob = repo.ok(&["hash-object", "windows.txt"]).trim().to_string();
+    assert_eq!(repo.object(&blob).1, b"one\ntwo\n");
+
+    repo.remove("windows.txt");
+    repo.ok(&["reset", &commit]);
+    assert_eq!(repo.read("windows.txt"), "one\r\ntwo\r\n");
+}
+
+#[test]
+fn adding_an_unchanged_file_twice_stages_it_once() {
+    let repo = Repo::new();
+    repo.write("a.txt", "a\n");
+
+    repo.ok(&["add", "a.txt"]);
+    let staging = repo.staging();
+    repo.ok(&["add", "a.txt"]);
+
+    assert_eq!(repo.staging(), staging);
+    assert_eq!(
+        repo.staging().matches("a.txt").count(),
+        1,
+        "{}",
+        repo.staging()
+    );
+}
+
+#[test]
+fn index_with_trailing_blank_lines_is_read() {
+    let repo = Repo::new();
+    repo.commit_file("a.txt", "a\n", "first");
+    let index = repo.read(".fit/index");
+    repo.write(".fit/index", &format!("{}\n\n", index.trim_end()));
+
+    assert_eq!(repo.ok(&["ls-files"]), "a.txt\n");
+    let status = repo.ok(&["status"]);
+    assert!(
+        status_section(&status, "Changes not staged for commit:").is_empty(),
+        "{}",
+        status
+    );
+}
+
+#[test]
+fn add_patch_stages_only_accepted_hunks() {
+    let repo = Repo::new();
+    let original: String = (1..=20).map(|n| format!("{}\n", n)).collect();
+    repo.commit_file("numbers.txt", &original, "numbers");
+    let edited = original
+        .replace("\n2\n", "\ntwo\n")
+        .replace("\n18\n", "\neighteen\n");
+    repo.write("numbers.txt", &edited);
+
+    let output = repo.run_with(&["add", "-p", "numbers.txt"], Some(b"y\nn\n"), &[]);
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    let staging = repo.staging();
+    let (kind, rest) = staging.trim().split_once(' ').unwrap();
+    assert_eq!(kind, "M");
+    let hash = rest.split(' ').next().unwrap();
+    let staged = repo.object_text(hash);
+    assert_eq!(staged, original.replace("\n2\n", "\ntwo\n"));
+    assert_eq!(repo.read("numbers.txt"), edited);
+}
+
+#[test]
+fn add_patch_refuses_non_utf8_files() {
+    let repo = Repo::new();
+    repo.commit_file("f", "cafe\n", "first");
+    repo.write_bytes("f", b"caf\xe9\n");
+
+    let output = repo.run_with(&["add", "-p", "f"], Some(b"y\n"), &[]);
+
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).contains("non-UTF-8"), "{}", stderr(&output));
+    assert!(!repo.exists(".fit/STAGING") || repo.staging().trim().is_empty());
+    repo.ok(&["add", "f"]);
+    let hash = repo.staging().split(' ').nth(1).unwrap().trim().to_string();
+    assert_eq!(repo.object(&hash).1, b"caf\xe9\n");
+}
+
+#[test]
+fn status_lists_a_file_edited_after_staging_in_both_sections() {
+    let repo = Repo::new();
+    repo.write("a.txt", "staged\n");
+    repo.ok(&["add", "a.txt"]);
+    repo.write("a.txt", "edited again\n");
+
+    let status = repo.ok(&["status"]);
+
+    assert_eq!(
+        status_section(&status, "Changes to be committed:"),
+        ["new file: a.txt"]
+    );
+    assert_eq!(
+        status_section(&status, "Changes not staged for commit:"),
+        ["modified: a.txt"]
+    );
+}
+
+#[test]
+fn add_rejects_paths_outside_the_repository() {
+    let repo = Repo::new();
+    std::fs::write(repo.scratch.root.join("secret"), "hidden\n").unwrap();
+
+    let error = repo.fails(&["add", "../secret"], 1);
+
+    assert!(
+        error.contains("'../secret' is outside the repository"),
+        "{}",
+        error
+    );
+    assert_eq!(repo.staging(), "");
+}
+
+#[test]
+fn intent_to_add_stages_the_path_without_content() {
+    let repo = Repo::new();
+    repo.write("new.txt", "not staged yet\n");
+
+    repo.ok(&["add", "-N", "new.txt"]);
+
+    let status = repo.ok(&["status"]);
+    assert_eq!(
+        status_section(&status, "Changes to be committed:"),
+        ["new file: new.txt"]
+    );
+    assert_eq!(
+        status_section(&status, "Changes not staged for commit:"),
+        ["modified: new.txt"]
+    );
+    // The staged blob is the empty one
+    assert_eq!(
+        repo.staging().trim(),
+        "A e69de29bb2d1d6434b8b29ae775ad8c2e48c5391 new.txt"
+    );
+}
+
+#[test]
+fn adding_the_repository_root_skips_the_fit_directory() {
+    let repo = Repo::new();
+    repo.write("a.txt", "a\n");
+    repo.write("dir/b.txt", "b\n");
+
+    repo.ok(&["add", "."]);
+
+    assert_eq!(repo.ok(&["ls-files"]), "a.txt\ndir/b.txt\n");
+    assert!(!repo.staging().contains(".fit"), "{}", repo.staging());
+    assert!(!repo.read(".fit/index").contains(".fit/"));
+}
+
+#[test]
+fn status_reports_a_moved_file_as_a_rename() {
+    let repo = Repo::new();
+    repo.commit_file("old_name.txt", "content that moves\n", "first");
+    std::fs::rename(repo.path("old_name.txt"), repo.path("new_name.txt")).unwrap();
+
+    let status = repo.ok(&["status"]);
+    assert_eq!(
+        status_section(&status, "Changes not staged for commit:"),
+        ["renamed: old_name.txt -> new_name.txt"]
+    );
+    assert!(
+        status_section(&status, "Untracked files:").is_empty(),
+        "{}",
+        status
+    );
+
+    repo.ok(&["rm", "old_name.txt"]);
+    repo.ok(&["add", "new_name.txt"]);
+    let status = repo.ok(&["status"]);
+    assert_eq!(
+        status_section(&status, "Changes to be committed:"),
+        ["renamed: old_name.txt -> new_name.txt"]
+    );
+}
+
+#[test]
+fn ignorecase_treats_differently_cased_paths_as_one() {
+    let repo = Repo::new();
+    repo.append_config("[core]\n\tignorecase = true\n");
+    repo.write("File.txt", "first\n");
+    repo.ok(&["add", "File.txt"]);
+    std::fs::rename(repo.path("File.txt"), repo.path("file.txt")).unwrap();
+    repo.write("file.txt", "second\n");
+
+    repo.ok(&["add", "file.txt"]);
+
+    assert_eq!(repo.ok(&["ls-files"]), "File.txt\n");
+    assert_eq!(repo.staging().lines().count(), 1, "{}", repo.staging());
+}
+
+#[test]
+fn ignorecase_folds_every_path_of_a_directory_add_and_rm() {
+    let repo = Repo::new();
+    repo.append_config("[core]\n\tignorecase = true\n");
+    repo.write("Dir/One.txt", "1\n");
+    repo.write("Dir/Two.txt", "2\n");
+    repo.ok(&["add", "."]);
+    repo.commit("tracked");
+    for name in ["One", "Two"] {
+        std::fs::rename(
+            repo.path(&format!("Dir/{}.txt", name)),
+            repo.path(&format!("Dir/{}.txt", name.to_lowercase())),
+        )
+        .unwrap();
+    }
+    repo.write("Dir/one.txt", "changed\n");
+
+    repo.ok(&["add", "."]);
+    assert_eq!(repo.ok(&["ls-files"]), "Dir/One.txt\nDir/Two.txt\n");
+    assert_eq!(repo.staging().lines().count(), 1, "{}", repo.staging());
+
+    repo.ok(&["rm", "dir/two.txt"]);
+    assert_eq!(repo.ok(&["ls-files"]), "Dir/One.txt\n");
+}
+
+#[test]
+fn info_exclude_hides_untracked_files() {
+    let repo = Repo::new();
+    repo.write(".fit/info/exclude", "*.log\n");
+    repo.write("debug.log", "noise\n");
+    repo.write("notes.txt", "keep\n");
+
+    let status = repo.ok(&["status"]);
+
+    assert_eq!(status_section(&status, "Untracked files:"), ["notes.txt"]);
+    repo.ok(&["add", "."]);
+    assert_eq!(repo.ok(&["ls-files"]), "notes.txt\n");
+}
+
+#[test]
+fn verbose_add_reports_each_file() {
+    let repo = Repo::new();
+    repo.write("a.txt", "a\n");
+    repo.write("dir/b.txt", "b\n");
+
+    let output = repo.ok(&["--verbose", "add", "."]);
+
+    assert!(
+        output.contains("Added a.txt to staging area\n"),
+        "{}",
+        output
+    );
+    assert!(
+        output.contains("Added dir/b.txt to staging area\n"),
+        "{}",
+        output
+    );
+    assert!(output.ends_with("Staged 2 files\n"), "{}", output);
+}
+
+#[test]
+fn plain_add_prints_only_the_summary() {
+    let repo = Repo::new();
+    repo.write("a.txt", "a\n");
+    repo.write("dir/b.txt", "b\n");
+
+    assert_eq!(repo.ok(&["add", "."]), "Staged 2 files\n");
+}
+
+#[test]
+fn staged_deletion_of_a_spaced_path_survives_a_reload() {
+    let repo = Repo::new();
+    repo.commit_file("my file.txt", "doomed\n", "first");
+    repo.commit_file("other.txt", "kept\n", "second");
+
+    repo.ok(&["rm", "my file.txt"]);
+    let status = repo.ok(&["status"]);
+    assert!(status.contains("my file.txt"), "{}", status);
+
+    let head = repo.commit("drop my file");
+    let tree = repo.commit_fields(&head)["tree"].clone();
+    let listing = repo.object_text(&tree);
+    assert!(!listing.contains("my file.txt"), "{}", listing);
+    assert!(listing.contains("other.txt"), "{}", listing);
+}
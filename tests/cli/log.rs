@@ -0,0 +1,237 @@
+use crate::common::*;
+
+#[test]
+fn log_name_status_lists_each_commits_files() {
+    let repo = Repo::new();
+    repo.commit_file("a.txt", "one\n", "add a");
+    repo.commit_file("a.txt", "two\n", "change a");
+
+    let log = repo.ok(&["log", "--name-status"]);
+
+    let entries: Vec<&str> = log
+        .split("commit ")
+        .filter(|entry| !entry.is_empty())
+        .collect();
+    assert!(entries[0].contains("change a"), "{}", log);
+    assert!(entries[0].contains("M\ta.txt"), "{}", log);
+    assert!(entries[1].contains("A\ta.txt"), "{}", log);
+}
+
+#[test]
+fn log_filters_by_author_and_message() {
+    let repo = Repo::new();
+    repo.write("a.txt", "a\n");
+    repo.ok(&["add", "a.txt"]);
+    repo.ok(&[
+        "commit",
+        "-m",
+        "fix parser",
+        "--author",
+        "Alice <alice@example.org>",
+    ]);
+    repo.write("b.txt", "b\n");
+    repo.ok(&["add", "b.txt"]);
+    repo.ok(&[
+        "commit",
+        "-m",
+        "add feature",
+        "--author",
+        "Bob <bob@example.net>",
+    ]);
+    repo.write("c.txt", "c\n");
+    repo.ok(&["add", "c.txt"]);
+    repo.ok(&[
+        "commit",
+        "-m",
+        "fix feature",
+        "--author",
+        "Alice <alice@example.org>",
+    ]);
+
+    let alice = repo.ok(&["log", "--oneline", "--author", "Alice"]);
+    let subjects: Vec<&str> = alice.lines().map(|line| &line[8..]).collect();
+    assert_eq!(subjects, ["fix feature", "fix parser"]);
+
+    let bob = repo.ok(&["log", "--oneline", "--author", "bob@example"]);
+    assert_eq!(bob.lines().count(), 1, "{}", bob);
+    assert!(bob.ends_with(" add feature\n"), "{}", bob);
+
+    let features = repo.ok(&["log", "--oneline", "--grep", "feature"]);
+    assert_eq!(features.lines().count(), 2, "{}", features);
+}
+
+#[test]
+fn log_stops_at_a_commit_that_is_its_own_parent() {
+    let repo = Repo::new();
+    let cyclic = "c0ffee0000000000000000000000000000000000";
+    let content = format!(
+        "tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904\nparent {}\n\
+         author A <a@example.com> 1 +0000\ncommitter A <a@example.com> 1 +0000\n\nloop\n",
+        cyclic
+    );
+    write_raw_object(&repo, cyclic, "commit", content.as_bytes());
+    repo.write(".fit/refs/heads/master", &format!("{}\n", cyclic));
+
+    let error = repo.fails(&["log"], 128);
+    assert!(
+        error.contains(&format!("commit {} is its own ancestor", cyclic)),
+        "{}",
+        error
+    );
+    let error = repo.fails(&["log", "--first-parent"], 128);
+    assert!(error.contains("is its own ancestor"), "{}", error);
+}
+
+#[test]
+fn log_since_and_until_window_commits() {
+    let repo = Repo::new();
+    for year in ["2001", "2002", "2003"] {
+        let date = format!("{}-01-01T12:00:00Z", year);
+        repo.write("f.txt", year);
+        repo.ok(&["add", "f.txt"]);
+        let output = repo.run_with(
+            &["commit", "-m", &format!("in {}", year), "--date", &date],
+            None,
+            &[("FIT_COMMITTER_DATE", &date)],
+        );
+        assert!(output.status.success(), "{}", stderr(&output));
+    }
+
+    let window = repo.ok(&[
+        "log",
+        "--oneline",
+        "--since",
+        "2001-06-01",
+        "--until",
+        "2002-06-01",
+    ]);
+    assert_eq!(window.lines().count(), 1, "{}", window);
+    assert!(window.ends_with(" in 2002\n"), "{}", window);
+
+    let since = repo.ok(&["log", "--oneline", "--since", "2002-01-01"]);
+    assert_eq!(since.lines().count(), 2, "{}", since);
+}
+
+#[test]
+fn log_all_includes_every_branch() {
+    let repo = Repo::new();
+    repo.commit_file("a.txt", "a\n", "base");
+    repo.ok(&["branch", "checkout-new", "left"]);
+    repo.commit_file("left.txt", "l\n", "only on left");
+    repo.ok(&["branch", "checkout", "master"]);
+    repo.commit_file("master.txt", "m\n", "only on master");
+
+    let plain = repo.ok(&["log", "--oneline"]);
+    assert!(!plain.contains("only on left"), "{}", plain);
+
+    let all = repo.ok(&["log", "--oneline", "--all"]);
+    assert!(all.contains(" only on left\n"), "{}", all);
+    assert!(all.contains(" only on master\n"), "{}", all);
+    assert_eq!(all.lines().count(), 4, "{}", all);
+}
+
+#[test]
+fn log_reports_an_empty_branch_cleanly() {
+    let repo = Repo::new();
+    repo.write(".fit/refs/heads/master", "");
+
+    assert_eq!(repo.ok(&["log"]), "no commits yet\n");
+
+    repo.remove(".fit/refs/heads/master");
+    assert_eq!(repo.ok(&["log"]), "no commits yet\n");
+}
+
+#[test]
+fn log_reverse_lists_the_oldest_commit_first() {
+    let repo = Repo::new();
+    repo.commit_file("a.txt", "1\n", "one");
+    repo.commit_file("a.txt", "2\n", "two");
+
+    let log = repo.ok(&["log", "--oneline", "--reverse"]);
+
+    let subjects: Vec<&str> = log.lines().map(|line| &line[8..]).collect();
+    assert_eq!(subjects, ["Initial commit", "one", "two"]);
+}
+
+#[test]
+fn log_follow_continues_past_a_rename() {
+    let repo = Repo::new();
+    repo.commit_file("old.txt", "v1\n", "create old");
+    repo.commit_file("old.txt", "v2\n", "edit old");
+    std::fs::rename(repo.path("old.txt"), repo.path("new.txt")).unwrap();
+    repo.ok(&["rm", "old.txt"]);
+    repo.ok(&["add", "new.txt"]);
+    repo.commit("rename");
+
+    let plain = repo.ok(&["log", "--oneline", "new.txt"]);
+    assert_eq!(plain.lines().count(), 1, "{}", plain);
+
+    let followed = repo.ok(&["log", "--oneline", "--follow", "new.txt"]);
+    let subjects: Vec<&str> = followed.lines().map(|line| &line[8..]).collect();
+    assert_eq!(subjects, ["rename", "edit old", "create old"]);
+}
+
+#[test]
+fn log_shows_a_commits_notes() {
+    let repo = Repo::new();
+    let first = repo.commit_file("a.txt", "a\n", "first");
+    repo.commit_file("a.txt", "b\n", "second");
+
+    repo.ok(&["notes", "add", "-m", "reviewed by QA", &first]);
+
+    let log = repo.ok(&["log"]);
+    let (newer, older) = log.split_once(&format!("commit {}", first)).unwrap();
+    assert!(!newer.contains("Notes:"), "{}", log);
+    assert!(
+        older.contains("    first\n\nNotes:\n    reviewed by QA\n"),
+        "{}",
+        log
+    );
+    assert_eq!(repo.ok(&["notes", "show", &first]), "reviewed by QA\n");
+}
+
+#[test]
+fn shortlog_groups_commits_by_author() {
+    let repo = Repo::new();
+    for (n, author) in [
+        "Alice <alice@example.com>",
+        "Bob <bob@example.com>",
+        "Alice <alice@example.com>",
+    ]
+    .iter()
+    .enumerate()
+    {
+        repo.write("f.txt", &n.to_string());
+        repo.ok(&["add", "f.txt"]);
+        repo.ok(&["commit", "-m", &format!("change {}", n), "--author", author]);
+    }
+
+    assert_eq!(
+        repo.ok(&["shortlog", "-s", "-n"]),
+        "     2\tAlice\n     1\tBob\n"
+    );
+    assert_eq!(
+        repo.ok(&["shortlog"]),
+        "Alice (2):\n      change 0\n      change 2\n\nBob (1):\n      change 1\n\n"
+    );
+}
+
+#[test]
+fn log_first_parent_skips_merged_in_commits() {
+    let repo = Repo::new();
+    repo.commit_file("a.txt", "a\n", "base");
+    repo.ok(&["branch", "checkout-new", "feature"]);
+    repo.commit_file("feature.txt", "f\n", "feature only");
+    repo.ok(&["branch", "checkout", "master"]);
+    repo.commit_file("main.txt", "m\n", "mainline");
+    repo.ok(&["merge", "feature"]);
+
+    let all = repo.ok(&["log", "--oneline"]);
+    assert!(all.contains("feature only"), "{}", all);
+
+    let mainline = repo.ok(&["log", "--oneline", "--first-parent"]);
+    assert!(!mainline.contains("feature only"), "{}", mainline);
+    assert!(mainline.contains("mainline"), "{}", mainline);
+    assert!(mainline.contains("base"), "{}", mainline);
+    assert_eq!(mainline.lines().count(), all.lines().count() - 1);
+}
@@ -0,0 +1,21 @@
+// End-to-end tests for the fit binary. Every test runs fit in its own temporary repository and
+// asserts on what it leaves behind: refs, objects, the working tree, output and exit codes.
+
+mod branch;
+mod checkout;
+mod commit;
+mod common;
+mod diff;
+mod errors;
+mod index;
+mod log;
+mod maintenance;
+mod merge;
+mod misc;
+mod objects;
+mod refs;
+mod remote;
+mod reset;
+mod stash;
+mod tags;
+mod worktree;
@@ -0,0 +1,180 @@
+use crate::common::*;
+
+// Backdates every reflog entry moving to or away from `commit` by `days`
+fn age_reflog_entries(repo: &Repo, reference: &str, commit: &str, days: i64) {
+    let path = format!(".fit/logs/{}", reference);
+    let log = repo.read(&path);
+    let mut aged = String::new();
+    for line in log.lines() {
+        let (entry, message) = line.split_once('\t').unwrap();
+        let fields: Vec<&str> = entry.rsplitn(3, ' ').collect();
+        let (zone, timestamp, rest) = (fields[0], fields[1], fields[2]);
+        let timestamp: i64 = timestamp.parse().unwrap();
+        let mut values = rest.split(' ').take(2);
+        let timestamp = if values.any(|value| value == commit) {
+            timestamp - days * 86400
+        } else {
+            timestamp
+        };
+        aged.push_str(&format!("{} {} {}\t{}\n", rest, timestamp, zone, message));
+    }
+    repo.write(&path, &aged);
+}
+
+#[test]
+fn prune_keeps_objects_reachable_from_recent_reflog_entries() {
+    let repo = Repo::new();
+    let base = repo.commit_file("a.txt", "base\n", "base");
+    let old = repo.commit_file("a.txt", "old\n", "old");
+    repo.ok(&["reset", &base]);
+    let recent = repo.commit_file("a.txt", "recent\n", "recent");
+    repo.ok(&["reset", &base]);
+    age_reflog_entries(&repo, "HEAD", &old, 120);
+    age_reflog_entries(&repo, "refs/heads/master", &old, 120);
+
+    let output = repo.ok(&["prune", "--expire", "30.days"]);
+
+    assert!(output.contains("Pruned "), "{}", output);
+    assert!(!repo.loose_object_path(&old).exists());
+    assert_eq!(repo.object(&recent).0, "commit");
+    assert_eq!(repo.object(&base).0, "commit");
+    let log = repo.read(".fit/logs/refs/heads/master");
+    assert!(!log.contains(&format!(" {} ", old)), "{}", log);
+    assert!(log.contains(&recent), "{}", log);
+    assert!(!repo.exists(".fit/logs/refs/heads/master.lock"));
+}
+
+#[test]
+fn verify_pack_flags_a_corrupted_entry() {
+    let repo = Repo::new();
+    for n in 1..=3 {
+        let lines: String = (0..n * 100).map(|line| format!("{}\n", line)).collect();
+        repo.write(&format!("f{}.txt", n), &lines);
+    }
+    repo.ok(&["add", "."]);
+    let commit = repo.commit("three files");
+    repo.ok(&["gc"]);
+    let pack = only_pack(&repo);
+    assert!(repo
+        .ok(&["verify-pack", pack.to_str().unwrap()])
+        .contains(": ok, 7 objects"));
+    assert_eq!(repo.object(&commit).0, "commit");
+
+    let listing = repo.ok(&["verify-pack", "-v", pack.to_str().unwrap()]);
+    let tree_line = listing
+        .lines()
+        .find(|line| line.contains(" tree "))
+        .unwrap();
+    let fields: Vec<&str> = tree_line.split(' ').collect();
+    let offset: usize = fields[3].parse().unwrap();
+    let mut bytes = std::fs::read(&pack).unwrap();
+    bytes[offset + 4] ^= 0xff;
+    std::fs::write(&pack, bytes).unwrap();
+
+    let output = repo.run(&["verify-pack", pack.to_str().unwrap()]);
+
+    assert_eq!(output.status.code(), Some(128));
+    assert!(
+        stdout(&output).contains(&format!("{}: CRC mismatch", fields[0])),
+        "{}",
+        stdout(&output)
+    );
+    assert!(
+        stderr(&output).contains("1 corrupt objects"),
+        "{}",
+        stderr(&output)
+    );
+}
+
+#[test]
+fn aggressive_gc_stores_a_similar_blob_as_a_delta() {
+    let original = noise(200_000, 7);
+    let similar = format!("{}CHANGED{}", &original[..100_000], &original[100_000..]);
+    let mut pack_sizes = Vec::new();
+    for gc_args in [&["gc"][..], &["gc", "--aggressive"][..]] {
+        let repo = Repo::new();
+        repo.write("original.txt", &original);
+        repo.write("similar.txt", &similar);
+        repo.ok(&["add", "."]);
+        repo.commit("two similar files");
+        let original_blob = repo.ok(&["hash-object", "original.txt"]).trim().to_string();
+        let similar_blob = repo.ok(&["hash-object", "similar.txt"]).trim().to_string();
+
+        repo.ok(gc_args);
+
+        pack_sizes.push(std::fs::metadata(only_pack(&repo)).unwrap().len());
+        assert_eq!(repo.object_text(&original_blob), original);
+        assert_eq!(repo.object_text(&similar_blob), similar);
+    }
+
+    // Without --aggressive both blobs are stored whole
+    assert!(pack_sizes[1] * 3 < pack_sizes[0] * 2, "{:?}", pack_sizes);
+}
+
+#[test]
+fn gc_keeps_fresh_unreachable_objects_until_pruned_now() {
+    let repo = Repo::new();
+    repo.commit_file("a.txt", "a\n", "first");
+    let orphan = store_object(&repo, "blob", "nobody points at me\n");
+
+    repo.ok(&["gc"]);
+    assert_eq!(repo.object_text(&orphan), "nobody points at me\n");
+    assert!(repo.loose_object_path(&orphan).exists());
+
+    let output = repo.ok(&["gc", "--prune=now"]);
+    assert!(
+        output.contains("Pruned 1 unreachable objects"),
+        "{}",
+        output
+    );
+    assert!(!repo.loose_object_path(&orphan).exists());
+    let batch = repo.run_with(
+        &["catfile", "--batch"],
+        Some(format!("{}\n", orphan).as_bytes()),
+        &[],
+    );
+    assert_eq!(stdout(&batch), format!("{} missing\n", orphan));
+}
+
+#[test]
+fn clean_d_removes_untracked_directories() {
+    let repo = Repo::new();
+    repo.commit_file("kept.txt", "kept\n", "first");
+    repo.write("junk/deep/file.txt", "junk\n");
+
+    let error = repo.fails(&["clean", "-d"], 1);
+    assert!(!error.is_empty());
+    assert!(repo.exists("junk/deep/file.txt"));
+
+    assert_eq!(repo.ok(&["clean", "-n", "-d"]), "Would remove junk/\n");
+    assert!(repo.exists("junk/deep/file.txt"));
+
+    assert_eq!(repo.ok(&["clean", "-df"]), "Removing junk/\n");
+    assert!(!repo.exists("junk"));
+    assert!(repo.exists("kept.txt"));
+}
+
+#[test]
+fn count_objects_verbose_splits_loose_and_packed() {
+    let repo = Repo::new();
+    repo.commit_file("a.txt", "a\n", "first");
+    repo.ok(&["gc"]);
+    repo.commit_file("big.txt", &"x".repeat(5000), "second");
+    let big = store_object(&repo, "blob", &"x".repeat(5000));
+
+    let output = repo.ok(&["count-objects", "-v"]);
+
+    let field = |name: &str| -> String {
+        output
+            .lines()
+            .find_map(|line| line.strip_prefix(&format!("{}: ", name)))
+            .unwrap_or_else(|| panic!("no {} in {}", name, output))
+            .to_string()
+    };
+    assert_eq!(field("count"), "3");
+    assert_ne!(field("in-pack"), "0");
+    assert_eq!(field("packs"), "1");
+    assert_ne!(field("size-pack"), "0");
+    assert_eq!(field("refs"), "1");
+    assert_eq!(field("largest"), format!("5000 bytes ({})", big));
+}
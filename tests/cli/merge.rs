@@ -0,0 +1,150 @@
+use crate::common::*;
+
+// The first-parent chain from HEAD as (hash, subject) pairs, newest first
+fn first_parent_chain(repo: &Repo) -> Vec<(String, String)> {
+    let mut chain = Vec::new();
+    let mut commit = repo.head();
+    loop {
+        let fields = repo.commit_fields(&commit);
+        chain.push((commit.clone(), fields[""].trim().to_string()));
+        match fields.get("parent") {
+            Some(parent) => commit = parent.clone(),
+            None => return chain,
+        }
+    }
+}
+
+#[test]
+fn rebase_replays_feature_commits_onto_master() {
+    let repo = Repo::new();
+    repo.commit_file("a.txt", "a\n", "base");
+    repo.ok(&["branch", "checkout-new", "feature"]);
+    repo.commit_file("f1.txt", "1\n", "feature one");
+    repo.commit_file("f2.txt", "2\n", "feature two");
+    repo.ok(&["branch", "checkout", "master"]);
+    let master = repo.commit_file("m.txt", "m\n", "master work");
+    repo.ok(&["branch", "checkout", "feature"]);
+
+    repo.ok(&["rebase", "master"]);
+
+    let chain = first_parent_chain(&repo);
+    let subjects: Vec<&str> = chain.iter().map(|(_, subject)| subject.as_str()).collect();
+    assert_eq!(
+        subjects,
+        [
+            "feature two",
+            "feature one",
+            "master work",
+            "base",
+            "Initial commit"
+        ]
+    );
+    assert_eq!(chain[2].0, master);
+    for (commit, _) in &chain {
+        assert!(repo.object_text(commit).matches("parent ").count() <= 1);
+    }
+    assert_eq!(repo.read_ref("refs/heads/master"), Some(master));
+    assert_eq!(repo.read("f1.txt"), "1\n");
+    assert_eq!(repo.read("m.txt"), "m\n");
+}
+
+#[test]
+fn rebase_conflict_keeps_clean_changes_and_continues() {
+    let repo = Repo::new();
+    repo.write("a", "base\n");
+    repo.write("b", "base\n");
+    repo.ok(&["add", "."]);
+    repo.commit("base");
+    repo.ok(&["branch", "checkout-new", "feature"]);
+    repo.write("a", "feature\n");
+    repo.write("b", "feature\n");
+    repo.ok(&["add", "."]);
+    repo.commit("feature both");
+    repo.commit_file("c", "c\n", "feature after");
+    repo.ok(&["branch", "checkout", "master"]);
+    let master = repo.commit_file("a", "master\n", "master change");
+    repo.ok(&["branch", "checkout", "feature"]);
+
+    let error = repo.fails(&["rebase", "master"], 1);
+    assert!(error.contains("fit rebase --continue"), "{}", error);
+    assert!(repo
+        .read("a")
+        .contains("<<<<<<< HEAD\nmaster\n=======\nfeature\n"));
+    assert_eq!(repo.read("b"), "feature\n");
+    let feature_b = store_object(&repo, "blob", "feature\n");
+    assert!(repo.staging().contains(&feature_b), "{}", repo.staging());
+
+    repo.fails(&["rebase", "--continue"], 1);
+    repo.write("a", "resolved\n");
+    repo.ok(&["add", "a"]);
+    repo.ok(&["rebase", "--continue"]);
+
+    assert!(!repo.exists(".fit/MERGE_STATE"));
+    let chain = first_parent_chain(&repo);
+    let subjects: Vec<&str> = chain.iter().map(|(_, subject)| subject.as_str()).collect();
+    assert_eq!(
+        subjects,
+        [
+            "feature after",
+            "feature both",
+            "master change",
+            "base",
+            "Initial commit"
+        ]
+    );
+    assert_eq!(chain[2].0, master);
+    let files = repo.ok(&["catfile", "-r", &repo.head()]);
+    assert_eq!(repo.read("a"), "resolved\n");
+    assert_eq!(repo.read("b"), "feature\n");
+    assert!(files.contains(&feature_b), "{}", files);
+    assert_eq!(repo.read("c"), "c\n");
+    repo.ok(&["rebase", "master"]);
+}
+
+#[test]
+fn squash_merge_stages_the_branch_without_a_merge_commit() {
+    let repo = Repo::new();
+    repo.commit_file("a.txt", "a\n", "base");
+    repo.ok(&["branch", "checkout-new", "feature"]);
+    repo.commit_file("f1.txt", "one\n", "feature one");
+    repo.commit_file("f2.txt", "two\n", "feature two");
+    repo.ok(&["branch", "checkout", "master"]);
+    let master = repo.head();
+
+    repo.ok(&["merge", "--squash", "feature"]);
+
+    assert_eq!(repo.head(), master);
+    assert!(!repo.exists(".fit/MERGE_STATE"));
+    let status = repo.ok(&["status"]);
+    let mut staged = status_section(&status, "Changes to be committed:");
+    staged.sort();
+    assert_eq!(staged, ["new file: f1.txt", "new file: f2.txt"]);
+    assert_eq!(repo.read("f2.txt"), "two\n");
+
+    let squashed = repo.commit("squash feature");
+    let fields = repo.commit_fields(&squashed);
+    assert_eq!(fields["parent"], master);
+    assert_eq!(repo.object_text(&squashed).matches("\nparent ").count(), 1);
+}
+
+#[test]
+fn checkout_theirs_stages_the_merged_branch_version() {
+    let repo = Repo::new();
+    repo.commit_file("a", "base\n", "base");
+    repo.ok(&["branch", "checkout-new", "feature"]);
+    repo.commit_file("a", "feature\n", "feature change");
+    repo.ok(&["branch", "checkout", "master"]);
+    repo.commit_file("a", "master\n", "master change");
+
+    let error = repo.fails(&["merge", "feature"], 1);
+    assert!(error.contains("merge conflict"), "{}", error);
+    assert!(repo.read(".fit/MERGE_STATE").contains("conflict a"));
+
+    repo.ok(&["checkout", "--theirs", "--", "a"]);
+
+    assert_eq!(repo.read("a"), "feature\n");
+    let staged = repo.staging();
+    let blob = store_object(&repo, "blob", "feature\n");
+    assert!(staged.contains(&blob), "{}", staged);
+    assert!(!repo.read(".fit/MERGE_STATE").contains("conflict a"));
+}
@@ -0,0 +1,158 @@
+use crate::common::*;
+
+#[test]
+fn describe_repo_sets_and_reads_the_description() {
+    let repo = Repo::new();
+    let default = repo.ok(&["describe-repo"]);
+    assert!(default.starts_with("Unnamed repository"), "{}", default);
+
+    repo.ok(&["describe-repo", "A test project"]);
+
+    assert_eq!(repo.ok(&["describe-repo"]), "A test project\n");
+    assert_eq!(repo.read(".fit/description").trim(), "A test project");
+}
+
+#[test]
+fn bare_init_has_no_work_tree() {
+    let repo = Repo::init_in("bare", &["init", "--bare"]);
+
+    assert!(repo.exists("HEAD"));
+    assert!(repo.exists("objects"));
+    assert!(!repo.exists(".fit"));
+    assert!(!repo.exists("index"));
+    repo.write("a.txt", "a\n");
+    let error = repo.fails(&["add", "a.txt"], 128);
+    assert!(error.contains("must be run in a work tree"), "{}", error);
+    assert!(!repo.exists("index"));
+}
+
+// (path, content) for each regular file in a ustar archive
+fn tar_entries(archive: &[u8]) -> Vec<(String, Vec<u8>)> {
+    let mut entries = Vec::new();
+    let mut offset = 0;
+    while offset + 512 <= archive.len() && archive[offset] != 0 {
+        let header = &archive[offset..offset + 512];
+        let field = |range: std::ops::Range<usize>| {
+            let bytes = &header[range];
+            let end = bytes
+                .iter()
+                .position(|byte| *byte == 0)
+                .unwrap_or(bytes.len());
+            String::from_utf8_lossy(&bytes[..end]).trim().to_string()
+        };
+        let prefix = field(345..500);
+        let name = field(0..100);
+        let path = if prefix.is_empty() {
+            name
+        } else {
+            format!("{}/{}", prefix, name)
+        };
+        let size = usize::from_str_radix(&field(124..136), 8).unwrap();
+        let content = archive[offset + 512..offset + 512 + size].to_vec();
+        if header[156] == b'0' || header[156] == 0 {
+            entries.push((path, content));
+        }
+        offset += 512 + size.div_ceil(512) * 512;
+    }
+    entries
+}
+
+#[test]
+fn archive_writes_the_commits_files_as_tar() {
+    let repo = Repo::new();
+    repo.write("a.txt", "top level\n");
+    repo.write("dir/b.txt", "nested\n");
+    repo.ok(&["add", "."]);
+    let commit = repo.commit("two files");
+    repo.write("a.txt", "not committed\n");
+
+    let archive_path = repo.scratch.root.join("out.tar");
+    repo.ok(&["archive", &commit, "-o", archive_path.to_str().unwrap()]);
+
+    let entries = tar_entries(&std::fs::read(&archive_path).unwrap());
+    assert_eq!(
+        entries,
+        [
+            ("a.txt".to_string(), b"top level\n".to_vec()),
+            ("dir/b.txt".to_string(), b"nested\n".to_vec()),
+        ]
+    );
+    let piped = repo.run(&["archive", "master"]);
+    assert_eq!(tar_entries(&piped.stdout), entries);
+}
+
+#[test]
+fn grep_prints_matching_lines_with_their_file() {
+    let repo = Repo::new();
+    repo.write("a.txt", "first\nfind the needle here\nlast\n");
+    repo.write("b.txt", "only hay\n");
+    repo.ok(&["add", "."]);
+    let commit = repo.commit("haystack");
+    repo.write("untracked.txt", "another needle\n");
+
+    assert_eq!(
+        repo.ok(&["grep", "needle"]),
+        "a.txt:2:find the needle here\n"
+    );
+    repo.write("a.txt", "changed since\n");
+    assert_eq!(
+        repo.ok(&["grep", "-i", "NEEDLE", &commit]),
+        "a.txt:2:find the needle here\n"
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn ceiling_directories_stop_the_repository_search() {
+    let repo = Repo::new();
+    let deep = repo.path("sub/deep");
+    std::fs::create_dir_all(&deep).unwrap();
+
+    // Found, but fit has to be run from the root
+    let found = fit_command(&deep, &["status"], None, &[]);
+    assert_eq!(found.status.code(), Some(128));
+    assert!(
+        stderr(&found).contains("not at the root of a fit repository"),
+        "{}",
+        stderr(&found)
+    );
+
+    let ceiling = repo.path("sub");
+    let stopped = fit_command(
+        &deep,
+        &["status"],
+        None,
+        &[("FIT_CEILING_DIRECTORIES", ceiling.to_str().unwrap())],
+    );
+    assert_eq!(stopped.status.code(), Some(128));
+    assert!(
+        stderr(&stopped).contains("not a fit repository"),
+        "{}",
+        stderr(&stopped)
+    );
+}
+
+#[test]
+fn format_patch_writes_one_mail_per_commit() {
+    let repo = Repo::new();
+    let base = repo.commit_file("a.txt", "old line\n", "first");
+    repo.commit_file("a.txt", "new line\n", "Fix the widget");
+    let out = repo.scratch.root.join("patches");
+
+    let output = repo.ok(&["format-patch", &base, "-o", out.to_str().unwrap()]);
+
+    let patch_path = out.join("0001-Fix-the-widget.patch");
+    assert!(output.contains("0001-Fix-the-widget.patch"), "{}", output);
+    let patch = std::fs::read_to_string(patch_path).unwrap();
+    assert!(
+        patch.contains("Subject: [PATCH] Fix the widget\n"),
+        "{}",
+        patch
+    );
+    assert!(
+        patch.contains("From: Test User <test@example.com>\n"),
+        "{}",
+        patch
+    );
+    assert!(patch.contains("-old line\n+new line\n"), "{}", patch);
+}
@@ -0,0 +1,434 @@
+use crate::common::*;
+
+#[test]
+fn verify_commit_flags_missing_tree() {
+    let repo = Repo::new();
+    let content = "tree 1111111111111111111111111111111111111111\n\
+                   author A <a@example.com> 1 +0000\n\
+                   committer A <a@example.com> 1 +0000\n\nbroken\n";
+    let output = repo.run_with(
+        &["hash-object", "-w", "-t", "commit", "--stdin"],
+        Some(content.as_bytes()),
+        &[],
+    );
+    let commit = stdout(&output).trim().to_string();
+
+    let error = repo.fails(&["verify-commit", &commit], 128);
+
+    assert!(
+        error.contains("tree 1111111111111111111111111111111111111111 does not exist"),
+        "{}",
+        error
+    );
+}
+
+#[test]
+fn verify_commit_accepts_well_formed_commit() {
+    let repo = Repo::new();
+    let commit = repo.commit_file("a.txt", "a\n", "first");
+
+    assert_eq!(
+        repo.ok(&["verify-commit", &commit]),
+        format!("commit {} is well-formed\n", commit)
+    );
+}
+
+#[test]
+fn compression_level_changes_object_size_but_not_content() {
+    let content = "a fairly repetitive line of text\n".repeat(200);
+    let mut sizes = Vec::new();
+    for level in ["0", "9"] {
+        let repo = Repo::new();
+        repo.append_config(&format!("[core]\n\tcompression = {}\n", level));
+        repo.write("big.txt", &content);
+        let hash = repo
+            .ok(&["hash-object", "-w", "big.txt"])
+            .trim()
+            .to_string();
+
+        assert_eq!(
+            repo.object(&hash),
+            ("blob".to_string(), content.clone().into_bytes())
+        );
+        sizes.push(
+            std::fs::metadata(repo.loose_object_path(&hash))
+                .unwrap()
+                .len(),
+        );
+    }
+
+    assert!(sizes[0] > content.len() as u64, "{:?}", sizes);
+    assert!(sizes[1] < sizes[0] / 10, "{:?}", sizes);
+}
+
+#[test]
+fn count_objects_counts_each_type() {
+    let repo = Repo::new();
+    repo.write("a.txt", "a\n");
+    repo.write("dir/b.txt", "b\n");
+    repo.ok(&["add", "."]);
+    repo.commit("two files");
+
+    let output = repo.ok(&["count-objects"]);
+
+    // init wrote the initial commit and the empty tree; the commit added two blobs, the root
+    // and dir trees and itself
+    assert!(output.starts_with("count: 7\n"), "{}", output);
+    assert!(output.contains("blob: 2\n"), "{}", output);
+    assert!(output.contains("tree: 3\n"), "{}", output);
+    assert!(output.contains("commit: 2\n"), "{}", output);
+}
+
+#[test]
+fn catfile_batch_prints_each_object_and_flags_missing_ones() {
+    let repo = Repo::new();
+    let commit = repo.commit_file("a.txt", "hello\n", "first");
+    let blob = repo.ok(&["hash-object", "a.txt"]).trim().to_string();
+    let commit_content = repo.object_text(&commit);
+    let input = format!("{}\nbogus\n{}\n", blob, commit);
+
+    let output = repo.run_with(&["catfile", "--batch"], Some(input.as_bytes()), &[]);
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        stdout(&output),
+        format!(
+            "{} blob 6\nhello\n\nbogus missing\n{} commit {}\n{}\n",
+            blob,
+            commit,
+            commit_content.len(),
+            commit_content
+        )
+    );
+}
+
+#[test]
+fn catfile_rejects_a_short_name_cleanly() {
+    let repo = Repo::new();
+
+    let output = repo.run(&["catfile", "xyz"]);
+
+    assert_eq!(output.status.code(), Some(128));
+    assert!(
+        stderr(&output).contains("not a valid object name 'xyz'"),
+        "{}",
+        stderr(&output)
+    );
+    assert!(!stderr(&output).contains("panicked"), "{}", stderr(&output));
+}
+
+#[test]
+fn large_file_round_trips_with_the_expected_hash() {
+    use sha1::{Digest, Sha1};
+
+    let repo = Repo::new();
+    let content: Vec<u8> = (0..3_000_000u32).map(|n| (n % 251) as u8).collect();
+    repo.write_bytes("big.bin", &content);
+
+    repo.ok(&["add", "big.bin"]);
+
+    let mut hasher = Sha1::new();
+    hasher.update(format!("blob {}\0", content.len()));
+    hasher.update(&content);
+    let expected = format!("{:x}", hasher.finalize());
+    assert!(repo.staging().contains(&expected), "{}", repo.staging());
+    assert_eq!(repo.object(&expected), ("blob".to_string(), content));
+}
+
+#[test]
+fn catfile_recursive_lists_every_leaf_of_a_nested_tree() {
+    let repo = Repo::new();
+    repo.write("top.txt", "top\n");
+    repo.write("dir/mid.txt", "mid\n");
+    repo.write("dir/sub/leaf.txt", "leaf\n");
+    repo.ok(&["add", "."]);
+    let commit = repo.commit("nested");
+    let hash = |path: &str| repo.ok(&["hash-object", path]).trim().to_string();
+
+    let listing = repo.ok(&["catfile", "-r", &commit]);
+
+    assert_eq!(
+        listing,
+        format!(
+            "100644 blob {}\tdir/mid.txt\n100644 blob {}\tdir/sub/leaf.txt\n100644 blob {}\ttop.txt\n",
+            hash("dir/mid.txt"),
+            hash("dir/sub/leaf.txt"),
+            hash("top.txt")
+        )
+    );
+    let tree = repo.commit_fields(&commit)["tree"].clone();
+    assert_eq!(repo.ok(&["catfile", "-r", &tree]), listing);
+}
+
+#[test]
+fn hash_object_without_write_leaves_the_store_alone() {
+    let repo = Repo::new();
+    repo.write("hello.txt", "hello\n");
+    let objects = repo.loose_objects();
+
+    let hash = repo.ok(&["hash-object", "hello.txt"]);
+
+    assert_eq!(hash, "ce013625030ba8dba906f756967f9e9ca394464a\n");
+    assert_eq!(repo.loose_objects(), objects);
+}
+
+#[test]
+fn hash_object_write_stores_the_blob() {
+    let repo = Repo::new();
+    repo.write("hello.txt", "hello\n");
+
+    let hash = repo.ok(&["hash-object", "-w", "hello.txt"]);
+
+    assert_eq!(hash, "ce013625030ba8dba906f756967f9e9ca394464a\n");
+    assert_eq!(
+        repo.object(hash.trim()),
+        ("blob".to_string(), b"hello\n".to_vec())
+    );
+}
+
+#[test]
+fn hash_object_reads_stdin() {
+    let repo = Repo::new();
+
+    let output = repo.run_with(&["hash-object", "--stdin"], Some(b"hello\n"), &[]);
+
+    assert_eq!(
+        stdout(&output),
+        "ce013625030ba8dba906f756967f9e9ca394464a\n"
+    );
+    assert!(!repo
+        .loose_object_path("ce013625030ba8dba906f756967f9e9ca394464a")
+        .exists());
+}
+
+#[test]
+fn write_tree_and_commit_tree_build_a_readable_commit() {
+    let repo = Repo::new();
+    repo.write("a.txt", "a\n");
+    repo.write("dir/b.txt", "b\n");
+    repo.ok(&["add", "."]);
+
+    let tree = repo.ok(&["write-tree"]).trim().to_string();
+    assert_eq!(repo.object(&tree).0, "tree");
+    let listing = repo.ok(&["catfile", "-r", &tree]);
+    assert!(
+        listing.contains("\ta.txt\n") && listing.contains("\tdir/b.txt\n"),
+        "{}",
+        listing
+    );
+
+    let commit = repo
+        .ok(&[
+            "commit-tree",
+            &tree,
+            "-m",
+            "built by hand",
+            "-p",
+            INITIAL_COMMIT,
+        ])
+        .trim()
+        .to_string();
+    let fields = repo.commit_fields(&commit);
+    assert_eq!(fields["tree"], tree);
+    assert_eq!(fields["parent"], INITIAL_COMMIT);
+    // commit-tree doesn't move any ref
+    assert_eq!(repo.head(), INITIAL_COMMIT);
+
+    repo.ok(&["update-ref", "refs/heads/master", &commit]);
+    let log = repo.ok(&["log", "--oneline"]);
+    assert_eq!(
+        log,
+        format!("{} built by hand\ne8666b9 Initial commit\n", &commit[..7])
+    );
+}
+
+fn copy_dir(from: &std::path::Path, to: &std::path::Path) {
+    std::fs::create_dir_all(to).unwrap();
+    for entry in std::fs::read_dir(from).unwrap() {
+        let entry = entry.unwrap();
+        let target = to.join(entry.file_name());
+        if entry.file_type().unwrap().is_dir() {
+            copy_dir(&entry.path(), &target);
+        } else {
+            std::fs::copy(entry.path(), target).unwrap();
+        }
+    }
+}
+
+#[test]
+fn objects_written_by_git_can_be_read() {
+    let scratch = Scratch::new();
+    let git_dir = scratch.root.join("git");
+    std::fs::create_dir_all(git_dir.join("dir")).unwrap();
+    let git = |args: &[&str]| {
+        std::process::Command::new("git")
+            .args([
+                "-c",
+                "user.name=Git User",
+                "-c",
+                "user.email=git@example.com",
+            ])
+            .args(args)
+            .current_dir(&git_dir)
+            .env("GIT_CONFIG_NOSYSTEM", "1")
+            .env("HOME", &scratch.root)
+            .output()
+    };
+    // Skip where git isn't installed
+    let Ok(init) = git(&["init", "-q"]) else {
+        return;
+    };
+    assert!(init.status.success());
+    std::fs::write(git_dir.join("a.txt"), "from git\n").unwrap();
+    std::fs::write(git_dir.join("dir/b.txt"), "nested\n").unwrap();
+    git(&["add", "."]).unwrap();
+    assert!(git(&["commit", "-q", "-m", "made by git"])
+        .unwrap()
+        .status
+        .success());
+    let head = git(&["rev-parse", "HEAD"]).unwrap();
+    let commit = String::from_utf8(head.stdout).unwrap().trim().to_string();
+
+    let repo = Repo::new();
+    copy_dir(&git_dir.join(".git/objects"), &repo.path(".fit/objects"));
+    repo.write(".fit/refs/heads/master", &commit);
+
+    let log = repo.ok(&["log", "--oneline"]);
+    assert_eq!(log, format!("{} made by git\n", &commit[..7]));
+    let listing = repo.ok(&["catfile", "-r", &commit]);
+    assert!(
+        listing.contains("\ta.txt\n") && listing.contains("\tdir/b.txt\n"),
+        "{}",
+        listing
+    );
+    repo.ok(&["reset", &commit]);
+    assert_eq!(repo.read("dir/b.txt"), "nested\n");
+}
+
+#[cfg(unix)]
+#[test]
+fn signed_commit_verifies_and_tampering_fails() {
+    let repo = Repo::new();
+    fake_signer(&repo);
+    repo.write("a.txt", "a\n");
+    repo.ok(&["add", "a.txt"]);
+    repo.ok(&["commit", "-S", "-m", "signed"]);
+    let head = repo.head();
+
+    assert!(repo
+        .object_text(&head)
+        .contains("gpgsig -----BEGIN PGP SIGNATURE-----"));
+    let output = repo.ok(&["verify-commit", "--signature", &head]);
+    assert!(
+        output.contains(&format!("Good signature on commit {}", head)),
+        "{}",
+        output
+    );
+    let log = repo.ok(&["log", "--show-signature"]);
+    assert!(log.contains("Good signature"), "{}", log);
+
+    let forged = store_object(
+        &repo,
+        "commit",
+        &repo.object_text(&head).replace("signed", "forged"),
+    );
+    let error = repo.fails(&["verify-commit", "--signature", &forged], 1);
+    assert!(error.contains("could not be verified"), "{}", error);
+
+    let unsigned = repo.commit_file("b.txt", "b\n", "unsigned");
+    let error = repo.fails(&["verify-commit", "--signature", &unsigned], 1);
+    assert!(error.contains("is not signed"), "{}", error);
+
+    let leftovers: Vec<_> = std::fs::read_dir(repo.path(".fit"))
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+        .filter(|name| name.starts_with("SIGNATURE"))
+        .collect();
+    assert!(leftovers.is_empty(), "{:?}", leftovers);
+}
+
+#[test]
+fn alternates_resolve_objects_from_another_repository() {
+    let first = Repo::new();
+    let shared = store_object(&first, "blob", "shared\n");
+    let second = first.sibling("second");
+    std::fs::create_dir_all(second.join(".fit/objects/info")).unwrap();
+    std::fs::write(
+        second.join(".fit/objects/info/alternates"),
+        format!("{}\n", first.path(".fit/objects").display()),
+    )
+    .unwrap();
+
+    let output = fit_in(&second, &["catfile", &shared]);
+    assert!(output.contains("Content:\nshared\n"), "{}", output);
+    assert!(!second
+        .join(".fit/objects")
+        .join(&shared[..2])
+        .join(&shared[2..])
+        .exists());
+
+    first.commit_file("a.txt", "packed\n", "first");
+    first.ok(&["gc"]);
+    let packed = first.ok(&["hash-object", "a.txt"]).trim().to_string();
+    assert!(!first.loose_object_path(&packed).exists());
+    let output = fit_in(&second, &["catfile", &packed]);
+    assert!(output.contains("Content:\npacked\n"), "{}", output);
+}
+
+#[test]
+fn interrupted_object_write_leaves_no_corrupt_object() {
+    use sha1::{Digest, Sha1};
+
+    let repo = Repo::new();
+    let content = noise(64 << 20, 7);
+    repo.write("big", &content);
+    let mut hasher = Sha1::new();
+    hasher.update(format!("blob {}\0", content.len()));
+    hasher.update(&content);
+    let hash: String = hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+
+    let mut child = std::process::Command::new(env!("CARGO_BIN_EXE_fit"))
+        .args(["add", "big"])
+        .current_dir(&repo.scratch.root)
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .spawn()
+        .unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(200));
+    let _ = child.kill();
+    child.wait().unwrap();
+
+    if repo.loose_object_path(&hash).exists() {
+        assert_eq!(repo.object(&hash).1.len(), content.len());
+    }
+    repo.ok(&["add", "big"]);
+    assert_eq!(repo.object(&hash).1.len(), content.len());
+}
+
+#[cfg(unix)]
+#[test]
+fn rewriting_an_existing_object_only_refreshes_it() {
+    use std::os::unix::fs::MetadataExt;
+
+    let repo = Repo::new();
+    let hash = store_object(&repo, "blob", "same\n");
+    let path = repo.loose_object_path(&hash);
+    let long_ago = std::time::SystemTime::now() - std::time::Duration::from_secs(30 * 24 * 3600);
+    std::fs::File::options()
+        .write(true)
+        .open(&path)
+        .unwrap()
+        .set_modified(long_ago)
+        .unwrap();
+    let before = std::fs::metadata(&path).unwrap();
+
+    assert_eq!(store_object(&repo, "blob", "same\n"), hash);
+
+    let after = std::fs::metadata(&path).unwrap();
+    assert_eq!(after.ino(), before.ino());
+    assert!(after.modified().unwrap() > before.modified().unwrap());
+}
@@ -0,0 +1,238 @@
+use crate::common::*;
+
+#[test]
+fn symbolic_ref_reads_head_after_init() {
+    let repo = Repo::new();
+
+    assert_eq!(repo.ok(&["symbolic-ref", "HEAD"]), "refs/heads/master\n");
+}
+
+#[test]
+fn symbolic_ref_rejects_detached_head() {
+    let repo = Repo::new();
+    repo.commit_file("a.txt", "a\n", "first");
+    repo.ok(&["checkout", INITIAL_COMMIT]);
+
+    assert_eq!(repo.read(".fit/HEAD").trim(), INITIAL_COMMIT);
+    let error = repo.fails(&["symbolic-ref", "HEAD"], 1);
+    assert!(error.contains("not a symbolic ref"), "{}", error);
+}
+
+#[test]
+fn merge_base_of_a_diamond_is_the_fork_point() {
+    let repo = Repo::new();
+    let base = repo.commit_file("a.txt", "a\n", "base");
+    repo.ok(&["branch", "checkout-new", "left"]);
+    repo.commit_file("left.txt", "l\n", "left");
+    repo.ok(&["branch", "checkout", "master"]);
+    repo.commit_file("right.txt", "r\n", "right");
+
+    assert_eq!(
+        repo.ok(&["merge-base", "master", "left"]),
+        format!("{}\n", base)
+    );
+    assert_eq!(
+        repo.ok(&["merge-base", "left", "master"]),
+        format!("{}\n", base)
+    );
+}
+
+#[test]
+fn merge_base_after_an_earlier_merge_is_the_merged_commit() {
+    let repo = Repo::new();
+    repo.commit_file("a.txt", "a\n", "base");
+    repo.ok(&["branch", "checkout-new", "feature"]);
+    let merged = repo.commit_file("f1.txt", "1\n", "feature one");
+    repo.ok(&["branch", "checkout", "master"]);
+    repo.commit_file("m.txt", "m\n", "master work");
+    repo.ok(&["merge", "feature"]);
+    let master = repo.commit_file("m2.txt", "m2\n", "more master work");
+    repo.ok(&["branch", "checkout", "feature"]);
+    repo.commit_file("f2.txt", "2\n", "feature two");
+
+    assert_eq!(
+        repo.ok(&["merge-base", "master", "feature"]),
+        format!("{}\n", merged)
+    );
+    assert_eq!(
+        repo.ok(&["merge-base", &master, &merged]),
+        format!("{}\n", merged)
+    );
+}
+
+#[test]
+fn show_ref_lists_branches_and_tags() {
+    let repo = Repo::new();
+    let commit = repo.commit_file("a.txt", "a\n", "first");
+    repo.ok(&["branch", "create", "topic"]);
+    repo.ok(&["tag", "v1"]);
+
+    let refs = repo.ok(&["show-ref"]);
+
+    assert_eq!(
+        refs,
+        format!(
+            "{0} refs/heads/master\n{0} refs/heads/topic\n{0} refs/tags/v1\n",
+            commit
+        )
+    );
+}
+
+#[test]
+fn bisect_converges_on_the_first_bad_commit() {
+    let repo = Repo::new();
+    let mut commits = Vec::new();
+    for version in 1..=8 {
+        commits.push(repo.commit_file(
+            "version.txt",
+            &format!("{}\n", version),
+            &format!("v{}", version),
+        ));
+    }
+    // The regression arrives with version 6
+    let first_bad = commits[5].clone();
+
+    repo.ok(&["bisect", "start"]);
+    repo.ok(&["bisect", "bad"]);
+    let mut output = repo.ok(&["bisect", "good", &commits[0]]);
+    for _ in 0..8 {
+        if output.contains("is the first bad commit") {
+            break;
+        }
+        let version: u32 = repo.read("version.txt").trim().parse().unwrap();
+        output = repo.ok(&["bisect", if version >= 6 { "bad" } else { "good" }]);
+    }
+
+    assert!(
+        output.contains(&format!("{} is the first bad commit", first_bad)),
+        "{}",
+        output
+    );
+    repo.ok(&["bisect", "reset"]);
+    assert_eq!(repo.read(".fit/HEAD").trim(), "ref: refs/heads/master");
+    assert_eq!(repo.read("version.txt"), "8\n");
+}
+
+#[test]
+fn update_ref_creates_a_ref_and_logs_it() {
+    let repo = Repo::new();
+    let commit = repo.commit_file("a.txt", "a\n", "first");
+
+    repo.ok(&["update-ref", "refs/heads/test", &commit]);
+
+    assert_eq!(repo.read_ref("refs/heads/test"), Some(commit.clone()));
+    let log = repo.read(".fit/logs/refs/heads/test");
+    assert!(
+        log.starts_with(&format!(
+            "0000000000000000000000000000000000000000 {} ",
+            commit
+        )),
+        "{}",
+        log
+    );
+}
+
+#[test]
+fn update_ref_rejects_a_stale_old_value() {
+    let repo = Repo::new();
+    let commit = repo.commit_file("a.txt", "a\n", "first");
+    repo.ok(&["update-ref", "refs/heads/test", &commit]);
+
+    let error = repo.fails(
+        &[
+            "update-ref",
+            "--old-value",
+            INITIAL_COMMIT,
+            "refs/heads/test",
+            INITIAL_COMMIT,
+        ],
+        1,
+    );
+
+    assert!(
+        error.contains(&format!(
+            "expected '{}' but it is '{}'",
+            INITIAL_COMMIT, commit
+        )),
+        "{}",
+        error
+    );
+    assert_eq!(repo.read_ref("refs/heads/test"), Some(commit.clone()));
+    repo.ok(&[
+        "update-ref",
+        "--old-value",
+        &commit,
+        "refs/heads/test",
+        INITIAL_COMMIT,
+    ]);
+    assert_eq!(
+        repo.read_ref("refs/heads/test"),
+        Some(INITIAL_COMMIT.to_string())
+    );
+}
+
+#[test]
+fn ref_updates_are_never_seen_half_written() {
+    let repo = Repo::new();
+    let first = repo.commit_file("a.txt", "1\n", "one");
+    let second = repo.commit_file("a.txt", "2\n", "two");
+    repo.ok(&["update-ref", "refs/heads/flip", &first]);
+
+    let ref_path = repo.path(".fit/refs/heads/flip");
+    let done = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+    let reader = {
+        let done = done.clone();
+        let valid = [first.clone(), second.clone()];
+        std::thread::spawn(move || {
+            let mut reads = 0;
+            while !done.load(std::sync::atomic::Ordering::SeqCst) {
+                let content = std::fs::read_to_string(&ref_path).unwrap();
+                assert!(
+                    valid.contains(&content.trim().to_string()),
+                    "read {:?}",
+                    content
+                );
+                reads += 1;
+            }
+            reads
+        })
+    };
+    for round in 0..20 {
+        let target = if round % 2 == 0 { &second } else { &first };
+        repo.ok(&["update-ref", "refs/heads/flip", target]);
+    }
+    done.store(true, std::sync::atomic::Ordering::SeqCst);
+
+    assert!(reader.join().unwrap() > 0);
+    assert_eq!(repo.read_ref("refs/heads/flip"), Some(first));
+    let leftovers: Vec<_> = std::fs::read_dir(repo.path(".fit/refs/heads"))
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .filter(|name| name != "master" && name != "flip")
+        .collect();
+    assert!(leftovers.is_empty(), "{:?}", leftovers);
+}
+
+#[test]
+fn detached_head_is_not_reported_as_master() {
+    let repo = Repo::new();
+    repo.commit_file("a.txt", "a\n", "first");
+    repo.ok(&["checkout", INITIAL_COMMIT]);
+
+    let status = repo.ok(&["status"]);
+
+    assert!(
+        status.starts_with("HEAD detached at e8666b9\n"),
+        "{}",
+        status
+    );
+    assert!(!status.contains("master"), "{}", status);
+}
+
+#[test]
+fn status_names_a_non_master_branch() {
+    let repo = Repo::new();
+    repo.ok(&["branch", "checkout-new", "trunk"]);
+
+    assert!(repo.ok(&["status"]).starts_with("On branch: trunk\n"));
+}
@@ -0,0 +1,83 @@
+use crate::common::*;
+
+use std::fs;
+
+#[test]
+fn fetch_updates_remote_tracking_refs_only() {
+    let repo = Repo::new();
+    let source = repo.sibling("source");
+    fs::write(source.join("a.txt"), "a\n").unwrap();
+    fit_in(&source, &["add", "a.txt"]);
+    fit_in(&source, &["commit", "-m", "first"]);
+    add_remote(&repo, "origin", &source);
+    repo.ok(&["fetch"]);
+    let first = ref_in(&source, "refs/heads/master").unwrap();
+    assert_eq!(repo.read_ref("refs/remotes/origin/master"), Some(first));
+
+    fs::write(source.join("b.txt"), "b\n").unwrap();
+    fit_in(&source, &["add", "b.txt"]);
+    fit_in(&source, &["commit", "-m", "second"]);
+    let output = repo.ok(&["fetch", "origin"]);
+
+    let second = ref_in(&source, "refs/heads/master").unwrap();
+    assert!(output.contains("master -> origin/master"), "{}", output);
+    assert_eq!(
+        repo.read_ref("refs/remotes/origin/master"),
+        Some(second.clone())
+    );
+    assert_eq!(repo.head(), INITIAL_COMMIT);
+    assert_eq!(repo.object(&second).0, "commit");
+}
+
+#[test]
+fn fetch_tracks_slash_named_branches() {
+    let repo = Repo::new();
+    let source = repo.sibling("source");
+    fs::write(source.join("a.txt"), "a\n").unwrap();
+    fit_in(&source, &["add", "a.txt"]);
+    fit_in(&source, &["commit", "-m", "first"]);
+    fit_in(&source, &["branch", "create", "feature/one"]);
+    fit_in(&source, &["branch", "create", "zebra"]);
+    add_remote(&repo, "origin", &source);
+
+    let output = repo.ok(&["fetch"]);
+
+    let commit = ref_in(&source, "refs/heads/master").unwrap();
+    assert!(
+        output.contains("feature/one -> origin/feature/one"),
+        "{}",
+        output
+    );
+    assert_eq!(
+        repo.read_ref("refs/remotes/origin/feature/one"),
+        Some(commit.clone())
+    );
+    assert_eq!(
+        repo.read_ref("refs/remotes/origin/master"),
+        Some(commit.clone())
+    );
+    assert_eq!(repo.read_ref("refs/remotes/origin/zebra"), Some(commit));
+}
+
+#[test]
+fn pull_fast_forwards_to_the_remote_branch() {
+    let repo = Repo::new();
+    let source = repo.sibling("source");
+    fs::write(source.join("a.txt"), "a\n").unwrap();
+    fit_in(&source, &["add", "a.txt"]);
+    fit_in(&source, &["commit", "-m", "first"]);
+    add_remote(&repo, "origin", &source);
+    repo.ok(&["pull"]);
+    assert_eq!(repo.read("a.txt"), "a\n");
+
+    fs::create_dir_all(source.join("dir")).unwrap();
+    fs::write(source.join("dir/b.txt"), "b\n").unwrap();
+    fit_in(&source, &["add", "dir"]);
+    fit_in(&source, &["commit", "-m", "second"]);
+    repo.ok(&["pull", "origin"]);
+
+    let second = ref_in(&source, "refs/heads/master").unwrap();
+    assert_eq!(repo.head(), second);
+    assert_eq!(repo.read("dir/b.txt"), "b\n");
+    assert!(repo.ok(&["log", "--oneline"]).contains(" second\n"));
+}
@@ -0,0 +1,306 @@
+use crate::common::*;
+
+fn make_read_only(path: &std::path::Path) {
+    let mut permissions = std::fs::metadata(path).unwrap().permissions();
+    permissions.set_readonly(true);
+    std::fs::set_permissions(path, permissions).unwrap();
+}
+
+#[test]
+fn reset_refuses_before_touching_anything_when_a_path_is_unwritable() {
+    let repo = Repo::new();
+    repo.write("a.txt", "a1\n");
+    repo.write("b.txt", "b1\n");
+    repo.ok(&["add", "."]);
+    let first = repo.commit("first");
+    repo.write("a.txt", "a2\n");
+    repo.write("b.txt", "b2\n");
+    repo.ok(&["add", "."]);
+    let second = repo.commit("second");
+    let index = repo.read(".fit/index");
+    make_read_only(&repo.path("b.txt"));
+
+    let error = repo.fails(&["reset", &first], 1);
+
+    assert!(
+        error.contains("cannot write 'b.txt': file is read-only"),
+        "{}",
+        error
+    );
+    assert_eq!(repo.read(".fit/index"), index);
+    assert_eq!(repo.head(), second);
+    assert_eq!(repo.read("a.txt"), "a2\n");
+    assert_eq!(repo.read("b.txt"), "b2\n");
+}
+
+#[test]
+fn refused_checkout_leaves_head_on_the_current_branch() {
+    let repo = Repo::new();
+    repo.commit_file("a.txt", "a1\n", "first");
+    repo.ok(&["branch", "create", "old"]);
+    repo.commit_file("a.txt", "a2\n", "second");
+    make_read_only(&repo.path("a.txt"));
+
+    repo.fails(&["branch", "checkout", "old"], 1);
+
+    assert_eq!(repo.read(".fit/HEAD").trim(), "ref: refs/heads/master");
+    assert_eq!(repo.read("a.txt"), "a2\n");
+}
+
+#[test]
+fn fitkeep_keeps_an_empty_directory_across_reset() {
+    let repo = Repo::new();
+    repo.write("logs/.fitkeep", "");
+    repo.ok(&["add", "logs/.fitkeep"]);
+    let commit = repo.commit("keep logs");
+    std::fs::remove_dir_all(repo.path("logs")).unwrap();
+
+    repo.ok(&["reset", &commit]);
+
+    assert!(repo.path("logs").is_dir());
+    assert!(repo.path("logs/.fitkeep").is_file());
+}
+
+#[test]
+fn reset_path_unstages_only_that_file() {
+    let repo = Repo::new();
+    repo.write("one.txt", "1\n");
+    repo.write("two.txt", "2\n");
+    repo.ok(&["add", "one.txt"]);
+    repo.ok(&["add", "two.txt"]);
+
+    repo.ok(&["reset", "HEAD", "--", "one.txt"]);
+
+    let status = repo.ok(&["status"]);
+    assert_eq!(
+        status_section(&status, "Changes to be committed:"),
+        ["new file: two.txt"]
+    );
+    assert_eq!(status_section(&status, "Untracked files:"), ["one.txt"]);
+    assert_eq!(repo.read("one.txt"), "1\n");
+}
+
+#[test]
+fn missing_trailing_newline_survives_commit_and_reset() {
+    let repo = Repo::new();
+    let commit = repo.commit_file("plain.txt", "no newline at the end", "first");
+    repo.remove("plain.txt");
+
+    repo.ok(&["reset", &commit]);
+
+    assert_eq!(
+        std::fs::read(repo.path("plain.txt")).unwrap(),
+        b"no newline at the end"
+    );
+}
+
+#[test]
+fn diff_marks_a_missing_trailing_newline() {
+    let repo = Repo::new();
+    repo.commit_file("plain.txt", "last line", "first");
+    repo.write("plain.txt", "last line\n");
+
+    let diff = repo.ok(&["diff"]);
+
+    assert!(
+        diff.contains("-last line\n\\ No newline at end of file\n+last line"),
+        "{}",
+        diff
+    );
+}
+
+#[test]
+fn reset_restores_deeply_nested_files() {
+    let repo = Repo::new();
+    repo.write("top.txt", "top\n");
+    repo.write("a/b/c/three.txt", "depth three\n");
+    repo.write("a/b/c/d/four.txt", "depth four\n");
+    repo.write("a/b/c/d/also.txt", "also four\n");
+    repo.ok(&["add", "."]);
+    let commit = repo.commit("nested");
+    std::fs::remove_dir_all(repo.path("a")).unwrap();
+    repo.remove("top.txt");
+
+    repo.ok(&["reset", &commit]);
+
+    assert_eq!(repo.read("top.txt"), "top\n");
+    assert_eq!(repo.read("a/b/c/three.txt"), "depth three\n");
+    assert_eq!(repo.read("a/b/c/d/four.txt"), "depth four\n");
+    assert_eq!(repo.read("a/b/c/d/also.txt"), "also four\n");
+    let tree = repo.commit_fields(&commit)["tree"].clone();
+    let (object_type, _) = repo.object(&tree);
+    assert_eq!(object_type, "tree");
+}
+
+#[test]
+fn reset_keep_refuses_when_a_changed_file_is_dirty() {
+    let repo = Repo::new();
+    repo.write("a.txt", "a1\n");
+    repo.write("keep.txt", "k\n");
+    repo.ok(&["add", "."]);
+    let first = repo.commit("first");
+    let second = repo.commit_file("a.txt", "a2\n", "second");
+    repo.write("a.txt", "dirty\n");
+
+    let error = repo.fails(&["reset", "--keep", &first], 1);
+
+    assert!(
+        error.contains("cannot reset --keep with local changes to a.txt"),
+        "{}",
+        error
+    );
+    assert_eq!(repo.head(), second);
+    assert_eq!(repo.read("a.txt"), "dirty\n");
+}
+
+#[test]
+fn reset_keep_moves_the_branch_and_keeps_unrelated_changes() {
+    let repo = Repo::new();
+    repo.write("a.txt", "a1\n");
+    repo.write("keep.txt", "k\n");
+    repo.ok(&["add", "."]);
+    let first = repo.commit("first");
+    repo.commit_file("a.txt", "a2\n", "second");
+    repo.write("keep.txt", "local edit\n");
+
+    repo.ok(&["reset", "--keep", &first]);
+
+    assert_eq!(repo.head(), first);
+    assert_eq!(repo.read("a.txt"), "a1\n");
+    assert_eq!(repo.read("keep.txt"), "local edit\n");
+    let log = repo.read(".fit/logs/refs/heads/master");
+    let last = log.lines().last().unwrap();
+    assert_eq!(last.split(' ').nth(1), Some(first.as_str()), "{}", log);
+}
+
+#[test]
+fn reset_backup_saves_and_restores_replaced_files() {
+    let repo = Repo::new();
+    repo.append_config("[core]\n\tresetBackup = true\n");
+    let first = repo.commit_file("a.txt", "a\n", "first");
+    repo.commit_file("b.txt", "precious\n", "second");
+
+    repo.ok(&["reset", &first]);
+    assert!(!repo.exists("b.txt"));
+
+    let entries: Vec<String> = std::fs::read_dir(repo.path(".fit/trash"))
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .collect();
+    assert_eq!(entries.len(), 1, "{:?}", entries);
+    let entry = &entries[0];
+    assert_eq!(
+        repo.read(&format!(".fit/trash/{}/b.txt", entry)),
+        "precious\n"
+    );
+    assert!(repo.ok(&["trash", "list"]).contains("b.txt"));
+
+    repo.ok(&["trash", "restore", entry]);
+    assert_eq!(repo.read("b.txt"), "precious\n");
+}
+
+#[test]
+fn reset_dry_run_lists_files_without_touching_them() {
+    let repo = Repo::new();
+    let first = repo.commit_file("a.txt", "one\n", "first");
+    repo.write("b.txt", "b\n");
+    repo.write("a.txt", "two\n");
+    repo.ok(&["add", "."]);
+    let second = repo.commit("second");
+    let index = repo.read(".fit/index");
+
+    let output = repo.ok(&["reset", "--dry-run", &first]);
+
+    assert_eq!(output, "Would overwrite a.txt\nWould delete b.txt\n");
+    assert_eq!(repo.head(), second);
+    assert_eq!(repo.read("a.txt"), "two\n");
+    assert_eq!(repo.read("b.txt"), "b\n");
+    assert_eq!(repo.read(".fit/index"), index);
+}
+
+#[cfg(unix)]
+#[test]
+fn symlink_is_committed_and_restored_as_a_link() {
+    let repo = Repo::new();
+    repo.write("target.txt", "pointed at\n");
+    std::os::unix::fs::symlink("target.txt", repo.path("link")).unwrap();
+    repo.ok(&["add", "target.txt"]);
+    repo.ok(&["add", "link"]);
+    let commit = repo.commit("add link");
+    let tree = repo.commit_fields(&commit)["tree"].clone();
+    let listing = repo.ok(&["catfile", "-r", &tree]);
+    assert!(listing.contains("120000 blob "), "{}", listing);
+
+    repo.remove("link");
+    repo.remove("target.txt");
+    repo.ok(&["reset", &commit]);
+
+    let metadata = std::fs::symlink_metadata(repo.path("link")).unwrap();
+    assert!(metadata.file_type().is_symlink());
+    assert_eq!(
+        std::fs::read_link(repo.path("link")).unwrap(),
+        std::path::Path::new("target.txt")
+    );
+    assert_eq!(repo.read("link"), "pointed at\n");
+}
+
+#[test]
+fn reset_patch_unstages_only_chosen_hunks() {
+    let repo = Repo::new();
+    let original: String = (1..=20).map(|n| format!("{}\n", n)).collect();
+    repo.commit_file("numbers.txt", &original, "numbers");
+    let edited = original
+        .replace("\n2\n", "\ntwo\n")
+        .replace("\n18\n", "\neighteen\n");
+    repo.write("numbers.txt", &edited);
+    repo.ok(&["add", "numbers.txt"]);
+
+    // Keep the first hunk staged, unstage the second
+    let output = repo.run_with(&["reset", "-p"], Some(b"n\ny\n"), &[]);
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    let staging = repo.staging();
+    let hash = staging.split(' ').nth(1).unwrap();
+    assert_eq!(repo.object_text(hash), original.replace("\n2\n", "\ntwo\n"));
+    assert_eq!(repo.read("numbers.txt"), edited);
+}
+
+#[test]
+fn reset_patch_leaves_non_utf8_files_staged_as_they_are() {
+    let repo = Repo::new();
+    let original: String = (1..=20).map(|n| format!("{}\n", n)).collect();
+    repo.commit_file("f", &original, "numbers");
+    let mut edited = original.replace("\n18\n", "\neighteen\n").into_bytes();
+    edited.splice(2..3, b"caf\xe9".iter().copied());
+    repo.write_bytes("f", &edited);
+    repo.ok(&["add", "f"]);
+    let staged = repo.staging();
+
+    // Would keep the non-UTF-8 first hunk and unstage the second
+    let output = repo.run_with(&["reset", "-p"], Some(b"n\ny\n"), &[]);
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    assert_eq!(repo.staging(), staged);
+    let hash = staged.split(' ').nth(1).unwrap().trim().to_string();
+    assert_eq!(repo.object(&hash).1, edited);
+}
+
+#[test]
+fn reset_to_a_reflog_position_recovers_the_branch() {
+    let repo = Repo::new();
+    let first = repo.commit_file("a.txt", "one\n", "first");
+    let second = repo.commit_file("a.txt", "two\n", "second");
+
+    repo.ok(&["reset", &first]);
+    assert_eq!(repo.read_ref("refs/heads/master").unwrap(), first);
+
+    repo.ok(&["reset", "HEAD@{1}"]);
+    assert_eq!(repo.read_ref("refs/heads/master").unwrap(), second);
+    assert_eq!(repo.read("a.txt"), "two\n");
+
+    repo.ok(&["reset", "master@{1}"]);
+    assert_eq!(repo.read_ref("refs/heads/master").unwrap(), first);
+
+    let error = repo.fails(&["reset", "HEAD@{50}"], 1);
+    assert!(error.contains("only has"), "{}", error);
+}
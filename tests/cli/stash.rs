@@ -0,0 +1,59 @@
+use crate::common::*;
+
+#[test]
+fn stash_show_prints_the_stashed_change() {
+    let repo = Repo::new();
+    repo.commit_file("a.txt", "original\n", "first");
+    repo.write("a.txt", "modified\n");
+    repo.ok(&["add", "a.txt"]);
+    repo.ok(&["stash"]);
+    assert_eq!(repo.read("a.txt"), "original\n");
+
+    let diff = repo.ok(&["stash", "show"]);
+    assert!(diff.contains("--- a/a.txt"), "{}", diff);
+    assert!(diff.contains("-original\n+modified"), "{}", diff);
+
+    assert_eq!(
+        repo.ok(&["stash", "show", "--name-only", "stash@{0}"]),
+        "a.txt\n"
+    );
+}
+
+#[test]
+fn stash_show_rejects_missing_stash() {
+    let repo = Repo::new();
+
+    let error = repo.fails(&["stash", "show", "stash@{3}"], 1);
+    assert!(error.contains("stash@{3} does not exist"), "{}", error);
+}
+
+#[test]
+fn stash_list_shows_named_stashes_newest_first() {
+    let repo = Repo::new();
+    repo.commit_file("a.txt", "a\n", "first");
+    repo.write("a.txt", "a\nb\n");
+    repo.ok(&["add", "a.txt"]);
+    repo.ok(&["stash", "save", "-m", "first wip"]);
+    repo.write("a.txt", "a\nc\n");
+    repo.ok(&["add", "a.txt"]);
+    repo.ok(&["stash", "save", "-m", "second wip"]);
+
+    assert_eq!(
+        repo.ok(&["stash", "list"]),
+        "stash@{0}: second wip\nstash@{1}: first wip\n"
+    );
+}
+
+#[test]
+fn unnamed_stash_is_described_by_its_base() {
+    let repo = Repo::new();
+    let commit = repo.commit_file("a.txt", "a\n", "first");
+    repo.write("a.txt", "a\nb\n");
+    repo.ok(&["add", "a.txt"]);
+    repo.ok(&["stash"]);
+
+    assert_eq!(
+        repo.ok(&["stash", "list"]),
+        format!("stash@{{0}}: WIP on master: {} first\n", &commit[..7])
+    );
+}
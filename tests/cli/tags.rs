@@ -0,0 +1,49 @@
+use crate::common::*;
+
+#[cfg(unix)]
+#[test]
+fn tag_verify_accepts_a_signed_tag() {
+    let repo = Repo::new();
+    fake_signer(&repo);
+    repo.ok(&["tag", "-s", "-m", "release", "v1"]);
+
+    let tag = repo.read_ref("refs/tags/v1").unwrap();
+    let content = repo.object_text(&tag);
+    assert!(
+        content.contains("-----BEGIN PGP SIGNATURE-----"),
+        "{}",
+        content
+    );
+    assert_eq!(
+        repo.ok(&["tag", "-v", "v1"]),
+        "Good signature on tag 'v1'\n"
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn tag_verify_rejects_tampered_and_unsigned_tags() {
+    let repo = Repo::new();
+    fake_signer(&repo);
+    repo.ok(&["tag", "-s", "-m", "release", "v1"]);
+    let content = repo.object_text(&repo.read_ref("refs/tags/v1").unwrap());
+    let forged = store_object(&repo, "tag", &content.replace("release", "re-release"));
+    repo.write(".fit/refs/tags/v1", &format!("{}\n", forged));
+
+    repo.fails(&["tag", "-v", "v1"], 1);
+
+    repo.ok(&["tag", "plain"]);
+    let error = repo.fails(&["tag", "-v", "plain"], 1);
+    assert!(error.contains("tag 'plain' is not signed"), "{}", error);
+}
+
+#[test]
+fn describe_counts_commits_since_the_tag() {
+    let repo = Repo::new();
+    repo.commit_file("a.txt", "1\n", "tagged");
+    repo.ok(&["tag", "v1.0"]);
+    repo.commit_file("a.txt", "2\n", "second");
+    let head = repo.commit_file("a.txt", "3\n", "third");
+
+    assert_eq!(repo.ok(&["describe"]), format!("v1.0-2-g{}\n", &head[..7]));
+}
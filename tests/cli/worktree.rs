@@ -0,0 +1,35 @@
+use crate::common::*;
+
+#[test]
+fn worktree_add_checks_out_a_new_branch_elsewhere() {
+    let repo = Repo::new();
+    repo.commit_file("a.txt", "a\n", "base");
+    repo.write("local.txt", "main tree only\n");
+    let linked = repo.scratch.root.join("linked");
+
+    repo.ok(&["worktree", "add", linked.to_str().unwrap(), "side"]);
+
+    assert_eq!(
+        std::fs::read_to_string(linked.join("a.txt")).unwrap(),
+        "a\n"
+    );
+    assert!(!linked.join("local.txt").exists());
+    assert_eq!(repo.read(".fit/HEAD").trim(), "ref: refs/heads/master");
+    assert_eq!(repo.read_ref("refs/heads/side"), Some(repo.head()));
+    let list = repo.ok(&["worktree", "list"]);
+    assert!(
+        list.contains(&format!("{} [side]", linked.display())),
+        "{}",
+        list
+    );
+
+    // Committing in the linked tree moves its branch and leaves the main one alone
+    let master = repo.head();
+    std::fs::write(linked.join("side.txt"), "side\n").unwrap();
+    fit_in(&linked, &["add", "side.txt"]);
+    fit_in(&linked, &["commit", "-m", "side work"]);
+    assert_eq!(repo.head(), master);
+    assert_ne!(repo.read_ref("refs/heads/side"), Some(master));
+    assert!(!repo.exists("side.txt"));
+    assert_eq!(repo.read("local.txt"), "main tree only\n");
+}